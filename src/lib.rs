@@ -0,0 +1,9499 @@
+use std::io::{
+    self,
+    Write,
+};
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::env;
+use std::io::{Seek, SeekFrom, Read};
+use std::mem::size_of;
+use std::convert::TryInto;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use scan_fmt::scan_fmt;
+use memoffset::offset_of;
+
+// println! and print! for the writer the caller supplied instead of
+// stdout. Like println!, they have nobody to report a failed write to, so
+// the error is dropped rather than turned into a panic.
+macro_rules! outln {
+    ($out:expr) => {{
+        let _ = writeln!($out);
+    }};
+    ($out:expr, $($arg:tt)*) => {{
+        let _ = writeln!($out, $($arg)*);
+    }};
+}
+
+macro_rules! out {
+    ($out:expr, $($arg:tt)*) => {{
+        let _ = write!($out, $($arg)*);
+    }};
+}
+
+const COLUMN_USERNAME_SIZE: usize = 32;
+const COLUMN_EMAIL_SIZE: usize = 255;
+
+const ID_SIZE: usize = size_of::<u32>();
+const USERNAME_SIZE: usize = COLUMN_USERNAME_SIZE;
+const EMAIL_SIZE: usize = COLUMN_EMAIL_SIZE;
+
+const ID_OFFSET: usize = offset_of!(Row, id);
+const USERNAME_OFFSET: usize = offset_of!(Row, username);
+const EMAIL_OFFSET: usize = offset_of!(Row, email);
+// One byte of per-row flags after the columns: bit n is set when column n
+// of Column::ALL holds NULL (the id column never does), and ROW_TOMBSTONE
+// marks a soft-deleted row
+const ROW_FLAGS_SIZE: usize = size_of::<u8>();
+const ROW_FLAGS_OFFSET: usize = offset_of!(Row, flags);
+// A row removed by a delete with .softdelete on. Its cell stays in the leaf,
+// hidden from every read, until .vacuum rebuilds the table without it.
+const ROW_TOMBSTONE: u8 = 1 << 7;
+
+const ROW_SIZE: usize = ID_SIZE + USERNAME_SIZE + EMAIL_SIZE + ROW_FLAGS_SIZE;
+
+/// Page size for new files unless --page-size picks another. Existing files
+/// keep the size recorded in their header.
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
+/// Smallest page size a file may use
+pub const MIN_PAGE_SIZE: usize = 1024;
+/// Largest page size a file may use
+pub const MAX_PAGE_SIZE: usize = 65536;
+
+/* Common Header Layout */ 
+const NODE_TYPE_SIZE: usize = size_of::<u8>();
+const NODE_TYPE_OFFSET: usize = 0;
+
+const IS_ROOT_SIZE: usize = size_of::<u8>();
+const IS_ROOT_OFFSET: usize = NODE_TYPE_OFFSET + NODE_TYPE_SIZE;
+
+const PARENT_POINTER_SIZE: usize = size_of::<u32>();
+const PARENT_POINTER_OFFSET: usize = IS_ROOT_OFFSET + IS_ROOT_SIZE;
+
+const COMMON_NODE_HEADER_SIZE: usize =
+    NODE_TYPE_SIZE + IS_ROOT_SIZE + PARENT_POINTER_SIZE;
+const INTERNAL_NODE_KEY_OFFSET: usize = INTERNAL_NODE_CHILD_SIZE;
+/* Leaf Node Header Layout */
+const LEAF_NODE_NUM_CELLS_SIZE: usize = size_of::<u32>();
+const LEAF_NODE_NUM_CELLS_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
+
+/* Leaf Node Body Layout */
+const LEAF_NODE_KEY_SIZE: usize = size_of::<u32>();
+const LEAF_NODE_KEY_OFFSET: usize = 0;
+const LEAF_NODE_VALUE_SIZE: usize = ROW_SIZE;
+const LEAF_NODE_VALUE_OFFSET: usize = LEAF_NODE_KEY_OFFSET + LEAF_NODE_KEY_SIZE;
+const LEAF_NODE_CELL_SIZE: usize = LEAF_NODE_KEY_SIZE + LEAF_NODE_VALUE_SIZE;
+
+
+/* Internal Node Header Layout */
+const INTERNAL_NODE_NUM_KEYS_SIZE: usize = size_of::<u32>();
+const INTERNAL_NODE_NUM_KEYS_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
+const INTERNAL_NODE_RIGHT_CHILD_SIZE: usize = size_of::<u32>();
+const INTERNAL_NODE_RIGHT_CHILD_OFFSET: usize =
+    INTERNAL_NODE_NUM_KEYS_OFFSET + INTERNAL_NODE_NUM_KEYS_SIZE;
+const INTERNAL_NODE_HEADER_SIZE: usize = COMMON_NODE_HEADER_SIZE +
+    INTERNAL_NODE_NUM_KEYS_SIZE + INTERNAL_NODE_RIGHT_CHILD_SIZE;
+
+/* Internal Node Body Layout */
+const INTERNAL_NODE_KEY_SIZE: usize = size_of::<u32>();
+const INTERNAL_NODE_CHILD_SIZE: usize = size_of::<u32>();
+const INTERNAL_NODE_CELL_SIZE: usize = INTERNAL_NODE_CHILD_SIZE + INTERNAL_NODE_KEY_SIZE;
+
+// Leaf node header layout
+const LEAF_NODE_NEXT_LEAF_SIZE: usize = size_of::<u32>();
+const LEAF_NODE_NEXT_LEAF_OFFSET: usize = LEAF_NODE_NUM_CELLS_OFFSET + LEAF_NODE_NUM_CELLS_SIZE;
+const LEAF_NODE_PREV_LEAF_SIZE: usize = size_of::<u32>();
+const LEAF_NODE_PREV_LEAF_OFFSET: usize = LEAF_NODE_NEXT_LEAF_OFFSET + LEAF_NODE_NEXT_LEAF_SIZE;
+const LEAF_NODE_HEADER_SIZE: usize = COMMON_NODE_HEADER_SIZE
+    + LEAF_NODE_NUM_CELLS_SIZE
+    + LEAF_NODE_NEXT_LEAF_SIZE
+    + LEAF_NODE_PREV_LEAF_SIZE;
+
+const INVALID_PAGE_NUM: u32 = u32::MAX;
+
+// Most parsed statements kept for reuse by prepare_cached
+const STATEMENT_CACHE_SIZE: usize = 64;
+
+// Ends the leaf chain in both directions: the next_leaf of the rightmost
+// leaf and the prev_leaf of the leftmost. Page 0 can serve because it is
+// always the table's root, which keeps its page through splits and merges,
+// so it is never a leaf with siblings to link to. Unlike INVALID_PAGE_NUM
+// this is what a zeroed page already holds, so files need no rewriting.
+const NO_LEAF: u32 = 0;
+
+// The file is grown this many pages at a time, so a bulk load causes a few
+// large size changes the filesystem can allocate contiguously instead of
+// one per page. db_close trims the unused tail again.
+const FILE_GROWTH_CHUNK_PAGES: usize = 64;
+
+// Version of the on-disk layout this build writes. Format 2 started the
+// file with a header page; format 3 added the previous-leaf pointer to the
+// leaf header, which moved every cell; format 4 added the row flags byte,
+// which made every cell one byte longer. Older files are upgraded on open.
+const FORMAT_VERSION: u32 = 4;
+// Rows and cells as formats 1 to 3 stored them, without the flags byte
+const FORMAT_3_ROW_SIZE: usize = ROW_SIZE - ROW_FLAGS_SIZE;
+const FORMAT_3_CELL_SIZE: usize = LEAF_NODE_KEY_SIZE + FORMAT_3_ROW_SIZE;
+// Format 1 files have no header to record their page size
+const FORMAT_1_PAGE_SIZE: usize = 4096;
+
+/* File Header Layout */
+const FILE_HEADER_MAGIC: &[u8; 8] = b"rustdb\0\0";
+const FILE_HEADER_VERSION_OFFSET: usize = FILE_HEADER_MAGIC.len();
+const FILE_HEADER_PAGE_SIZE_OFFSET: usize = FILE_HEADER_VERSION_OFFSET + size_of::<u32>();
+// Schema: a column count, then each column's width in bytes
+const FILE_HEADER_NUM_COLUMNS_OFFSET: usize = FILE_HEADER_PAGE_SIZE_OFFSET + size_of::<u32>();
+const FILE_HEADER_COLUMN_WIDTHS_OFFSET: usize = FILE_HEADER_NUM_COLUMNS_OFFSET + size_of::<u32>();
+const FILE_HEADER_COLUMN_WIDTHS_END: usize = FILE_HEADER_COLUMN_WIDTHS_OFFSET + Column::ALL.len() * size_of::<u32>();
+// Free-page list: the first free page and how many there are. Files written
+// before the list existed have zeros here, which reads as an empty list.
+const FILE_HEADER_FREE_HEAD_OFFSET: usize = FILE_HEADER_COLUMN_WIDTHS_END;
+const FILE_HEADER_FREE_COUNT_OFFSET: usize = FILE_HEADER_FREE_HEAD_OFFSET + size_of::<u32>();
+// Optional features chosen when the file was created. Older files have
+// zeros here, which turns all of them off.
+const FILE_HEADER_FLAGS_OFFSET: usize = FILE_HEADER_FREE_COUNT_OFFSET + size_of::<u32>();
+// Root pages of the email and username indexes, or 0 (the table's root)
+// when there is none. Files from before the username index have zeros.
+const FILE_HEADER_EMAIL_INDEX_OFFSET: usize = FILE_HEADER_FLAGS_OFFSET + size_of::<u32>();
+const FILE_HEADER_USERNAME_INDEX_OFFSET: usize = FILE_HEADER_EMAIL_INDEX_OFFSET + size_of::<u32>();
+// Table directory: how many named tables there are, then for each, in
+// creation order, its name, NUL-padded, its root page and its first heap
+// page, 0 unless it is heap-organized. Older files have zeros here, which
+// reads as no named tables.
+const FILE_HEADER_TABLE_COUNT_OFFSET: usize = FILE_HEADER_USERNAME_INDEX_OFFSET + size_of::<u32>();
+const FILE_HEADER_TABLES_OFFSET: usize = FILE_HEADER_TABLE_COUNT_OFFSET + size_of::<u32>();
+const TABLE_NAME_SIZE: usize = 24;
+const TABLE_ENTRY_SIZE: usize = TABLE_NAME_SIZE + 2 * size_of::<u32>();
+const MAX_TABLES: usize = 16;
+const FILE_HEADER_SIZE: usize = FILE_HEADER_TABLES_OFFSET + MAX_TABLES * TABLE_ENTRY_SIZE;
+
+// Every page ends with a CRC-32 of the rest of the page
+const FLAG_PAGE_CHECKSUMS: u32 = 1;
+const PAGE_CHECKSUM_SIZE: usize = size_of::<u32>();
+
+/* Write-Ahead Log Layout */
+// The log is a header (magic, page size) followed by frames: a page number
+// and that page's bytes. A batch ends with a commit frame holding
+// WAL_COMMIT and the free-list head and count in place of page bytes.
+// Pages reach the file only through a synced batch, so a crash leaves the
+// file as of the last sync. A panic first undoes the statement it hit and
+// then syncs, so the statements before it are kept (see Drop for Table).
+const WAL_MAGIC: &[u8; 8] = b"rustwal\0";
+const WAL_HEADER_SIZE: usize = WAL_MAGIC.len() + size_of::<u32>();
+const WAL_COMMIT: u32 = INVALID_PAGE_NUM;
+
+/* Secondary Index Layout */
+// Each index is another B-tree in the same file, keyed by a hash of one
+// text column. Each cell's value is a bucket of the ids whose values share
+// that hash: a count and then the ids in ascending order, so rows sharing a
+// value come out of a lookup in id order. A bucket that outgrows its cell
+// is marked overflowed and lookups for its hash fall back to a full scan.
+const INDEX_BUCKET_IDS: usize = (LEAF_NODE_VALUE_SIZE - size_of::<u32>()) / size_of::<u32>();
+const INDEX_OVERFLOW: u32 = u32::MAX;
+// The columns .createindex accepts
+const INDEXED_COLUMNS: [Column; 2] = [Column::Username, Column::Email];
+
+/* Free Page Layout */
+// A freed page keeps only its type byte and the number of the next free
+// page. Page 0 is the root and is never freed, so 0 ends the list.
+const FREE_PAGE_TYPE: u8 = 2;
+const FREE_PAGE_NEXT_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
+
+/* Heap Layout */
+// A table created with 'create table <name> heap' keeps its rows in heap
+// pages, appended in insert order, and its leaves hold only each key and
+// a locator: the heap page and the row's slot on it. A leaf then takes far
+// more keys, at the cost of a second page read for each row. Heap leaves
+// have their own type byte so their narrower cells can be told apart, and
+// the table's first heap page also records the last one, which new rows
+// are appended to.
+const HEAP_LEAF_TYPE: u8 = 3;
+const HEAP_LOCATOR_SIZE: usize = 2 * size_of::<u32>();
+const HEAP_PAGE_TYPE: u8 = 4;
+const HEAP_PAGE_NUM_ROWS_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
+const HEAP_PAGE_LAST_OFFSET: usize = HEAP_PAGE_NUM_ROWS_OFFSET + size_of::<u32>();
+const HEAP_PAGE_HEADER_SIZE: usize = HEAP_PAGE_LAST_OFFSET + size_of::<u32>();
+
+
+/* Page Checksums */
+// CRC-32 (IEEE), table driven; the table is built at compile time
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &b| {
+        CRC32_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+// The checksum covers everything but its own slot at the end of the page
+fn set_page_checksum(page: &mut [u8]) {
+    let slot = page.len() - PAGE_CHECKSUM_SIZE;
+    let checksum = crc32(&page[..slot]);
+    set_u32_at(page, slot, checksum);
+}
+
+// A page of zeros was never written (the file grows a chunk at a time
+// ahead of use) and so has no checksum to check
+fn page_checksum_ok(page: &[u8]) -> bool {
+    let slot = page.len() - PAGE_CHECKSUM_SIZE;
+    get_u32_at(page, slot) == crc32(&page[..slot]) || page.iter().all(|&b| b == 0)
+}
+
+/* Example helper function */
+fn get_u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+// Header fields are not 4-byte aligned, so values are always copied out
+// and back in rather than referenced in place.
+fn set_u32_at(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/* Internal Node Read/Write Accessors */
+fn internal_node_num_keys(node: &[u8]) -> u32 {
+    get_u32_at(node, INTERNAL_NODE_NUM_KEYS_OFFSET)
+}
+fn internal_node_right_child(node: &[u8]) -> u32 {
+    get_u32_at(node, INTERNAL_NODE_RIGHT_CHILD_OFFSET)
+}
+fn internal_node_cell_offset(cell_num: usize) -> usize {
+    INTERNAL_NODE_HEADER_SIZE + cell_num * INTERNAL_NODE_CELL_SIZE
+}
+
+// Child child_num of an internal node; num_keys is the right child. A child
+// past the right one, or a pointer left unset, is an InvalidChild error.
+fn internal_node_child(node: &[u8], child_num: usize) -> Result<u32, DbError> {
+    let num_keys = internal_node_num_keys(node) as usize;
+    let child_ptr = match child_num.cmp(&num_keys) {
+        std::cmp::Ordering::Greater => INVALID_PAGE_NUM,
+        std::cmp::Ordering::Equal => internal_node_right_child(node),
+        std::cmp::Ordering::Less => get_u32_at(node, internal_node_cell_offset(child_num)),
+    };
+    if child_ptr == INVALID_PAGE_NUM {
+        return Err(DbError::InvalidChild { child_num, num_keys });
+    }
+    Ok(child_ptr)
+}
+
+fn internal_node_key_at(node: &[u8], key_num: usize) -> u32 {
+    let offset = internal_node_cell_offset(key_num) + INTERNAL_NODE_CHILD_SIZE;
+    get_u32_at(node, offset)
+}
+
+fn internal_node_key(node: &[u8], key_num: usize) -> u32 {
+    internal_node_key_at(node, key_num)
+}
+
+fn get_node_max_key(pager: &mut Pager, page_num: usize) -> Result<u32, DbError> {
+    let node = get_page(pager, page_num)?;
+    
+    match node_type_checked(node, page_num)? {
+        NodeType::Leaf => {
+            // Return the last key in the leaf node; an empty leaf has none
+            match leaf_node_num_cells(node) as usize {
+                0 => Err(DbError::EmptyNode { page_num }),
+                num_cells => Ok(leaf_node_key(node, num_cells - 1)),
+            }
+        }
+        NodeType::Internal => {
+            // Follow the rightmost child recursively
+            let right_child_page_num = internal_node_right_child(node) as usize;
+            get_node_max_key(pager, right_child_page_num)
+        }
+    }
+}
+
+
+fn internal_node_cell_mut(node: &mut [u8], cell_num: usize) -> &mut [u8] {
+    let offset = internal_node_cell_offset(cell_num);
+    &mut node[offset..offset + INTERNAL_NODE_CELL_SIZE]
+}
+
+fn internal_node_cell(node: &[u8], cell_num: usize) -> &[u8] {
+    let offset = internal_node_cell_offset(cell_num);
+    &node[offset..offset + INTERNAL_NODE_CELL_SIZE]
+}
+
+// Helper to set internal node child at specific index
+fn set_internal_node_child(node: &mut [u8], child_num: usize, page_num: u32) {
+    if child_num == internal_node_num_keys(node) as usize {
+        set_internal_node_right_child(node, page_num);
+    } else {
+        set_u32_at(node, internal_node_cell_offset(child_num), page_num);
+    }
+}
+
+// Helper to set the right child
+fn set_internal_node_right_child(node: &mut [u8], page_num: u32) {
+    set_u32_at(node, INTERNAL_NODE_RIGHT_CHILD_OFFSET, page_num);
+}
+
+
+//Keep track of the root node
+fn is_node_root(node: &[u8]) -> bool {
+    node[IS_ROOT_OFFSET] != 0
+}
+
+fn set_node_root(node: &mut [u8], is_root: bool) {
+    node[IS_ROOT_OFFSET] = is_root as u8;
+}
+
+
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeType {
+    Internal = 0,
+    Leaf = 1,
+}
+
+// read the number of cells in a leaf node
+fn leaf_node_num_cells(node: &[u8]) -> u32 {
+    let start = LEAF_NODE_NUM_CELLS_OFFSET;
+    let end = start + 4;
+    u32::from_le_bytes(node[start..end].try_into().unwrap())
+}
+
+// set the number of cells in a leaf node
+fn set_leaf_node_num_cells(node: &mut [u8], num_cells: u32) {
+    let bytes = num_cells.to_le_bytes();
+    let start = LEAF_NODE_NUM_CELLS_OFFSET;
+    node[start..start + 4].copy_from_slice(&bytes);
+}
+
+// Bytes of a cell's value: a row or an index bucket, or in a heap leaf the
+// row's locator
+fn leaf_node_value_size(node: &[u8]) -> usize {
+    if node[NODE_TYPE_OFFSET] == HEAP_LEAF_TYPE {
+        HEAP_LOCATOR_SIZE
+    } else {
+        LEAF_NODE_VALUE_SIZE
+    }
+}
+
+fn leaf_node_cell_size(node: &[u8]) -> usize {
+    LEAF_NODE_KEY_SIZE + leaf_node_value_size(node)
+}
+
+// get the offset of the n-th cell in a leaf node
+fn leaf_node_cell_offset(node: &[u8], cell_num: usize) -> usize {
+    LEAF_NODE_HEADER_SIZE + cell_num * leaf_node_cell_size(node)
+}
+
+// get a slice of the n-th cell
+fn leaf_node_key(node: &[u8], cell_num: usize) -> u32 {
+    let offset = leaf_node_cell_offset(node, cell_num) + LEAF_NODE_KEY_OFFSET;
+    u32::from_le_bytes(node[offset..offset + 4].try_into().unwrap())
+}
+
+
+// Bounds-checked variants of the leaf accessors for scans over pages read
+// back from disk. A corrupt num_cells can make a computed cell offset run
+// past the end of the page; these report it instead of panicking on the
+// out-of-range slice.
+fn checked_cell_range(node: &[u8], page_num: usize, cell_num: usize, offset: usize, len: usize) -> Result<Range<usize>, DbError> {
+    if offset + len > node.len() {
+        return Err(DbError::CorruptNode { page_num, cell_num });
+    }
+    Ok(offset..offset + len)
+}
+
+fn leaf_node_key_checked(node: &[u8], page_num: usize, cell_num: usize) -> Result<u32, DbError> {
+    let offset = leaf_node_cell_offset(node, cell_num) + LEAF_NODE_KEY_OFFSET;
+    let range = checked_cell_range(node, page_num, cell_num, offset, LEAF_NODE_KEY_SIZE)?;
+    Ok(u32::from_le_bytes(node[range].try_into().unwrap()))
+}
+
+// The whole row must lie inside the page, otherwise deserializing it would
+// read past the end. A heap leaf's value is the row's locator; leaf_row
+// follows it.
+fn leaf_node_value_checked(node: &[u8], page_num: usize, cell_num: usize) -> Result<&[u8], DbError> {
+    let offset = leaf_node_cell_offset(node, cell_num) + LEAF_NODE_VALUE_OFFSET;
+    let value_size = leaf_node_value_size(node);
+    if offset + value_size > node.len() {
+        return Err(DbError::CorruptCell { page_num, cell_num });
+    }
+    Ok(&node[offset..offset + value_size])
+}
+
+// Added: Helper function to get mutable slice of a cell
+fn leaf_node_cell(node: &mut [u8], cell_num: usize) -> &mut [u8] {
+    let offset = leaf_node_cell_offset(node, cell_num);
+    let cell_size = leaf_node_cell_size(node);
+    &mut node[offset..offset + cell_size]
+}
+
+/// Initialize a new leaf node (set num_cells = 0)
+fn initialize_leaf_node(node: &mut [u8]) {
+    set_node_type(node, NodeType::Leaf);
+    set_node_root(node, false);
+    set_leaf_node_num_cells(node, 0);
+    set_leaf_node_next_leaf(node, NO_LEAF);
+    set_leaf_node_prev_leaf(node, NO_LEAF);
+}
+
+fn initialize_internal_node(node: &mut [u8]) {
+    set_node_type(node, NodeType::Internal);
+    set_node_root(node, false);
+    set_internal_node_num_keys(node, 0);
+    set_internal_node_right_child(node, INVALID_PAGE_NUM);
+}
+
+fn set_internal_node_num_keys(node: &mut [u8], value: u32) {
+    let bytes = value.to_le_bytes();
+    node[INTERNAL_NODE_NUM_KEYS_OFFSET..INTERNAL_NODE_NUM_KEYS_OFFSET + 4]
+        .copy_from_slice(&bytes);
+}
+
+fn get_leaf_node_next_leaf(node: &[u8]) -> u32 {
+    let bytes: [u8; 4] = node[LEAF_NODE_NEXT_LEAF_OFFSET..LEAF_NODE_NEXT_LEAF_OFFSET + 4]
+        .try_into()
+        .unwrap();
+    u32::from_le_bytes(bytes)
+}
+
+fn set_leaf_node_next_leaf(node: &mut [u8], next_leaf: u32) {
+    let bytes = next_leaf.to_le_bytes();
+    node[LEAF_NODE_NEXT_LEAF_OFFSET..LEAF_NODE_NEXT_LEAF_OFFSET + 4]
+        .copy_from_slice(&bytes);
+}
+
+// Mirror of next_leaf for backward scans; NO_LEAF on the leftmost leaf
+fn get_leaf_node_prev_leaf(node: &[u8]) -> u32 {
+    get_u32_at(node, LEAF_NODE_PREV_LEAF_OFFSET)
+}
+
+fn set_leaf_node_prev_leaf(node: &mut [u8], prev_leaf: u32) {
+    set_u32_at(node, LEAF_NODE_PREV_LEAF_OFFSET, prev_leaf);
+}
+
+fn update_internal_node_key(node: &mut [u8], old_key: u32, new_key: u32) {
+    let child_index = internal_node_find_child(node, old_key);
+    // The right child has no cell, so its max key is not stored in this node
+    // (an ancestor bounds it); writing at num_keys would clobber the unused
+    // slot past the last cell
+    if child_index == internal_node_num_keys(node) {
+        return;
+    }
+    set_internal_node_key(node, child_index as usize, new_key);
+}
+
+fn set_internal_node_key(node: &mut [u8], index: usize, key: u32) {
+    let offset = INTERNAL_NODE_HEADER_SIZE + index * INTERNAL_NODE_CELL_SIZE + INTERNAL_NODE_KEY_OFFSET;
+    node[offset..offset + 4].copy_from_slice(&key.to_le_bytes());
+}
+
+
+// One entry of the table directory
+#[derive(Clone)]
+struct NamedTable {
+    name: String,
+    root_page_num: u32,
+    // First page of the table's heap, 0 when its rows are in its leaves
+    heap_page_num: u32,
+}
+
+struct Pager {
+    // None for an in-memory database, whose pages never leave the cache
+    file_descriptor: Option<File>,
+    // The file's name, for rebuilding it in place (None in memory)
+    path: Option<String>,
+    file_length: u64,  
+    num_pages: usize,
+    page_size: usize,
+    // Where page 0 starts in the file: after the header page, or 0 when
+    // there is no header (no file, or an empty one opened read-only)
+    data_offset: u64,
+    // One slot per page in use, so pages.len() == num_pages; grows as new
+    // pages are allocated
+    pages: Vec<Option<Box<[u8]>>>,
+    // Cached pages changed since they were last written to the file
+    dirty: HashSet<usize>,
+    // Cap on resident pages (None for no cap) and, per page, the tick of its
+    // last access so the least recently used one can be evicted
+    max_cached_pages: Option<usize>,
+    last_used: Vec<u64>,
+    access_clock: u64,
+    // Pages that were not in the cache when asked for
+    cache_misses: u64,
+    // Bytes statements asked to store vs bytes written to the file, for
+    // measuring write amplification
+    logical_bytes_written: u64,
+    physical_bytes_written: u64,
+    // Head and length of the free-page list, and whether they have changed
+    // since they were last written to the header
+    free_head: u32,
+    free_count: u32,
+    header_dirty: bool,
+    // Set between .begin and .commit or .rollback
+    transaction: Option<Transaction>,
+    // Set while a statement or meta command runs
+    savepoint: Option<Savepoint>,
+    // None for an in-memory or read-only database
+    wal: Option<Wal>,
+    // Whether pages carry a checksum, from the file header
+    checksums: bool,
+    // Root page of each column's index by Column position, 0 when it has
+    // none
+    index_roots: [u32; Column::ALL.len()],
+    // Named tables from the header's directory, in creation order
+    tables: Vec<NamedTable>,
+    // Text column widths from the file header, set by create table
+    text_widths: TextWidths,
+}
+
+// The <filename>-wal redo log. Pages are appended and synced here before
+// they are written to the database file, so a crash part way through
+// leaves a copy that the next open can finish writing.
+struct Wal {
+    file: File,
+    path: String,
+}
+
+// Pager state saved by .begin so .rollback can put it back. Pages are
+// copied on their first change; pages allocated since .begin are not
+// copied and are dropped on rollback instead.
+struct Transaction {
+    pages: HashMap<usize, Box<[u8]>>,
+    num_pages: usize,
+    dirty: HashSet<usize>,
+    free_head: u32,
+    free_count: u32,
+    header_dirty: bool,
+}
+
+// Pager state as it was before the running statement or meta command, so
+// a panic part way through can put it back before the panic-time flush.
+// Like a transaction's copies, but only pages that were clean before the
+// statement are marked clean again, instead of copying the dirty set for
+// every statement.
+struct Savepoint {
+    pages: HashMap<usize, Box<[u8]>>,
+    newly_dirty: HashSet<usize>,
+    num_pages: usize,
+    free_head: u32,
+    free_count: u32,
+    header_dirty: bool,
+}
+
+// A position in the table: page and cell numbers, not references into
+// page memory, so every step reads its page back through the pager. The
+// cursor borrows the table mutably for as long as it lives, so no insert,
+// delete or split can run in the middle of a scan, and a scan sees the
+// table as it was when the cursor was made. With a single writer nothing
+// has to be pinned or copied for this: the cache only evicts clean pages,
+// so a page the scan reads again comes back with the same bytes.
+struct Cursor<'a> {
+    table: &'a mut Table,
+    page_num: usize,
+    cell_num: usize,
+    end_of_table: bool,
+}
+
+fn table_start(table: &mut Table) -> Result<Cursor<'_>, DbError> {
+    let root_page_num = table.root_page_num;
+    tree_start(table, root_page_num)
+}
+
+// The tree functions below work on the B-tree rooted at any page of the
+// file; the table_ versions are shorthands for the table's own tree
+fn tree_start(table: &mut Table, root_page_num: usize) -> Result<Cursor<'_>, DbError> {
+    let page_num = tree_leftmost_leaf(table, root_page_num)?;
+    let mut cursor = Cursor {
+        table,
+        page_num,
+        cell_num: 0,
+        end_of_table: false,
+    };
+    cursor_skip_empty_leaves(&mut cursor)?;
+    Ok(cursor)
+}
+
+fn table_end(table: &mut Table) -> Result<Cursor<'_>, DbError> {
+    let root_page_num = table.root_page_num;
+    tree_end(table, root_page_num)
+}
+
+// Cursor one past the last row: on the rightmost leaf at cell_num ==
+// num_cells. An empty table gives the root leaf at cell 0.
+fn tree_end(table: &mut Table, root_page_num: usize) -> Result<Cursor<'_>, DbError> {
+    let page_num = tree_rightmost_leaf(table, root_page_num)?;
+    let num_cells = leaf_node_num_cells(get_page(&mut table.pager, page_num)?);
+    Ok(Cursor {
+        table,
+        page_num,
+        cell_num: num_cells as usize,
+        end_of_table: true,
+    })
+}
+
+fn table_leftmost_leaf(table: &mut Table) -> Result<usize, DbError> {
+    let root_page_num = table.root_page_num;
+    tree_leftmost_leaf(table, root_page_num)
+}
+
+fn table_rightmost_leaf(table: &mut Table) -> Result<usize, DbError> {
+    let root_page_num = table.root_page_num;
+    tree_rightmost_leaf(table, root_page_num)
+}
+
+// Page number of the leaf holding the smallest keys, found by following
+// child 0 down from the root
+fn tree_leftmost_leaf(table: &mut Table, root_page_num: usize) -> Result<usize, DbError> {
+    let mut page_num = root_page_num;
+    loop {
+        let node = get_page(&mut table.pager, page_num)?;
+        match node_type_checked(node, page_num)? {
+            NodeType::Leaf => return Ok(page_num),
+            NodeType::Internal => page_num = internal_node_child(node, 0)? as usize,
+        }
+    }
+}
+
+// Page number of the leaf holding the largest keys, found by following
+// the right child down from the root
+fn tree_rightmost_leaf(table: &mut Table, root_page_num: usize) -> Result<usize, DbError> {
+    let mut page_num = root_page_num;
+    loop {
+        let node = get_page(&mut table.pager, page_num)?;
+        match node_type_checked(node, page_num)? {
+            NodeType::Leaf => return Ok(page_num),
+            NodeType::Internal => page_num = internal_node_right_child(node) as usize,
+        }
+    }
+}
+
+
+fn table_find(table: &mut Table, key: usize) -> Result<Cursor<'_>, DbError> {
+    let root_page_num = table.root_page_num;
+    tree_find(table, root_page_num, key)
+}
+
+fn tree_find(table: &mut Table, root_page_num: usize, key: usize) -> Result<Cursor<'_>, DbError> {
+    let root_node = get_page(&mut table.pager, root_page_num)?; 
+
+    if node_type_checked(root_node, root_page_num)? == NodeType::Leaf {
+        leaf_node_find(table, root_page_num, key as u32)
+    } else {
+        internal_node_find(table, root_page_num, key)
+    }
+}
+
+// Membership test: descend to the leaf the key would live in and compare
+// the key there, reading only the row's flags to pass over a tombstone
+fn table_key_exists(table: &mut Table, key: u32) -> Result<bool, DbError> {
+    let cursor = table_find(table, key as usize)?;
+    let (page_num, cell_num) = (cursor.page_num, cursor.cell_num);
+    let node = get_page(&mut cursor.table.pager, page_num)?;
+
+    Ok(cell_num < leaf_node_num_cells(node) as usize
+        && leaf_node_key(node, cell_num) == key
+        && !is_tombstone(leaf_row(&mut cursor.table.pager, page_num, cell_num)?))
+}
+
+fn internal_node_find_child(node: &[u8], key: u32) -> u32 {
+    let num_keys = get_u32_at(node, INTERNAL_NODE_NUM_KEYS_OFFSET);
+
+    // Binary search
+    let mut left = 0u32;
+    let mut right = num_keys;
+
+    while left != right {
+        let mid = (left + right) / 2;
+        let mid_key = internal_node_key_at(node, mid as usize);
+
+        if key <= mid_key {
+            right = mid;
+        } else {
+            left = mid + 1;
+        }
+    }
+
+    left
+}
+
+fn internal_node_find(table: &mut Table, page_num: usize, key: usize) -> Result<Cursor<'_>, DbError> {
+    // Get the internal node from the page
+    let node = get_page(&mut table.pager, page_num)?;
+    
+    let child_index = internal_node_find_child(node, key as u32); // Convert key to u32
+    let child_page_num = internal_node_child(node, child_index as usize)? as usize; // Convert child_index to usize
+    let child = get_page(&mut table.pager, child_page_num)?;
+
+    // Recurse or return cursor depending on child type
+    match node_type_checked(child, child_page_num)? {
+        NodeType::Leaf => leaf_node_find(table, child_page_num, key as u32),
+        NodeType::Internal => internal_node_find(table, child_page_num, key),
+    }
+}
+
+fn internal_node_insert(table: &mut Table, parent_page_num: usize, child_page_num: usize) -> Result<(), DbError> {
+    // Step 1: Compute child_max_key first
+    let child_max_key = get_node_max_key(&mut table.pager, child_page_num)?;
+
+    // Step 2: Get parent info and check capacity
+    let (original_num_keys, right_child_page_num) = {
+        let parent = get_page(&mut table.pager, parent_page_num)?;
+        let num_keys = internal_node_num_keys(parent);
+        let right_child = internal_node_right_child(parent);
+        (num_keys, right_child)
+    };
+
+    // Step 3: Handle max capacity case
+    if original_num_keys >= table.internal_max_cells as u32 {
+        internal_node_split_and_insert(table, parent_page_num, child_page_num)?;
+        return Ok(());
+    }
+
+    // Step 4: Handle case where right child is invalid
+    if right_child_page_num == INVALID_PAGE_NUM {
+        let parent = get_page_mut(&mut table.pager, parent_page_num)?;
+        set_internal_node_right_child(parent, child_page_num as u32);
+        return Ok(());
+    }
+
+    // Step 5: Get the index where we should insert
+    let index = {
+        let parent = get_page(&mut table.pager, parent_page_num)?;
+        internal_node_find_child(parent, child_max_key) as usize
+    };
+
+    // Step 6: Get right_max_key
+    let right_max_key = get_node_max_key(&mut table.pager, right_child_page_num as usize)?;
+
+    // Step 7: Perform the insertion
+    {
+        let parent = get_page_mut(&mut table.pager, parent_page_num)?;
+        
+        if child_max_key > right_max_key {
+            // Insert at the end and move right child
+            set_u32_at(parent, internal_node_cell_offset(original_num_keys as usize), right_child_page_num);
+            set_internal_node_key(parent, original_num_keys as usize, right_max_key);
+            set_internal_node_right_child(parent, child_page_num as u32);
+        } else {
+            // Shift existing cells and insert in the middle
+            for i in (index..original_num_keys as usize).rev() {
+                // We need to be careful about borrowing here
+                let cell_data = internal_node_cell(parent, i).to_vec();
+                let dest_cell = internal_node_cell_mut(parent, i + 1);
+                dest_cell.copy_from_slice(&cell_data);
+            }
+
+            // Written as a cell even when index == num_keys, where
+            // set_internal_node_child would replace the right child
+            set_u32_at(parent, internal_node_cell_offset(index), child_page_num as u32);
+            set_internal_node_key(parent, index, child_max_key);
+        }
+
+        set_internal_node_num_keys(parent, original_num_keys + 1);
+    }
+
+    Ok(())
+}
+
+
+fn leaf_node_find(table: &mut Table, page_num: usize, key: u32) -> Result<Cursor<'_>, DbError> {
+    
+    let num_cells;
+    {
+        let node = get_page(&mut table.pager, page_num)?;
+        num_cells = leaf_node_num_cells(node);
+    }
+
+    
+    let mut cursor = Cursor {
+        table,
+        page_num,
+        cell_num: 0,
+        end_of_table: false,
+    };
+
+    // Binary search
+    let mut min_index = 0;
+    let mut one_past_max_index = num_cells;
+
+    // To access the node again, re-borrow
+    let node = get_page(&mut cursor.table.pager, page_num)?;
+
+    while min_index != one_past_max_index {
+        let index = (min_index + one_past_max_index) / 2;
+        // The search can land on a cell a corrupt num_cells made up
+        let key_at_index = leaf_node_key_checked(node, page_num, index as usize)?;
+
+        if key == key_at_index {
+            cursor.cell_num = index as usize;
+            return Ok(cursor);
+        } else if key < key_at_index {
+            one_past_max_index = index;
+        } else {
+            min_index = index + 1;
+        }
+    }
+
+    cursor.cell_num = min_index as usize;
+    Ok(cursor)
+}
+
+
+// Node type for traversals, reporting an unknown type byte as an error
+// instead of panicking
+fn node_type_checked(node: &[u8], page_num: usize) -> Result<NodeType, DbError> {
+    match node[NODE_TYPE_OFFSET] {
+        0 => Ok(NodeType::Internal),
+        1 | HEAP_LEAF_TYPE => Ok(NodeType::Leaf),
+        node_type => Err(DbError::NodeTypeInvalid { page_num, node_type }),
+    }
+}
+
+// Set node type in a mutable byte slice
+// Node capacities that fill a page of the given size
+fn leaf_node_max_cells(page_size: usize) -> usize {
+    (page_size - LEAF_NODE_HEADER_SIZE) / LEAF_NODE_CELL_SIZE
+}
+
+fn heap_leaf_max_cells(page_size: usize) -> usize {
+    (page_size - LEAF_NODE_HEADER_SIZE) / (LEAF_NODE_KEY_SIZE + HEAP_LOCATOR_SIZE)
+}
+
+fn heap_page_max_rows(page_size: usize) -> usize {
+    (page_size - HEAP_PAGE_HEADER_SIZE) / ROW_SIZE
+}
+
+fn internal_node_max_cells(page_size: usize) -> usize {
+    (page_size - INTERNAL_NODE_HEADER_SIZE) / INTERNAL_NODE_CELL_SIZE
+}
+
+fn set_node_type(node: &mut [u8], node_type: NodeType) {
+    node[NODE_TYPE_OFFSET] = node_type as u8;
+}
+
+
+/*
+ * fn leaf_node_num_cells(node: &[u8; PAGE_SIZE]) -> usize {
+    let value = u32::from_le_bytes([
+        node[LEAF_NODE_NUM_CELLS_OFFSET],
+        node[LEAF_NODE_NUM_CELLS_OFFSET + 1],
+        node[LEAF_NODE_NUM_CELLS_OFFSET + 2],
+        node[LEAF_NODE_NUM_CELLS_OFFSET + 3],
+    ]);
+    value as usize
+}
+ */
+
+// value is a cell's serialized value, leaf_node_value_size bytes: a row in
+// the table's tree, an id bucket in an index, a locator in a heap leaf
+fn leaf_node_insert(cursor: &mut Cursor, key: u32, value: &[u8]) -> Result<(), DbError> {
+    let page_num = cursor.page_num;
+    let node = get_page_mut(&mut cursor.table.pager, page_num)?;
+
+    let num_cells = leaf_node_num_cells(node);
+
+    if num_cells >= cursor.table.leaf_max_cells as u32 {
+        leaf_node_split_and_insert(cursor, key, value)?;
+
+        return Ok(());
+    }
+
+    // Make room for the new cell if inserting in the middle
+    let cell_size = leaf_node_cell_size(node);
+    if cursor.cell_num < num_cells as usize {
+        // Move cells to make room - iterate in reverse to avoid overwriting
+        for i in ((cursor.cell_num + 1)..=num_cells as usize).rev() {
+            let src_offset = leaf_node_cell_offset(node, i - 1);
+            let dest_offset = leaf_node_cell_offset(node, i);
+            
+            // Copy the cell data
+            let (left, right) = node.split_at_mut(dest_offset);
+            let dest = &mut right[..cell_size];
+            let src = &left[src_offset..src_offset + cell_size];
+            dest.copy_from_slice(src);
+        }
+    }
+
+    // Update cell count
+    set_leaf_node_num_cells(node, num_cells + 1);
+
+    // Insert key at the correct offset
+    let key_offset = leaf_node_cell_offset(node, cursor.cell_num);
+    let key_bytes = key.to_le_bytes();
+    node[key_offset..key_offset + 4].copy_from_slice(&key_bytes);
+
+    // Serialize value at the correct offset
+    let value_offset = key_offset + LEAF_NODE_KEY_SIZE;
+    node[value_offset..value_offset + cell_size - LEAF_NODE_KEY_SIZE].copy_from_slice(value);
+
+    debug_assert_leaf_sorted(node, page_num, cursor.table.leaf_max_cells);
+
+    Ok(())
+}
+
+// Debug-build tripwire run after every leaf mutation: the cell count must
+// fit the node and keys must be strictly increasing. Panics naming the
+// page and the out-of-order keys, so the insert that broke the leaf is the
+// one that fails. Release builds skip it.
+fn debug_assert_leaf_sorted(node: &[u8], page_num: usize, max_cells: usize) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    let num_cells = leaf_node_num_cells(node) as usize;
+    assert!(
+        num_cells <= max_cells,
+        "leaf {} has {} cells, more than {}",
+        page_num,
+        num_cells,
+        max_cells
+    );
+    for i in 1..num_cells {
+        let (previous, key) = (leaf_node_key(node, i - 1), leaf_node_key(node, i));
+        assert!(
+            previous < key,
+            "leaf {}: key {} at cell {} follows key {}",
+            page_num,
+            key,
+            i,
+            previous
+        );
+    }
+}
+
+fn leaf_node_split_and_insert(cursor: &mut Cursor, key: u32, value: &[u8]) -> Result<(), DbError> {
+    // The rightmost leaf may be about to change
+    cursor.table.rightmost_leaf = None;
+
+    // Get the old page number first
+    let old_page_num = cursor.page_num;
+    let new_page_num = get_unused_page_num(&mut cursor.table.pager)?;
+
+    // The old node's max key before the split, to find its parent cell by.
+    // Deletes can leave that cell's key above it, which still routes here.
+    let old_max = get_node_max_key(&mut cursor.table.pager, old_page_num)?;
+    
+    // First, get the old next leaf value, and the type byte that tells a
+    // heap leaf from a row leaf
+    let (old_next_leaf, leaf_type) = {
+        let old_node = get_page(&mut cursor.table.pager, old_page_num)?;
+        (get_leaf_node_next_leaf(old_node), old_node[NODE_TYPE_OFFSET])
+    };
+    
+    // Initialize the new node and link it in between the old node and the
+    // old node's successor
+    {
+        let new_node = get_page_mut(&mut cursor.table.pager, new_page_num)?;
+        initialize_leaf_node(new_node);
+        new_node[NODE_TYPE_OFFSET] = leaf_type;
+        set_leaf_node_next_leaf(new_node, old_next_leaf);
+        set_leaf_node_prev_leaf(new_node, old_page_num as u32);
+    }
+    if old_next_leaf != NO_LEAF {
+        let next_node = get_page_mut(&mut cursor.table.pager, old_next_leaf as usize)?;
+        set_leaf_node_prev_leaf(next_node, new_page_num as u32);
+    }
+
+    // Get parent page number and set it for the new node
+    let parent_page_num = {
+        let old_node = get_page(&mut cursor.table.pager, old_page_num)?;
+        node_parent(old_node)
+    };
+    
+    {
+        let new_node = get_page_mut(&mut cursor.table.pager, new_page_num)?;
+        set_node_parent(new_node, parent_page_num);
+    }
+    
+    // Update the old node's next pointer
+    {
+        let old_node = get_page_mut(&mut cursor.table.pager, old_page_num)?;
+        set_leaf_node_next_leaf(old_node, new_page_num as u32);
+    }
+
+    // Gather the old node's cells and the new one, in key order: the new
+    // cell goes at cursor.cell_num, which may be one past the last cell
+    let max_cells = cursor.table.leaf_max_cells;
+    let left_split_count = if cursor.table.pack_appends && cursor.cell_num == max_cells {
+        max_cells
+    } else {
+        cursor.table.leaf_left_split_count()
+    };
+    let mut all_cells = Vec::with_capacity(max_cells + 1);
+    let cell_size = {
+        let old_node = get_page(&mut cursor.table.pager, old_page_num)?;
+        let num_cells = leaf_node_num_cells(old_node) as usize;
+        let cell_size = leaf_node_cell_size(old_node);
+        for i in 0..num_cells {
+            let offset = leaf_node_cell_offset(old_node, i);
+            all_cells.push(old_node[offset..offset + cell_size].to_vec());
+        }
+        cell_size
+    };
+    let mut new_cell = vec![0u8; cell_size];
+    new_cell[..LEAF_NODE_KEY_SIZE].copy_from_slice(&key.to_le_bytes());
+    new_cell[LEAF_NODE_KEY_SIZE..].copy_from_slice(value);
+    all_cells.insert(cursor.cell_num, new_cell);
+    debug_assert_eq!(all_cells.len(), max_cells + 1, "split of a leaf that is not full");
+
+    // The first left_split_count cells stay in the old node and the rest
+    // move to the new one
+    let (left_cells, right_cells) = all_cells.split_at(left_split_count);
+    {
+        let old_node = get_page_mut(&mut cursor.table.pager, old_page_num)?;
+        for (i, cell) in left_cells.iter().enumerate() {
+            leaf_node_cell(old_node, i).copy_from_slice(cell);
+        }
+        set_leaf_node_num_cells(old_node, left_cells.len() as u32);
+    }
+    {
+        let new_node = get_page_mut(&mut cursor.table.pager, new_page_num)?;
+        for (i, cell) in right_cells.iter().enumerate() {
+            leaf_node_cell(new_node, i).copy_from_slice(cell);
+        }
+        set_leaf_node_num_cells(new_node, right_cells.len() as u32);
+    }
+
+    for page_num in [old_page_num, new_page_num] {
+        let node = get_page(&mut cursor.table.pager, page_num)?;
+        debug_assert_leaf_sorted(node, page_num, max_cells);
+    }
+
+    // Check if we need to create a new root
+    let is_root = {
+        let old_node = get_page(&mut cursor.table.pager, old_page_num)?;
+        is_node_root(old_node)
+    };
+    
+    if is_root {
+        create_new_root(cursor.table, old_page_num, new_page_num)?;
+    } else {
+        // 1. Get the parent page number
+        let parent_page_num = {
+            let old_node = get_page(&mut cursor.table.pager, old_page_num)?;
+            node_parent(old_node) as usize
+        };
+
+        // 2. Get max key of old_node after the split
+        let new_max = get_node_max_key(&mut cursor.table.pager, old_page_num)?;
+
+        // 3. Load the parent page and update the key
+        {
+            let parent = get_page_mut(&mut cursor.table.pager, parent_page_num)?;
+            update_internal_node_key(parent, old_max, new_max);
+        }
+
+        // 4. Insert the new_node into the parent
+        internal_node_insert(cursor.table, parent_page_num, new_page_num)?;
+    }
+
+    Ok(())
+}
+
+fn node_parent(node: &[u8]) -> u32 {
+    let offset = PARENT_POINTER_OFFSET;
+    let bytes = &node[offset..offset + std::mem::size_of::<u32>()];
+    u32::from_le_bytes(bytes.try_into().expect("Failed to read parent pointer"))
+}
+
+//setter function
+fn set_node_parent(node: &mut [u8], parent_page_num: u32) {
+    let offset = PARENT_POINTER_OFFSET;
+    node[offset..offset + 4].copy_from_slice(&parent_page_num.to_le_bytes());
+}
+
+fn internal_node_split_and_insert(table: &mut Table, parent_page_num: usize, child_page_num: usize) -> Result<(), DbError> {
+    let old_page_num = parent_page_num;
+    
+    // Get the old node's max key before any modifications
+    let old_max = get_node_max_key(&mut table.pager, parent_page_num)?;
+
+    // Get the child's max key
+    let child_max = get_node_max_key(&mut table.pager, child_page_num)?;
+
+    let new_page_num = get_unused_page_num(&mut table.pager)?;
+
+    // Check if we're splitting the root
+    let splitting_root = {
+        let old_node = get_page(&mut table.pager, old_page_num)?;
+        is_node_root(old_node)
+    };
+
+    let (actual_old_page_num, parent_page_num) = if splitting_root {
+        // Create new root and get the new structure
+        create_new_root(table, old_page_num, new_page_num)?;
+        
+        // Get the new left child page number (which is where old content moved);
+        // the root itself stays on its page
+        let parent = get_page(&mut table.pager, old_page_num)?;
+        let left_child_page_num = internal_node_child(parent, 0)? as usize;
+        
+        (left_child_page_num, old_page_num)
+    } else {
+        // Initialize the new node
+        {
+            let new_node = get_page_mut(&mut table.pager, new_page_num)?;
+            initialize_internal_node(new_node);
+        }
+        
+        // Get parent page number
+        let parent_page_num = {
+            let old_node = get_page(&mut table.pager, old_page_num)?;
+            node_parent(old_node) as usize
+        };
+        
+        (old_page_num, parent_page_num)
+    };
+
+    // Get the right child of the old node before we start moving things
+    let cur_page_num = {
+        let old_node = get_page(&mut table.pager, actual_old_page_num)?;
+        internal_node_right_child(old_node)
+    };
+
+    // First, put the right child into the new node and invalidate old node's right child
+    internal_node_insert(table, new_page_num, cur_page_num as usize)?;
+    
+    // Update the moved child's parent pointer
+    {
+        let cur_child = get_page_mut(&mut table.pager, cur_page_num as usize)?;
+        set_node_parent(cur_child, new_page_num as u32);
+    }
+    
+    // Set old node's right child to invalid
+    {
+        let old_node = get_page_mut(&mut table.pager, actual_old_page_num)?;
+        set_internal_node_right_child(old_node, INVALID_PAGE_NUM);
+    }
+
+    // Move keys and children from old node to new node
+    // We need to be careful with borrowing here
+    let mut keys_to_move = Vec::new();
+    {
+        let old_node = get_page(&mut table.pager, actual_old_page_num)?;
+        let num_keys = internal_node_num_keys(old_node);
+        
+        // Collect the keys and children we need to move (from right to left)
+        let max_cells = table.internal_max_cells;
+        for i in ((max_cells / 2 + 1)..max_cells).rev() {
+            if i < num_keys as usize {
+                let child_page_num = internal_node_child(old_node, i)?;
+                keys_to_move.push((i, child_page_num));
+            }
+        }
+    }
+
+    // Now move the collected keys and children
+    for (_i, child_page_num) in keys_to_move {
+        internal_node_insert(table, new_page_num, child_page_num as usize)?;
+        
+        // Update the child's parent pointer
+        {
+            let child = get_page_mut(&mut table.pager, child_page_num as usize)?;
+            set_node_parent(child, new_page_num as u32);
+        }
+        
+        // Decrement the old node's key count
+        {
+            let old_node = get_page_mut(&mut table.pager, actual_old_page_num)?;
+            let current_keys = internal_node_num_keys(old_node);
+            set_internal_node_num_keys(old_node, current_keys - 1);
+        }
+    }
+
+    // Set the child before the middle key to be the old node's right child
+    {
+        let old_node = get_page_mut(&mut table.pager, actual_old_page_num)?;
+        let num_keys = internal_node_num_keys(old_node);
+        let right_child_page_num = internal_node_child(old_node, num_keys as usize - 1)?;
+        
+        set_internal_node_right_child(old_node, right_child_page_num);
+        set_internal_node_num_keys(old_node, num_keys - 1);
+    }
+
+    // Determine which node should contain the child to be inserted
+    let max_after_split = get_node_max_key(&mut table.pager, actual_old_page_num)?;
+
+    let destination_page_num = if child_max < max_after_split {
+        actual_old_page_num
+    } else {
+        new_page_num
+    };
+
+    // Insert the child into the appropriate node
+    internal_node_insert(table, destination_page_num, child_page_num)?;
+    
+    // Update the child's parent pointer
+    {
+        let child = get_page_mut(&mut table.pager, child_page_num)?;
+        set_node_parent(child, destination_page_num as u32);
+    }
+
+    // Update the parent's key that pointed to the old node
+    {
+        let new_old_max = get_node_max_key(&mut table.pager, actual_old_page_num)?;
+        let parent = get_page_mut(&mut table.pager, parent_page_num)?;
+        update_internal_node_key(parent, old_max, new_old_max);
+    }
+
+    // If we're not splitting the root, insert the new node into its parent
+    if !splitting_root {
+        let parent_of_old = {
+            let old_node = get_page(&mut table.pager, actual_old_page_num)?;
+            node_parent(old_node)
+        };
+
+        // Set the new node's parent first: if the insert splits the parent
+        // in turn, the new node may move to the parent's new sibling, and
+        // that split points it there
+        {
+            let new_node = get_page_mut(&mut table.pager, new_page_num)?;
+            set_node_parent(new_node, parent_of_old);
+        }
+
+        internal_node_insert(table, parent_of_old as usize, new_page_num)?;
+    }
+
+    Ok(())
+}
+
+//creating a new root; the root keeps its page number so callers holding
+//it stay valid
+fn create_new_root(table: &mut Table, root_page_num: usize, right_child_page_num: usize) -> Result<(), DbError> {
+
+    // Load the right child first so it counts towards num_pages; otherwise
+    // the left child would be handed the same unused page number.
+    get_page(&mut table.pager, right_child_page_num)?;
+    let left_child_page_num = get_unused_page_num(&mut table.pager)?;
+
+    // First, get data we need from the root
+    let (root_is_internal, root_data) = {
+        let root = get_page(&mut table.pager, root_page_num)?;
+        let is_internal = node_type_checked(root, root_page_num)? == NodeType::Internal;
+        let data = root.to_vec(); // Copy the data
+        (is_internal, data)
+    };
+
+    // Initialize the children based on root type
+    if root_is_internal {
+        let right_child = get_page_mut(&mut table.pager, right_child_page_num)?;
+        initialize_internal_node(right_child);
+        
+        let left_child = get_page_mut(&mut table.pager, left_child_page_num)?;
+        initialize_internal_node(left_child);
+    }
+
+    // Copy the old root's data into the new left child
+    {
+        let left_child = get_page_mut(&mut table.pager, left_child_page_num)?;
+        left_child.copy_from_slice(&root_data);
+        set_node_root(left_child, false);
+    }
+
+    // If left child is internal, update its children's parent pointers
+    if root_is_internal {
+        let (num_keys, right_page_num) = {
+            let left_child = get_page(&mut table.pager, left_child_page_num)?;
+            let num_keys = internal_node_num_keys(left_child);
+            let right_page_num = internal_node_right_child(left_child);
+            (num_keys, right_page_num)
+        };
+
+        // Update children's parent pointers
+        for i in 0..num_keys {
+            let child_page_num = {
+                let left_child = get_page(&mut table.pager, left_child_page_num)?;
+                internal_node_child(left_child, i as usize)?
+            };
+            
+            let child = get_page_mut(&mut table.pager, child_page_num as usize)?;
+            set_node_parent(child, left_child_page_num as u32);
+        }
+
+        // Also update the right child of the internal node
+        if right_page_num != INVALID_PAGE_NUM {
+            let right = get_page_mut(&mut table.pager, right_page_num as usize)?;
+            set_node_parent(right, left_child_page_num as u32);
+        }
+    }
+
+    // Get the left max key before reinitializing root
+    let left_max_key = get_node_max_key(&mut table.pager, left_child_page_num)?;
+
+    // Re-initialize the root as a fresh internal node with two children
+    {
+        let root = get_page_mut(&mut table.pager, root_page_num)?;
+        initialize_internal_node(root);
+        set_node_root(root, true);
+        set_internal_node_num_keys(root, 1);
+        set_internal_node_child(root, 0, left_child_page_num as u32);
+        set_internal_node_key(root, 0, left_max_key);
+        set_internal_node_right_child(root, right_child_page_num as u32);
+    }
+
+    // Set parent pointers for new children
+    {
+        let left_child = get_page_mut(&mut table.pager, left_child_page_num)?;
+        set_node_parent(left_child, root_page_num as u32);
+    }
+    
+    {
+        let right_child = get_page_mut(&mut table.pager, right_child_page_num)?;
+        set_node_parent(right_child, root_page_num as u32);
+        // A split leaf root linked its new sibling back to the root's page;
+        // those cells now live in the left child
+        if !root_is_internal {
+            set_leaf_node_prev_leaf(right_child, left_child_page_num as u32);
+        }
+    }
+
+    Ok(())
+}
+
+// Reuse the most recently freed page if there is one, otherwise the page
+// just past the end. A reused page is zeroed so it looks like a new one.
+fn get_unused_page_num(pager: &mut Pager) -> Result<usize, DbError> {
+    if pager.free_head == 0 {
+        return Ok(pager.num_pages);
+    }
+
+    let page_num = pager.free_head as usize;
+    let page = get_page_mut(pager, page_num)?;
+    if page[NODE_TYPE_OFFSET] != FREE_PAGE_TYPE {
+        return Err(DbError::NodeTypeInvalid { page_num, node_type: page[NODE_TYPE_OFFSET] });
+    }
+    let next = get_u32_at(page, FREE_PAGE_NEXT_OFFSET);
+    page.fill(0);
+
+    pager.free_head = next;
+    pager.free_count -= 1;
+    pager.header_dirty = true;
+    Ok(page_num)
+}
+
+// Push a page nobody points to any more onto the free list
+fn free_page(pager: &mut Pager, page_num: usize) -> Result<(), DbError> {
+    let free_head = pager.free_head;
+    let page = get_page_mut(pager, page_num)?;
+    page.fill(0);
+    page[NODE_TYPE_OFFSET] = FREE_PAGE_TYPE;
+    set_u32_at(page, FREE_PAGE_NEXT_OFFSET, free_head);
+
+    pager.free_head = page_num as u32;
+    pager.free_count += 1;
+    pager.header_dirty = true;
+    Ok(())
+}
+
+// Follow the free list from the header's head pointer, most recently freed
+// first. A link to a page that isn't free is an error; a link back to a page
+// already listed ends the walk, so the caller sees fewer pages than the
+// header counts.
+fn free_list_pages(pager: &mut Pager) -> Result<Vec<usize>, DbError> {
+    let mut pages = Vec::new();
+    let mut seen = HashSet::new();
+    let mut page_num = pager.free_head as usize;
+
+    while page_num != 0 && seen.insert(page_num) {
+        let page = get_page(pager, page_num)?;
+        if page[NODE_TYPE_OFFSET] != FREE_PAGE_TYPE {
+            return Err(DbError::NodeTypeInvalid { page_num, node_type: page[NODE_TYPE_OFFSET] });
+        }
+        pages.push(page_num);
+        page_num = get_u32_at(page, FREE_PAGE_NEXT_OFFSET) as usize;
+    }
+
+    Ok(pages)
+}
+
+
+struct Table {
+    root_page_num: usize,
+    pager: Box<Pager>, // Changed from 'pages' to 'pager'
+    // Page of the rightmost leaf, cached for the sequential insert fast
+    // path. Cleared whenever a leaf splits.
+    rightmost_leaf: Option<usize>,
+    settings: Settings,
+    // Node capacities, normally the page-derived maximums. They can be
+    // lowered to force splits with only a few rows.
+    leaf_max_cells: usize,
+    internal_max_cells: usize,
+    // The capacity of a heap leaf, which in_named_table puts in place of
+    // leaf_max_cells for a heap-organized table
+    heap_leaf_max_cells: usize,
+    // First heap page of the table statements run on, or 0 when it keeps
+    // its rows in its leaves
+    heap_page_num: usize,
+    // Parsed statements keyed by their trimmed text (after ? binding), so a
+    // repeated line skips prepare_statement. Only parses that depend on the
+    // text alone are kept; anything that changes how the same text parses
+    // (a runtime schema change, say) must clear it.
+    statement_cache: HashMap<String, Statement>,
+    // Set while vacuum rebuilds a table: a full leaf taking a key past its
+    // last one keeps all its cells and the new key starts the next leaf,
+    // so rows inserted in key order pack every leaf instead of half of it
+    pack_appends: bool,
+    // Where meta commands and statements run through run_input print
+    // their results; a sink until Database::set_output supplies one
+    out: Box<dyn Write>,
+}
+
+impl Table {
+    // Leaf node split balancing
+    fn leaf_right_split_count(&self) -> usize {
+        self.leaf_max_cells.div_ceil(2)
+    }
+
+    // The output writer, for code that prints while the table is borrowed
+    // by a scan; output goes to a sink until it is put back
+    fn take_out(&mut self) -> Box<dyn Write> {
+        std::mem::replace(&mut self.out, Box::new(io::sink()))
+    }
+
+    fn leaf_left_split_count(&self) -> usize {
+        (self.leaf_max_cells + 1) - self.leaf_right_split_count()
+    }
+
+    // The fewest keys a non-root internal node keeps after a delete
+    fn internal_min_keys(&self) -> usize {
+        (self.internal_max_cells / 2).max(1)
+    }
+}
+
+// Session options toggled by meta commands
+#[derive(Default)]
+struct Settings {
+    // Print a "(N rows)" line after each select
+    footer: bool,
+    // Print each input line before running it
+    echo: bool,
+    // Compare username/email equality with an ASCII case fold
+    username_nocase: bool,
+    email_nocase: bool,
+    // Most rows one select may scan before it is aborted; None is unlimited
+    max_scan: Option<usize>,
+    // Leave the preallocated tail in place on close instead of trimming the
+    // file to its last real page
+    keep_preallocated: bool,
+    // Values bound to ? placeholders, set with .param set <n> <value>;
+    // params[0] is ?1
+    params: Vec<Option<String>>,
+    // Reject inserts into a full leaf with TableFull instead of splitting
+    bounded: bool,
+    // Delete by marking the row as a tombstone instead of removing its cell
+    soft_delete: bool,
+    // Cut over-long values to the column width with a warning instead of
+    // rejecting the statement
+    truncate: bool,
+    // Fail a select on text that is not valid UTF-8 instead of printing it
+    // with replacement characters
+    strict_utf8: bool,
+    // How select prints rows, set with .mode
+    output_mode: OutputMode,
+    // Where .vacuum builds its copy; None builds it next to the database.
+    // Set with --temp-dir or DB_TEMP_DIR.
+    temp_dir: Option<PathBuf>,
+}
+
+#[derive(Default, Clone, Copy)]
+enum OutputMode {
+    // (id, username, email)
+    #[default]
+    List,
+    // {"id":N,"username":"...","email":"..."}, one object per line
+    Json,
+    // (id, x'...', x'...'): the stored bytes as hex literals that insert
+    // accepts back, for values that are not text
+    Hex,
+    // id,username,email lines quoted the way .export writes them
+    Csv,
+}
+
+// Safety net for panics: if a panic unwinds past the table, the statement
+// it hit is undone and the work of earlier statements is synced through the
+// log like any other batch. This is a best-effort mitigation, not a
+// durability guarantee: a panic outside any statement has no savepoint to
+// undo, and I/O errors are ignored since there is nobody left to report
+// them to. Changes of an open transaction are not written, since it never
+// committed.
+impl Drop for Table {
+    fn drop(&mut self) {
+        if !std::thread::panicking() || self.pager.transaction.is_some() {
+            return;
+        }
+        let pager = &mut *self.pager;
+        if let Some(savepoint) = pager.savepoint.take() {
+            undo_statement(pager, savepoint);
+        }
+        let _ = pager_sync(pager);
+    }
+}
+
+// The row under the cursor, from the heap for a heap-organized table
+fn cursor_value<'a>(cursor: &'a mut Cursor) -> Result<&'a [u8], DbError> {
+    leaf_row(&mut cursor.table.pager, cursor.page_num, cursor.cell_num)
+}
+
+// The row a leaf cell holds: its value, or for a heap leaf the heap slot
+// its locator names
+fn leaf_row(pager: &mut Pager, page_num: usize, cell_num: usize) -> Result<&[u8], DbError> {
+    let node = get_page(pager, page_num)?;
+    let value = leaf_node_value_checked(node, page_num, cell_num)?;
+    if node[NODE_TYPE_OFFSET] != HEAP_LEAF_TYPE {
+        let node = get_page(pager, page_num)?;
+        return leaf_node_value_checked(node, page_num, cell_num);
+    }
+
+    let (heap_page_num, slot) = (get_u32_at(value, 0) as usize, get_u32_at(value, 4) as usize);
+    if heap_page_num >= pager.num_pages {
+        return Err(DbError::CorruptCell { page_num, cell_num });
+    }
+    let heap_page = get_page(pager, heap_page_num)?;
+    let num_rows = get_u32_at(heap_page, HEAP_PAGE_NUM_ROWS_OFFSET) as usize;
+    if heap_page[NODE_TYPE_OFFSET] != HEAP_PAGE_TYPE || slot >= num_rows {
+        return Err(DbError::CorruptCell { page_num, cell_num });
+    }
+    let offset = HEAP_PAGE_HEADER_SIZE + slot * ROW_SIZE;
+    Ok(&heap_page[offset..offset + ROW_SIZE])
+}
+
+// Append a row to the table's heap, on its last page or on a new one when
+// that is full, and return the row's locator
+fn heap_append(table: &mut Table, row: &Row) -> Result<[u8; HEAP_LOCATOR_SIZE], DbError> {
+    let first_page_num = table.heap_page_num;
+    let max_rows = heap_page_max_rows(usable_page_size(&table.pager));
+    let first_page = get_page(&mut table.pager, first_page_num)?;
+    let mut page_num = get_u32_at(first_page, HEAP_PAGE_LAST_OFFSET) as usize;
+    let last_page = get_page(&mut table.pager, page_num)?;
+    let mut slot = get_u32_at(last_page, HEAP_PAGE_NUM_ROWS_OFFSET) as usize;
+    if slot >= max_rows {
+        page_num = get_unused_page_num(&mut table.pager)?;
+        initialize_heap_page(get_page_mut(&mut table.pager, page_num)?, page_num);
+        let first_page = get_page_mut(&mut table.pager, first_page_num)?;
+        set_u32_at(first_page, HEAP_PAGE_LAST_OFFSET, page_num as u32);
+        slot = 0;
+    }
+
+    let page = get_page_mut(&mut table.pager, page_num)?;
+    let offset = HEAP_PAGE_HEADER_SIZE + slot * ROW_SIZE;
+    serialize_row(row, &mut page[offset..offset + ROW_SIZE]);
+    set_u32_at(page, HEAP_PAGE_NUM_ROWS_OFFSET, slot as u32 + 1);
+    table.pager.logical_bytes_written += ROW_SIZE as u64;
+
+    let mut locator = [0u8; HEAP_LOCATOR_SIZE];
+    set_u32_at(&mut locator, 0, page_num as u32);
+    set_u32_at(&mut locator, 4, slot as u32);
+    Ok(locator)
+}
+
+// An empty heap page whose last-page field names itself, which only the
+// first page of a heap reads
+fn initialize_heap_page(page: &mut [u8], page_num: usize) {
+    page.fill(0);
+    page[NODE_TYPE_OFFSET] = HEAP_PAGE_TYPE;
+    set_u32_at(page, HEAP_PAGE_LAST_OFFSET, page_num as u32);
+}
+
+// Step forward one row, moving on to the next leaf that has rows when this
+// one is used up. A page that can't be read is an error for the caller.
+fn cursor_advance(cursor: &mut Cursor) -> Result<(), DbError> {
+    let page_num = cursor.page_num;
+    let node = get_page(&mut cursor.table.pager, page_num)?;
+
+    cursor.cell_num += 1;
+
+    // If we've exhausted the cells in this leaf
+    let num_cells = leaf_node_num_cells(node);
+    if cursor.cell_num >= num_cells as usize {
+        let next_page_num = get_leaf_node_next_leaf(node);
+
+        if next_page_num == NO_LEAF {
+            // This is the rightmost leaf node
+            cursor.end_of_table = true;
+        } else {
+            // Jump to the next leaf node
+            cursor.page_num = next_page_num as usize;
+            cursor.cell_num = 0;
+            cursor_skip_empty_leaves(cursor)?;
+        }
+    }
+    Ok(())
+}
+
+// Step back one row: to the previous cell, or to the last cell of the
+// nearest earlier leaf that has rows. From table_end this lands on the last
+// row; stepping back from the first row sets end_of_table.
+fn cursor_retreat(cursor: &mut Cursor) -> Result<(), DbError> {
+    if cursor.cell_num > 0 {
+        cursor.cell_num -= 1;
+        cursor.end_of_table = false;
+        return Ok(());
+    }
+
+    loop {
+        let node = get_page(&mut cursor.table.pager, cursor.page_num)?;
+        let prev_page_num = get_leaf_node_prev_leaf(node);
+        if prev_page_num == NO_LEAF {
+            cursor.end_of_table = true;
+            return Ok(());
+        }
+        cursor.page_num = prev_page_num as usize;
+
+        let num_cells = leaf_node_num_cells(get_page(&mut cursor.table.pager, cursor.page_num)?);
+        if num_cells > 0 {
+            cursor.cell_num = num_cells as usize - 1;
+            cursor.end_of_table = false;
+            return Ok(());
+        }
+    }
+}
+
+// Deletes can leave leaves with no cells; move a cursor sitting on one
+// forward to the next leaf that has rows, or to the end of the table
+fn cursor_skip_empty_leaves(cursor: &mut Cursor) -> Result<(), DbError> {
+    loop {
+        let node = get_page(&mut cursor.table.pager, cursor.page_num)?;
+        if cursor.cell_num < leaf_node_num_cells(node) as usize {
+            return Ok(());
+        }
+
+        let next_page_num = get_leaf_node_next_leaf(node);
+        if next_page_num == NO_LEAF {
+            cursor.end_of_table = true;
+            return Ok(());
+        }
+        cursor.page_num = next_page_num as usize;
+        cursor.cell_num = 0;
+    }
+}
+
+/// Iterator over the keys of a table in ascending order. It walks the leaf
+/// chain like a select does, but only reads each cell's key and the flags
+/// byte that marks a soft-deleted row, and never deserializes the row.
+pub struct KeyIter<'a> {
+    cursor: Cursor<'a>,
+    // The cursor still sits on the key last returned; it moves on at the
+    // start of the next call so a failed step is reported as an item
+    advance_pending: bool,
+}
+
+impl Iterator for KeyIter<'_> {
+    type Item = Result<u32, DbError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = iter_skip_tombstones(&mut self.cursor, &mut self.advance_pending) {
+            return Some(Err(e));
+        }
+        if self.cursor.end_of_table {
+            return None;
+        }
+
+        let page_num = self.cursor.page_num;
+        let key = get_page(&mut self.cursor.table.pager, page_num)
+            .and_then(|node| leaf_node_key_checked(node, page_num, self.cursor.cell_num));
+
+        // Stop after reporting an unreadable page or corrupt cell rather
+        // than walking on
+        if key.is_err() {
+            self.cursor.end_of_table = true;
+        } else {
+            self.advance_pending = true;
+        }
+
+        Some(key)
+    }
+}
+
+/// Iterator over the rows of a table in ascending key order. Each row is
+/// deserialized as the cursor reaches it, so a scan holds one row at a time.
+pub struct RowIter<'a> {
+    cursor: Cursor<'a>,
+    // As in KeyIter
+    advance_pending: bool,
+}
+
+impl Iterator for RowIter<'_> {
+    type Item = Result<Row, DbError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = iter_skip_tombstones(&mut self.cursor, &mut self.advance_pending) {
+            return Some(Err(e));
+        }
+        if self.cursor.end_of_table {
+            return None;
+        }
+
+        let row = cursor_value(&mut self.cursor).map(Row::deserialize);
+
+        // Same as KeyIter: an error ends the iteration
+        if row.is_err() {
+            self.cursor.end_of_table = true;
+        } else {
+            self.advance_pending = true;
+        }
+
+        Some(row)
+    }
+}
+
+// The deferred step shared by KeyIter and RowIter. A failed step ends the
+// iteration once its error has been returned.
+fn iter_advance(cursor: &mut Cursor, advance_pending: &mut bool) -> Result<(), DbError> {
+    if std::mem::take(advance_pending) {
+        if let Err(e) = cursor_advance(cursor) {
+            cursor.end_of_table = true;
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+// iter_advance, then on past any soft-deleted rows, so an iterator only
+// stops on live ones
+fn iter_skip_tombstones(cursor: &mut Cursor, advance_pending: &mut bool) -> Result<(), DbError> {
+    iter_advance(cursor, advance_pending)?;
+    while !cursor.end_of_table {
+        match cursor_value(cursor) {
+            Ok(value) if is_tombstone(value) => {}
+            Ok(_) => break,
+            Err(e) => {
+                cursor.end_of_table = true;
+                return Err(e);
+            }
+        }
+        *advance_pending = true;
+        iter_advance(cursor, advance_pending)?;
+    }
+    Ok(())
+}
+
+// Visit every leaf in physical page order (0..num_pages) instead of key
+// order, skipping internal nodes and heap pages. This is the primitive for
+// maintenance tools such as checksumming or relocation that don't care
+// about keys.
+fn for_each_leaf_page<F: FnMut(usize, &[u8])>(pager: &mut Pager, mut f: F) -> Result<(), DbError> {
+    for page_num in 0..pager.num_pages {
+        let node = get_page(pager, page_num)?;
+        if matches!(node[NODE_TYPE_OFFSET], FREE_PAGE_TYPE | HEAP_PAGE_TYPE) {
+            continue;
+        }
+        if node_type_checked(node, page_num)? == NodeType::Leaf {
+            f(page_num, node);
+        }
+    }
+    Ok(())
+}
+
+fn table_keys(table: &mut Table) -> Result<KeyIter<'_>, DbError> {
+    Ok(KeyIter {
+        cursor: table_start(table)?,
+        advance_pending: false,
+    })
+}
+
+fn table_rows(table: &mut Table) -> Result<RowIter<'_>, DbError> {
+    Ok(RowIter {
+        cursor: table_start(table)?,
+        advance_pending: false,
+    })
+}
+
+// Load pages into the cache in file order, up to limit pages (all of them
+// when None). Never more than the cache holds, or the last pages loaded
+// would evict the first.
+fn warm_cache(pager: &mut Pager, limit: Option<usize>) -> Result<(), DbError> {
+    let count = pager
+        .num_pages
+        .min(limit.unwrap_or(usize::MAX))
+        .min(pager.max_cached_pages.unwrap_or(usize::MAX));
+    for page_num in 0..count {
+        get_page(pager, page_num)?;
+    }
+    Ok(())
+}
+
+// Read access to a page. Pages loaded this way stay clean and are not
+// written back on flush.
+fn get_page(pager: &mut Pager, page_num: usize) -> Result<&[u8], DbError> {
+    load_page(pager, page_num).map(|page| &*page)
+}
+
+// Write access to a page; marks it dirty so the next flush writes it back
+fn get_page_mut(pager: &mut Pager, page_num: usize) -> Result<&mut [u8], DbError> {
+    load_page(pager, page_num)?;
+    if let Some(transaction) = pager.transaction.as_mut() {
+        if page_num < transaction.num_pages && !transaction.pages.contains_key(&page_num) {
+            let page = pager.pages[page_num].clone().expect("page was just cached");
+            transaction.pages.insert(page_num, page);
+        }
+    }
+    if let Some(savepoint) = pager.savepoint.as_mut() {
+        if page_num < savepoint.num_pages && !savepoint.pages.contains_key(&page_num) {
+            let page = pager.pages[page_num].clone().expect("page was just cached");
+            savepoint.pages.insert(page_num, page);
+            if !pager.dirty.contains(&page_num) {
+                savepoint.newly_dirty.insert(page_num);
+            }
+        }
+    }
+    pager.dirty.insert(page_num);
+    Ok(pager.pages[page_num].as_deref_mut().expect("page was just cached"))
+}
+
+// The bytes of each page that nodes may use, short of the checksum slot
+// when there is one
+fn usable_page_size(pager: &Pager) -> usize {
+    if pager.checksums {
+        pager.page_size - PAGE_CHECKSUM_SIZE
+    } else {
+        pager.page_size
+    }
+}
+
+fn page_offset(pager: &Pager, page_num: usize) -> u64 {
+    pager.data_offset + (page_num * pager.page_size) as u64
+}
+
+fn load_page(pager: &mut Pager, page_num: usize) -> Result<&mut [u8], DbError> {
+    // New pages are only ever allocated at num_pages, so anything further
+    // out came from a bad child pointer
+    if page_num > pager.num_pages {
+        return Err(DbError::PageOutOfBounds { page_num });
+    }
+    if page_num == pager.num_pages {
+        pager.pages.push(None);
+        pager.last_used.push(0);
+        pager.num_pages += 1;
+    }
+    pager.access_clock += 1;
+    pager.last_used[page_num] = pager.access_clock;
+
+    if pager.pages[page_num].is_none() {
+        pager.cache_misses += 1;
+        evict_pages(pager);
+        let page_size = pager.page_size;
+        let mut page = vec![0u8; page_size].into_boxed_slice();
+        let data_length = pager.file_length.saturating_sub(pager.data_offset);
+        let num_pages = (data_length / page_size as u64) as usize;
+        let has_partial_page = !data_length.is_multiple_of(page_size as u64);
+
+        if page_num < num_pages || (page_num == num_pages && has_partial_page) {
+            // Seek to the correct position
+            let offset = page_offset(pager, page_num);
+            let file = pager
+                .file_descriptor
+                .as_mut()
+                .expect("an in-memory database has no pages in a file");
+            if let Err(error) = file.seek(SeekFrom::Start(offset)) {
+                return Err(DbError::SeekFailed { page_num, error });
+            }
+
+            // Calculate how many bytes to read
+            let bytes_to_read = if page_num < num_pages {
+                page_size
+            } else {
+                // This is a partial page
+                (data_length % page_size as u64) as usize
+            };
+
+            // Read only the bytes that exist in the file
+            if let Err(error) = file.read_exact(&mut page[..bytes_to_read]) {
+                return Err(DbError::ReadFailed { page_num, error });
+            }
+            if pager.checksums && !page_checksum_ok(&page) {
+                return Err(DbError::ChecksumMismatch { page_num });
+            }
+        }
+
+        pager.pages[page_num] = Some(page);
+    }
+
+    Ok(pager.pages[page_num].as_deref_mut().expect("page was just cached"))
+}
+
+// Make room for one more page under max_cached_pages by dropping the least
+// recently used clean pages. Dirty pages are pinned: writing one in the
+// middle of a statement would put half of its changes in the file, so they
+// wait for trim_cache once the statement is over, and the cache may run
+// past its cap until then. Evicting is only safe because nothing holds a
+// page across calls: get_page's borrow ends before the next pager call,
+// and cursors keep page numbers, not pages.
+fn evict_pages(pager: &mut Pager) {
+    // An in-memory database would lose evicted pages, so it never evicts
+    let max_cached_pages = match pager.max_cached_pages {
+        Some(max_cached_pages) if pager.file_descriptor.is_some() => max_cached_pages,
+        _ => return,
+    };
+
+    let cached = (0..pager.num_pages).filter(|&page_num| pager.pages[page_num].is_some()).count();
+    if cached < max_cached_pages {
+        return;
+    }
+    let mut clean: Vec<usize> = (0..pager.num_pages)
+        .filter(|&page_num| pager.pages[page_num].is_some() && !pager.dirty.contains(&page_num))
+        .collect();
+    clean.sort_by_key(|&page_num| pager.last_used[page_num]);
+    clean.truncate(cached + 1 - max_cached_pages);
+    for page_num in clean {
+        pager.pages[page_num] = None;
+    }
+}
+
+// Bring a cache that dirty pages have pushed past its cap back under it
+// at the end of a statement: its pages are synced as one batch, so they
+// become clean, and the least recently used are evicted. Inside a
+// transaction nothing can be written, so the pages stay until it commits.
+fn trim_cache(pager: &mut Pager) -> Result<(), DbError> {
+    let Some(max_cached_pages) = pager.max_cached_pages else {
+        return Ok(());
+    };
+    let cached = pager.pages.iter().filter(|page| page.is_some()).count();
+    if cached <= max_cached_pages || pager.file_descriptor.is_none() {
+        return Ok(());
+    }
+    pager_sync(pager)?;
+    evict_pages(pager);
+    Ok(())
+}
+
+// Node capacities can be lowered through the environment (for example
+// DB_LEAF_MAX_CELLS=3) so split behaviour can be exercised with a handful
+// of rows. Values are clamped to [min, max] so a node always fits its page.
+fn node_capacity_from_env(name: &str, min: usize, max: usize, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .map(|value| value.clamp(min, max))
+        .unwrap_or(default)
+}
+
+// Page size and checksums only apply when the file is created
+fn db_open(filename: &str, read_only: bool, page_size: usize, checksums: bool) -> Result<Table, DbError> {
+    let mut pager = if filename == ":memory:" {
+        memory_pager(page_size)
+    } else {
+        pager_open(filename, read_only, page_size, checksums)?
+    };
+    let page_size = usable_page_size(&pager);
+    let root_page_num = 0;
+
+    if pager.num_pages == 0 {
+        // New DB file — initialize page 0 as a leaf node.
+        let root_node = get_page_mut(&mut pager, root_page_num)?;
+        initialize_leaf_node(root_node);
+        set_node_root(root_node, true);
+    }
+
+    Ok(Table {
+        pager: Box::new(pager),
+        root_page_num,
+        rightmost_leaf: None,
+        settings: Settings {
+            temp_dir: env::var_os("DB_TEMP_DIR").map(PathBuf::from),
+            ..Settings::default()
+        },
+        leaf_max_cells: node_capacity_from_env(
+            "DB_LEAF_MAX_CELLS",
+            2,
+            leaf_node_max_cells(page_size),
+            leaf_node_max_cells(page_size),
+        ),
+        internal_max_cells: node_capacity_from_env(
+            "DB_INTERNAL_MAX_CELLS",
+            3,
+            internal_node_max_cells(page_size),
+            internal_node_max_cells(page_size),
+        ),
+        heap_leaf_max_cells: heap_leaf_max_cells(page_size),
+        heap_page_num: 0,
+        statement_cache: HashMap::new(),
+        pack_appends: false,
+        out: Box::new(io::sink()),
+    })
+}
+
+
+// Like SQLite's :memory:, a pager with no file behind it. Pages are
+// allocated zeroed on demand and are gone once the process exits.
+fn memory_pager(page_size: usize) -> Pager {
+    Pager {
+        file_descriptor: None,
+        path: None,
+        file_length: 0,
+        num_pages: 0,
+        page_size,
+        data_offset: 0,
+        pages: Vec::new(),
+        dirty: HashSet::new(),
+        max_cached_pages: None,
+        last_used: Vec::new(),
+        access_clock: 0,
+        cache_misses: 0,
+        logical_bytes_written: 0,
+        physical_bytes_written: 0,
+        free_head: 0,
+        free_count: 0,
+        header_dirty: false,
+        transaction: None,
+        savepoint: None,
+        wal: None,
+        checksums: false,
+        index_roots: [0; Column::ALL.len()],
+        tables: Vec::new(),
+        text_widths: TextWidths::default(),
+    }
+}
+
+fn write_schema(header: &mut [u8], widths: TextWidths) {
+    set_u32_at(header, FILE_HEADER_NUM_COLUMNS_OFFSET, Column::ALL.len() as u32);
+    for (i, &column) in Column::ALL.iter().enumerate() {
+        let offset = FILE_HEADER_COLUMN_WIDTHS_OFFSET + i * size_of::<u32>();
+        set_u32_at(header, offset, widths.width(column) as u32);
+    }
+}
+
+// Rows are laid out by this build's columns. A text column may be declared
+// narrower than its stored width, but a wider one or an id of another size
+// means the file was written by a different layout and would be misread.
+// Returns the declared text widths.
+fn check_schema(header: &[u8]) -> Result<TextWidths, DbError> {
+    let num_columns = get_u32_at(header, FILE_HEADER_NUM_COLUMNS_OFFSET) as usize;
+    if num_columns != Column::ALL.len() {
+        return Err(DbError::SchemaMismatch { column: None });
+    }
+    let mut widths = [0; Column::ALL.len()];
+    for (i, column) in Column::ALL.iter().enumerate() {
+        let offset = FILE_HEADER_COLUMN_WIDTHS_OFFSET + i * size_of::<u32>();
+        widths[i] = get_u32_at(header, offset) as usize;
+        let fits = match column {
+            Column::Id => widths[i] == ID_SIZE,
+            _ => (1..=column.range().len()).contains(&widths[i]),
+        };
+        if !fits {
+            return Err(DbError::SchemaMismatch { column: Some(column.name()) });
+        }
+    }
+    Ok(TextWidths { username: widths[1], email: widths[2] })
+}
+
+/// Whether a page size is a power of two from MIN_PAGE_SIZE to
+/// MAX_PAGE_SIZE. Checked when a new file is created and when a header is
+/// read back.
+pub fn valid_page_size(page_size: usize) -> bool {
+    page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size)
+}
+
+// Files the database creates hold user data, so on unix they are readable
+// and writable by the owner only (0600). This is the crate's one platform
+// split: other targets such as Windows have no mode bits and keep the
+// default permissions.
+#[cfg(unix)]
+fn owner_only(options: &mut OpenOptions) -> &mut OpenOptions {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600)
+}
+
+#[cfg(not(unix))]
+fn owner_only(options: &mut OpenOptions) -> &mut OpenOptions {
+    options
+}
+
+// A new file gets a header page recording the format, page size and
+// whether pages are checksummed. Files without the magic predate the header
+// (format 1); they and format 2 files are upgraded to format 3 first, and
+// format 3 files then have their rows rebuilt in the current format.
+fn pager_open(filename: &str, read_only: bool, page_size: usize, checksums: bool) -> Result<Pager, DbError> {
+    let mut options = OpenOptions::new();
+    if read_only {
+        options.read(true);
+    } else {
+        owner_only(options.read(true).write(true).create(true).truncate(false));
+    }
+
+    let mut file = options.open(filename)?;
+    
+    let mut file_length = file.seek(SeekFrom::End(0))?;
+    let mut header = [0u8; FILE_HEADER_SIZE];
+    let mut version = FORMAT_VERSION;
+    let (page_size, data_offset) = if file_length == 0 && !read_only {
+        let header_page = new_header_page(page_size, checksums);
+        header.copy_from_slice(&header_page[..FILE_HEADER_SIZE]);
+        file.write_all(&header_page)?;
+        file_length = page_size as u64;
+        (page_size, page_size as u64)
+    } else {
+        file.seek(SeekFrom::Start(0))?;
+        let has_header = file_length >= FILE_HEADER_SIZE as u64
+            && file.read_exact(&mut header).is_ok()
+            && &header[..FILE_HEADER_MAGIC.len()] == FILE_HEADER_MAGIC;
+        if has_header {
+            let page_size = get_u32_at(&header, FILE_HEADER_PAGE_SIZE_OFFSET) as usize;
+            version = get_u32_at(&header, FILE_HEADER_VERSION_OFFSET);
+            if !(2..=FORMAT_VERSION).contains(&version) {
+                return Err(DbError::UnsupportedFormat { version });
+            }
+            if !valid_page_size(page_size) {
+                return Err(DbError::InvalidPageSize { page_size });
+            }
+            if version == 2 {
+                // Format 2 headers written before the schema was recorded
+                // have no columns; those files used this build's widths
+                if get_u32_at(&header, FILE_HEADER_NUM_COLUMNS_OFFSET) != 0 {
+                    check_schema(&header)?;
+                }
+                return upgrade_file(file, filename, read_only, page_size, page_size as u64);
+            }
+            (page_size, page_size as u64)
+        } else if file_length > 0 {
+            return upgrade_file(file, filename, read_only, FORMAT_1_PAGE_SIZE, 0);
+        } else {
+            (DEFAULT_PAGE_SIZE, 0)
+        }
+    };
+
+    let text_widths = check_schema(&header)?;
+    let mut free_head = get_u32_at(&header, FILE_HEADER_FREE_HEAD_OFFSET);
+    let mut free_count = get_u32_at(&header, FILE_HEADER_FREE_COUNT_OFFSET);
+    let checksums = get_u32_at(&header, FILE_HEADER_FLAGS_OFFSET) & FLAG_PAGE_CHECKSUMS != 0;
+    let mut index_roots = [0; Column::ALL.len()];
+    for column in INDEXED_COLUMNS {
+        index_roots[column as usize] = get_u32_at(&header, index_root_header_offset(column));
+    }
+    let tables = read_table_directory(&header);
+
+    // Finish any committed writes a crash cut short. A read-only open
+    // cannot write them back, so it sees the file as it was left.
+    let wal = if read_only || data_offset == 0 {
+        None
+    } else {
+        let mut wal = wal_open(filename)?;
+        if let Some(free_list) = wal_replay(&mut wal.file, &mut file, page_size, data_offset)? {
+            (free_head, free_count) = free_list;
+            file_length = file.seek(SeekFrom::End(0))?;
+        }
+        Some(wal)
+    };
+
+    let data_length = file_length - data_offset.min(file_length);
+    if !data_length.is_multiple_of(page_size as u64) {
+        return Err(DbError::FileNotWholePages { file_length });
+    }
+
+    let num_pages = (data_length / page_size as u64) as usize;
+    let num_pages = pages_in_use(&mut file, data_offset, page_size, num_pages)?;
+    let pages = vec![None; num_pages];
+    
+    let pager = Pager {
+        file_descriptor: Some(file),
+        path: Some(filename.to_string()),
+        file_length,
+        num_pages,
+        page_size,
+        data_offset,
+        pages,
+        dirty: HashSet::new(),
+        max_cached_pages: None,
+        last_used: vec![0; num_pages],
+        access_clock: 0,
+        cache_misses: 0,
+        logical_bytes_written: 0,
+        physical_bytes_written: 0,
+        free_head,
+        free_count,
+        header_dirty: false,
+        transaction: None,
+        savepoint: None,
+        wal,
+        checksums,
+        index_roots,
+        tables,
+        text_widths,
+    };
+    if version == 3 {
+        return upgrade_rows(pager, filename, read_only);
+    }
+    Ok(pager)
+}
+
+// The file's pages up to the last one that is not all zeros. A tail kept
+// by .preallocation keep is zeroed space, never a node (every node type
+// has a non-zero header), so it is left out and the pages it holds are
+// handed out again as the table grows instead of the file growing past it.
+fn pages_in_use(
+    file: &mut File,
+    data_offset: u64,
+    page_size: usize,
+    num_pages: usize,
+) -> Result<usize, DbError> {
+    let mut page = vec![0u8; page_size];
+    let mut page_num = num_pages;
+    while page_num > 0 {
+        file.seek(SeekFrom::Start(data_offset + ((page_num - 1) * page_size) as u64))?;
+        file.read_exact(&mut page)?;
+        if page.iter().any(|&b| b != 0) {
+            break;
+        }
+        page_num -= 1;
+    }
+    Ok(page_num)
+}
+
+// The header page of a new file, recording the format, page size, schema
+// and whether pages are checksummed
+fn new_header_page(page_size: usize, checksums: bool) -> Vec<u8> {
+    let mut header_page = vec![0u8; page_size];
+    let header = &mut header_page[..FILE_HEADER_SIZE];
+    header[..FILE_HEADER_MAGIC.len()].copy_from_slice(FILE_HEADER_MAGIC);
+    set_u32_at(header, FILE_HEADER_VERSION_OFFSET, FORMAT_VERSION);
+    set_u32_at(header, FILE_HEADER_PAGE_SIZE_OFFSET, page_size as u32);
+    write_schema(header, TextWidths::default());
+    if checksums {
+        set_u32_at(header, FILE_HEADER_FLAGS_OFFSET, FLAG_PAGE_CHECKSUMS);
+    }
+    header_page
+}
+
+// Rewrite a format 1 or 2 file, whose pages start at data_offset, in
+// format 3, then have upgrade_rows take it the rest of the way. The new
+// file is built as <file>-upgrade and synced before it is renamed over the
+// old one, so a crash leaves one complete file. A read-only open upgrades
+// the pages in memory instead and leaves the file alone.
+fn upgrade_file(
+    mut file: File,
+    filename: &str,
+    read_only: bool,
+    page_size: usize,
+    data_offset: u64,
+) -> Result<Pager, DbError> {
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(data_offset))?;
+    file.read_to_end(&mut data)?;
+    if data.len() % page_size != 0 {
+        return Err(DbError::FileNotWholePages { file_length: data_offset + data.len() as u64 });
+    }
+    upgrade_pages(&mut data, page_size)?;
+
+    if read_only {
+        let mut pager = memory_pager(page_size);
+        pager.pages = data.chunks(page_size).map(|page| Some(page.into())).collect();
+        pager.num_pages = pager.pages.len();
+        pager.last_used = vec![0; pager.num_pages];
+        return upgrade_rows(pager, filename, read_only);
+    }
+
+    let temp_path = format!("{}-upgrade", filename);
+    let mut upgraded = owner_only(OpenOptions::new().write(true).create(true).truncate(true))
+        .open(&temp_path)?;
+    let mut header_page = new_header_page(page_size, false);
+    set_u32_at(&mut header_page, FILE_HEADER_VERSION_OFFSET, 3);
+    upgraded.write_all(&header_page)?;
+    upgraded.write_all(&data)?;
+    upgraded.sync_all()?;
+    drop(upgraded);
+    drop(file);
+    std::fs::rename(&temp_path, filename)?;
+    pager_open(filename, read_only, page_size, false)
+}
+
+// Make room in every leaf for the previous-leaf pointer, which formats 1
+// and 2 did not have, by moving its cells down, then fill the pointers in
+// from the next-leaf chain
+fn upgrade_pages(data: &mut [u8], page_size: usize) -> Result<(), DbError> {
+    let old_header_size = LEAF_NODE_PREV_LEAF_OFFSET;
+    let mut next_leaves = Vec::new();
+    for (page_num, page) in data.chunks_mut(page_size).enumerate() {
+        if page[NODE_TYPE_OFFSET] != NodeType::Leaf as u8 {
+            continue;
+        }
+        let num_cells = leaf_node_num_cells(page) as usize;
+        let cells_end = old_header_size + num_cells * FORMAT_3_CELL_SIZE;
+        if cells_end + LEAF_NODE_PREV_LEAF_SIZE > page_size {
+            return Err(DbError::CorruptNode { page_num, cell_num: num_cells });
+        }
+        page.copy_within(old_header_size..cells_end, LEAF_NODE_HEADER_SIZE);
+        set_leaf_node_prev_leaf(page, NO_LEAF);
+        next_leaves.push((page_num, get_leaf_node_next_leaf(page)));
+    }
+    for (page_num, next_leaf) in next_leaves {
+        if next_leaf == NO_LEAF {
+            continue;
+        }
+        if let Some(next) = data.chunks_mut(page_size).nth(next_leaf as usize) {
+            set_leaf_node_prev_leaf(next, page_num as u32);
+        }
+    }
+    Ok(())
+}
+
+// Rebuild a format 3 table in the current format. Format 4 made every
+// cell a byte longer, so a leaf that was full may no longer fit its page
+// and its cells can't simply be moved down. Instead the rows are read with
+// the old cell size and inserted into a fresh table the way .vacuum does,
+// none of them NULL. The copy is built as <file>-upgrade and closed before
+// it is renamed over the old file; a read-only open builds it in memory.
+fn upgrade_rows(mut old: Pager, filename: &str, read_only: bool) -> Result<Pager, DbError> {
+    let rows = format_3_rows(&mut old)?;
+    let (page_size, checksums) = (old.page_size, old.checksums);
+    let temp_path = if read_only {
+        ":memory:".to_string()
+    } else {
+        format!("{}-upgrade", filename)
+    };
+    if !read_only {
+        remove_vacuum_files(&temp_path)?;
+    }
+
+    let mut rebuilt = db_open(&temp_path, false, page_size, checksums)?;
+    rebuilt.pack_appends = true;
+    set_text_widths(&mut rebuilt.pager, old.text_widths)?;
+    for row in rows {
+        let statement = Statement {
+            statement_type: StatementType::Insert(OnConflict::Abort),
+            row_to_insert: Some(row),
+            predicate: None,
+            table: None,
+            order_by: Column::Id,
+            descending: false,
+            limit: None,
+            warnings: Vec::new(),
+        };
+        execute_insert(&statement, &mut rebuilt)?;
+    }
+    for column in INDEXED_COLUMNS {
+        if index_root(&old, column) != 0 {
+            create_index(&mut rebuilt, column)?;
+        }
+    }
+
+    if read_only {
+        return Ok(*std::mem::replace(&mut rebuilt.pager, Box::new(memory_pager(page_size))));
+    }
+    db_close(rebuilt)?;
+    drop(old);
+    std::fs::rename(&temp_path, filename)?;
+    pager_open(filename, read_only, page_size, checksums)
+}
+
+// Every row of a format 3 table in key order, found by following the leaf
+// chain from the leftmost leaf; internal nodes did not change in format 4
+fn format_3_rows(pager: &mut Pager) -> Result<Vec<Row>, DbError> {
+    if pager.num_pages == 0 {
+        return Ok(Vec::new());
+    }
+    let mut page_num = 0;
+    loop {
+        let node = get_page(pager, page_num)?;
+        if node_type_checked(node, page_num)? == NodeType::Leaf {
+            break;
+        }
+        page_num = internal_node_child(node, 0)? as usize;
+    }
+
+    let usable = usable_page_size(pager);
+    let mut rows = Vec::new();
+    // A chain longer than the file has pages must loop back on itself
+    for _ in 0..pager.num_pages {
+        let node = get_page(pager, page_num)?;
+        for cell_num in 0..leaf_node_num_cells(node) as usize {
+            let offset = LEAF_NODE_HEADER_SIZE + cell_num * FORMAT_3_CELL_SIZE;
+            let value = offset + LEAF_NODE_KEY_SIZE..offset + FORMAT_3_CELL_SIZE;
+            if value.end > usable {
+                return Err(DbError::CorruptNode { page_num, cell_num });
+            }
+            let mut bytes = [0u8; ROW_SIZE];
+            bytes[..FORMAT_3_ROW_SIZE].copy_from_slice(&node[value]);
+            rows.push(Row::deserialize(&bytes));
+        }
+        page_num = get_leaf_node_next_leaf(node) as usize;
+        if page_num == NO_LEAF as usize {
+            return Ok(rows);
+        }
+    }
+    Err(DbError::CorruptNode { page_num, cell_num: 0 })
+}
+
+fn wal_open(filename: &str) -> Result<Wal, DbError> {
+    let path = format!("{}-wal", filename);
+    let file = owner_only(OpenOptions::new().read(true).write(true).create(true).truncate(false))
+        .open(&path)?;
+    Ok(Wal { file, path })
+}
+
+// Write every committed batch in the log into the database file, returning
+// the free list of the last one (None if nothing was committed). Frames
+// after the last commit frame belong to a batch that never finished and
+// are ignored.
+fn wal_replay(wal: &mut File, file: &mut File, page_size: usize, data_offset: u64) -> Result<Option<(u32, u32)>, DbError> {
+    let mut log = Vec::new();
+    wal.seek(SeekFrom::Start(0))?;
+    wal.read_to_end(&mut log)?;
+    if log.len() < WAL_HEADER_SIZE
+        || &log[..WAL_MAGIC.len()] != WAL_MAGIC
+        || get_u32_at(&log, WAL_MAGIC.len()) as usize != page_size
+    {
+        return Ok(None);
+    }
+
+    let mut free_list = None;
+    let mut batch = Vec::new();
+    let mut offset = WAL_HEADER_SIZE;
+    while offset + size_of::<u32>() <= log.len() {
+        let page_num = get_u32_at(&log, offset);
+        offset += size_of::<u32>();
+        if page_num == WAL_COMMIT {
+            if offset + 2 * size_of::<u32>() > log.len() {
+                break;
+            }
+            for (page_num, page) in batch.drain(..) {
+                file.seek(SeekFrom::Start(data_offset + page_num as u64 * page_size as u64))?;
+                file.write_all(page)?;
+            }
+            let fields = &log[offset..offset + 2 * size_of::<u32>()];
+            file.seek(SeekFrom::Start(FILE_HEADER_FREE_HEAD_OFFSET as u64))?;
+            file.write_all(fields)?;
+            free_list = Some((get_u32_at(fields, 0), get_u32_at(fields, size_of::<u32>())));
+            offset += 2 * size_of::<u32>();
+        } else {
+            if offset + page_size > log.len() {
+                break;
+            }
+            batch.push((page_num, &log[offset..offset + page_size]));
+            offset += page_size;
+        }
+    }
+
+    if free_list.is_some() {
+        file.sync_all()?;
+    }
+    wal.set_len(0)?;
+    Ok(free_list)
+}
+
+// Log the given pages and the free list as one committed batch and sync
+// the log, so they can be written to the database file in place
+fn wal_append(pager: &mut Pager, page_nums: &[usize]) -> Result<(), DbError> {
+    let Some(wal) = pager.wal.as_mut() else {
+        return Ok(());
+    };
+    if page_nums.is_empty() && !pager.header_dirty || pager.transaction.is_some() {
+        return Ok(());
+    }
+
+    let mut log = Vec::new();
+    if wal.file.seek(SeekFrom::End(0))? == 0 {
+        log.extend_from_slice(WAL_MAGIC);
+        log.extend_from_slice(&(pager.page_size as u32).to_le_bytes());
+    }
+    for &page_num in page_nums {
+        let page = pager.pages[page_num].as_mut().expect("logged pages are cached");
+        if pager.checksums {
+            set_page_checksum(page);
+        }
+        log.extend_from_slice(&(page_num as u32).to_le_bytes());
+        log.extend_from_slice(page);
+    }
+    log.extend_from_slice(&WAL_COMMIT.to_le_bytes());
+    log.extend_from_slice(&pager.free_head.to_le_bytes());
+    log.extend_from_slice(&pager.free_count.to_le_bytes());
+    wal.file.write_all(&log)?;
+    wal.file.sync_data()?;
+    Ok(())
+}
+
+// Once the database file itself is synced the log is no longer needed
+fn wal_reset(pager: &mut Pager) -> Result<(), DbError> {
+    if let Some(wal) = pager.wal.as_mut() {
+        wal.file.set_len(0)?;
+    }
+    Ok(())
+}
+
+// Cached pages that a flush would write, in page order
+fn dirty_cached_pages(pager: &Pager) -> Vec<usize> {
+    let mut page_nums: Vec<usize> = pager
+        .dirty
+        .iter()
+        .copied()
+        .filter(|&page_num| pager.pages[page_num].is_some())
+        .collect();
+    page_nums.sort_unstable();
+    page_nums
+}
+
+// Takes the table by value so that nothing can touch it after it has been
+// closed; the pages and file handle are dropped on return.
+fn db_close(mut table: Table) -> Result<(), DbError> {
+    let pager = &mut table.pager;
+
+    // Like SQLite, closing with a transaction open abandons it
+    if pager.transaction.is_some() {
+        rollback_transaction(pager)?;
+    }
+
+    let dirty_pages = dirty_cached_pages(pager);
+    wal_append(pager, &dirty_pages)?;
+    for i in 0..pager.num_pages {
+        if pager.pages[i].is_some() {
+            pager_flush(pager, i)?;
+            pager.pages[i] = None; // Drop the page
+        }
+    }
+    write_free_list(pager)?;
+
+    // Drop the preallocated space past the last real page, or with
+    // keep_preallocated only a partial page at the very end, so the file is
+    // always a whole number of pages when it is next opened
+    let page_size = pager.page_size as u64;
+    let used_length = page_offset(pager, pager.num_pages);
+    let logical_length = if table.settings.keep_preallocated {
+        let data_length = pager.file_length.saturating_sub(pager.data_offset);
+        let whole_pages = pager.data_offset + data_length - data_length % page_size;
+        whole_pages.max(used_length)
+    } else {
+        used_length
+    };
+    if let Some(file) = pager.file_descriptor.as_mut() {
+        if pager.file_length > logical_length {
+            file.set_len(logical_length)?;
+            pager.file_length = logical_length;
+        }
+
+        // Flush and close the file
+        file.sync_all()?;
+    }
+    // Everything logged is now in the file
+    if let Some(wal) = pager.wal.take() {
+        drop(wal.file);
+        std::fs::remove_file(&wal.path)?;
+    }
+
+    Ok(())
+}
+
+// The free-list fields sit in the header page, which the pager does not
+// cache, so they are written to the file directly when they have changed
+// The format version recorded in the open file's header: None for an
+// in-memory database, and 1 for a format 1 file opened read-only, which
+// has no header
+fn file_format_version(pager: &mut Pager) -> Result<Option<u32>, DbError> {
+    let Some(file) = pager.file_descriptor.as_mut() else {
+        return Ok(None);
+    };
+    if pager.data_offset == 0 {
+        return Ok(Some(1));
+    }
+    let mut version = [0u8; size_of::<u32>()];
+    file.seek(SeekFrom::Start(FILE_HEADER_VERSION_OFFSET as u64))?;
+    file.read_exact(&mut version)?;
+    Ok(Some(get_u32_at(&version, 0)))
+}
+
+fn write_free_list(pager: &mut Pager) -> Result<(), DbError> {
+    if !pager.header_dirty || pager.data_offset == 0 || pager.transaction.is_some() {
+        return Ok(());
+    }
+    let Some(file) = pager.file_descriptor.as_mut() else {
+        return Ok(());
+    };
+
+    let mut fields = [0u8; 2 * size_of::<u32>()];
+    set_u32_at(&mut fields, 0, pager.free_head);
+    set_u32_at(&mut fields, size_of::<u32>(), pager.free_count);
+    file.seek(SeekFrom::Start(FILE_HEADER_FREE_HEAD_OFFSET as u64))?;
+    file.write_all(&fields)?;
+    pager.header_dirty = false;
+    Ok(())
+}
+
+// Write out everything changed so far, so the file matches memory when
+// the transaction starts and only the transaction's changes are held back
+fn begin_transaction(pager: &mut Pager) -> Result<(), DbError> {
+    if pager.transaction.is_some() {
+        return Err(DbError::TransactionOpen);
+    }
+    pager_sync(pager)?;
+    pager.transaction = Some(Transaction {
+        pages: HashMap::new(),
+        num_pages: pager.num_pages,
+        dirty: pager.dirty.clone(),
+        free_head: pager.free_head,
+        free_count: pager.free_count,
+        header_dirty: pager.header_dirty,
+    });
+    Ok(())
+}
+
+fn commit_transaction(pager: &mut Pager) -> Result<(), DbError> {
+    if pager.transaction.take().is_none() {
+        return Err(DbError::NoTransaction);
+    }
+    pager_sync(pager)
+}
+
+// Nothing was written during the transaction, so restoring the copied
+// pages and dropping the new ones leaves memory as it was at .begin
+fn rollback_transaction(pager: &mut Pager) -> Result<(), DbError> {
+    let Some(transaction) = pager.transaction.take() else {
+        return Err(DbError::NoTransaction);
+    };
+    for (page_num, page) in transaction.pages {
+        pager.pages[page_num] = Some(page);
+    }
+    pager.pages.truncate(transaction.num_pages);
+    pager.last_used.truncate(transaction.num_pages);
+    pager.num_pages = transaction.num_pages;
+    pager.dirty = transaction.dirty;
+    pager.free_head = transaction.free_head;
+    pager.free_count = transaction.free_count;
+    pager.header_dirty = transaction.header_dirty;
+    restart_savepoint(pager);
+    Ok(())
+}
+
+fn savepoint(pager: &Pager) -> Savepoint {
+    Savepoint {
+        pages: HashMap::new(),
+        newly_dirty: HashSet::new(),
+        num_pages: pager.num_pages,
+        free_head: pager.free_head,
+        free_count: pager.free_count,
+        header_dirty: pager.header_dirty,
+    }
+}
+
+// Once memory has been synced or rolled back, the running statement's
+// earlier copies no longer describe what a panic should go back to
+fn restart_savepoint(pager: &mut Pager) {
+    if pager.savepoint.is_some() {
+        pager.savepoint = Some(savepoint(pager));
+    }
+}
+
+// Run one statement or meta command with a savepoint, and trim the cache
+// once it is done. Nested calls, such as a meta command that runs
+// statements, share the outermost one.
+fn in_statement<T>(
+    table: &mut Table,
+    f: impl FnOnce(&mut Table) -> Result<T, DbError>,
+) -> Result<T, DbError> {
+    if table.pager.savepoint.is_some() {
+        return f(table);
+    }
+    table.pager.savepoint = Some(savepoint(&table.pager));
+    let result = f(table);
+    table.pager.savepoint = None;
+    let value = result?;
+    trim_cache(&mut table.pager)?;
+    Ok(value)
+}
+
+// Put back the pages a statement changed, drop the pages it allocated and
+// mark clean again the pages it was the first to dirty
+fn undo_statement(pager: &mut Pager, savepoint: Savepoint) {
+    for (page_num, page) in savepoint.pages {
+        pager.pages[page_num] = Some(page);
+    }
+    pager.pages.truncate(savepoint.num_pages);
+    pager.last_used.truncate(savepoint.num_pages);
+    pager.num_pages = savepoint.num_pages;
+    let num_pages = savepoint.num_pages;
+    let newly_dirty = savepoint.newly_dirty;
+    pager.dirty.retain(|page_num| *page_num < num_pages && !newly_dirty.contains(page_num));
+    pager.free_head = savepoint.free_head;
+    pager.free_count = savepoint.free_count;
+    pager.header_dirty = savepoint.header_dirty;
+}
+
+// Durability checkpoint for long sessions: write every dirty page back and
+// sync the file, but keep the pages cached and the table open
+fn pager_sync(pager: &mut Pager) -> Result<(), DbError> {
+    // Held back until commit, like the page writes below
+    if pager.transaction.is_some() {
+        return Ok(());
+    }
+    let dirty_pages = dirty_cached_pages(pager);
+    wal_append(pager, &dirty_pages)?;
+    for page_num in 0..pager.num_pages {
+        if pager.pages[page_num].is_some() {
+            pager_flush(pager, page_num)?;
+        }
+    }
+    write_free_list(pager)?;
+    if let Some(file) = pager.file_descriptor.as_mut() {
+        file.sync_all()?;
+    }
+    restart_savepoint(pager);
+    wal_reset(pager)
+}
+
+fn pager_flush(pager: &mut Pager, page_num: usize) -> Result<(), DbError> {
+    if pager.pages[page_num].is_none() {
+        return Err(DbError::PageNotCached { page_num });
+    }
+    // Pages that were only read already match the file, and changes made
+    // inside a transaction stay in memory until it commits
+    if !pager.dirty.contains(&page_num) || pager.transaction.is_some() {
+        return Ok(());
+    }
+
+    let offset = page_offset(pager, page_num);
+    let page_end = page_offset(pager, page_num + 1);
+    let Some(file) = pager.file_descriptor.as_mut() else {
+        // In-memory pages have nowhere to go
+        return Ok(());
+    };
+
+    // Grow the file by a whole chunk when this page lies past its end
+    if page_end > pager.file_length {
+        let chunk = (FILE_GROWTH_CHUNK_PAGES * pager.page_size) as u64;
+        let new_length = pager.data_offset + (page_end - pager.data_offset).div_ceil(chunk) * chunk;
+        file.set_len(new_length)?;
+        pager.file_length = new_length;
+    }
+
+    // Seek to the correct position
+    file.seek(SeekFrom::Start(offset))?;
+
+    // Write the page data
+    let page_data = pager.pages[page_num].as_mut().unwrap();
+    if pager.checksums {
+        set_page_checksum(page_data);
+    }
+
+    file.write_all(page_data)?;
+    pager.physical_bytes_written += pager.page_size as u64;
+    pager.dirty.remove(&page_num);
+    Ok(())
+}
+
+
+/// Everything that can go wrong opening, reading or changing a database
+#[derive(Debug)]
+pub enum DbError {
+    /// A cell offset computed from the node header runs past the page end
+    CorruptNode { page_num: usize, cell_num: usize },
+    /// A cell's row value is cut short by the end of the page
+    CorruptCell { page_num: usize, cell_num: usize },
+    /// The page number is past what the pager can cache
+    PageOutOfBounds { page_num: usize },
+    /// A page was to be written back but is not in the cache
+    PageNotCached { page_num: usize },
+    /// An internal node has no child with this number, or its pointer is unset
+    InvalidChild { child_num: usize, num_keys: usize },
+    /// A leaf that should hold at least one key holds none
+    EmptyNode { page_num: usize },
+    /// Seeking to a page in the file failed
+    SeekFailed { page_num: usize, error: io::Error },
+    /// Reading a page from the file failed
+    ReadFailed { page_num: usize, error: io::Error },
+    /// A page's type byte is neither internal nor leaf
+    NodeTypeInvalid { page_num: usize, node_type: u8 },
+    /// The file length is not a whole number of pages
+    FileNotWholePages { file_length: u64 },
+    /// A text column holds bytes that are not valid UTF-8
+    InvalidUtf8 { page_num: usize, cell_num: usize, column: &'static str },
+    /// The file is in an on-disk format this build cannot read or upgrade
+    UnsupportedFormat { version: u32 },
+    /// The header records a page size this build cannot use
+    InvalidPageSize { page_size: usize },
+    /// The header records a different column count (None) or a different
+    /// width for the named column
+    SchemaMismatch { column: Option<&'static str> },
+    /// A statement given to Database::execute that could not be prepared,
+    /// with the reason
+    InvalidStatement(String),
+    /// An insert's id is already in the table
+    DuplicateKey,
+    /// A leaf is full and the table is bounded, so it may not split
+    TableFull,
+    /// The id a statement names is not in the table
+    KeyNotFound,
+    /// A select scanned more rows than pragma max_scan allows
+    ScanLimitExceeded,
+    /// .createindex when the column's index already exists
+    IndexExists { column: &'static str },
+    /// create table on a table that already has rows
+    TableNotEmpty,
+    /// create table <name> when a table already has that name
+    TableExists { name: String },
+    /// create table <name> when the directory has no room for another
+    TooManyTables,
+    /// insert into or select from a name that is not in the directory
+    NoSuchTable { name: String },
+    /// A page read from the file does not match its checksum
+    ChecksumMismatch { page_num: usize },
+    /// .begin while a transaction is open
+    TransactionOpen,
+    /// .commit or .rollback without an open transaction
+    NoTransaction,
+    /// Any other failure of the underlying file
+    Io(io::Error),
+}
+
+impl From<io::Error> for DbError {
+    fn from(error: io::Error) -> Self {
+        DbError::Io(error)
+    }
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbError::CorruptNode { page_num, cell_num } => write!(
+                f,
+                "Corrupt node on page {}: cell {} lies outside the page",
+                page_num, cell_num
+            ),
+            DbError::CorruptCell { page_num, cell_num } => write!(
+                f,
+                "Corrupt cell {} on page {}: row runs past the end of the page",
+                cell_num, page_num
+            ),
+            DbError::PageNotCached { page_num } => {
+                write!(f, "Page {} is not in the cache", page_num)
+            }
+            DbError::EmptyNode { page_num } => write!(f, "Page {} has no keys", page_num),
+            DbError::InvalidChild { child_num, num_keys } => write!(
+                f,
+                "Internal node with {} keys has no valid child {}",
+                num_keys, child_num
+            ),
+            DbError::PageOutOfBounds { page_num } => write!(
+                f,
+                "Page {} is past the end of the database",
+                page_num
+            ),
+            DbError::SeekFailed { page_num, error } => {
+                write!(f, "Seek to page {} failed: {}", page_num, error)
+            }
+            DbError::ReadFailed { page_num, error } => {
+                write!(f, "Read of page {} failed: {}", page_num, error)
+            }
+            DbError::NodeTypeInvalid { page_num, node_type } => {
+                write!(f, "Page {} has unknown node type {}", page_num, node_type)
+            }
+            DbError::FileNotWholePages { file_length } => write!(
+                f,
+                "Db file is not a whole number of pages ({} bytes). Corrupt file",
+                file_length
+            ),
+            DbError::ChecksumMismatch { page_num } => write!(f, "Checksum mismatch on page {}", page_num),
+            DbError::InvalidPageSize { page_size } => write!(
+                f,
+                "Invalid page size {} (must be a power of two from {} to {})",
+                page_size, MIN_PAGE_SIZE, MAX_PAGE_SIZE
+            ),
+            DbError::InvalidUtf8 { page_num, cell_num, column } => write!(
+                f,
+                "Invalid UTF-8 in {} of cell {} on page {}",
+                column, cell_num, page_num
+            ),
+            DbError::UnsupportedFormat { version } => write!(
+                f,
+                "File format {} is not supported (this build reads format {})",
+                version, FORMAT_VERSION
+            ),
+            DbError::SchemaMismatch { column: None } => {
+                write!(f, "File has a different number of columns")
+            }
+            DbError::SchemaMismatch { column: Some(column) } => {
+                write!(f, "File has a different width for column {}", column)
+            }
+            DbError::TransactionOpen => write!(f, "A transaction is already open"),
+            DbError::IndexExists { column } => write!(f, "The {} index already exists", column),
+            DbError::TableNotEmpty => write!(f, "The table already has rows"),
+            DbError::TableExists { name } => write!(f, "Table {} already exists", name),
+            DbError::TooManyTables => write!(f, "A file holds at most {} named tables", MAX_TABLES),
+            DbError::NoSuchTable { name } => write!(f, "No such table: {}", name),
+            DbError::NoTransaction => write!(f, "No transaction is open"),
+            DbError::InvalidStatement(reason) => write!(f, "Invalid statement: {}", reason),
+            DbError::DuplicateKey => write!(f, "Duplicate key"),
+            DbError::TableFull => write!(f, "Table full"),
+            DbError::KeyNotFound => write!(f, "Key not found"),
+            DbError::ScanLimitExceeded => write!(f, "Scan limit exceeded"),
+            DbError::Io(error) => write!(f, "I/O error: {}", error),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum MetaCommandResult {
+    Success,
+    Exit,
+    UnrecognizedCommand,
+}
+
+enum PrepareResult {
+    Success(Box<Statement>),
+    StringTooLong,
+    SyntaxError,
+    NegativeId,
+    IdOutOfRange,
+    InvalidHex,
+    // A ? placeholder with no value bound, by number
+    UnboundParameter(usize),
+    // Everything wrong with a create table's columns, not just the first
+    InvalidSchema(Vec<String>),
+    UnrecognizedStatement,
+}
+
+enum ExecuteResult {
+    Success,
+    TableFull,
+    DuplicateKey,
+    ScanLimitExceeded,
+    NotFound,
+    // The result of select count, which the caller prints or returns
+    Count(usize),
+    // Whether select exists found its key
+    Exists(bool),
+}
+
+#[derive(Debug, Clone)]
+enum StatementType {
+    Select,
+    Insert(OnConflict),
+    Count,
+    Exists,
+    // pragma max_scan = <n>; 0 turns the limit off
+    SetMaxScan(usize),
+    // pragma cache_size = <n>; 0 lets the cache grow without bound
+    SetCacheSize(usize),
+    CountDistinct(Column),
+    Delete,
+    Update,
+    // create table (id int, username text(<n>), email text(<n>))
+    CreateTable(TextWidths),
+    // create table <name> [heap], true when heap-organized
+    CreateNamedTable(String, bool),
+}
+
+// What an insert does when its id is already in the table: a bare insert
+// fails with DuplicateKey, 'insert or replace' overwrites the row and
+// 'insert or ignore' leaves it alone
+#[derive(Debug, Clone, Copy)]
+enum OnConflict {
+    Abort,
+    Replace,
+    Ignore,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Column {
+    Id,
+    Username,
+    Email,
+}
+
+// Column descriptors: rows are serialized, read back and recorded in the
+// file header through these rather than through the raw offsets
+impl Column {
+    const ALL: [Column; 3] = [Column::Id, Column::Username, Column::Email];
+
+    fn name(self) -> &'static str {
+        match self {
+            Column::Id => "id",
+            Column::Username => "username",
+            Column::Email => "email",
+        }
+    }
+
+    // The type as .schema shows it; text columns are fixed-width
+    fn type_name(self, widths: TextWidths) -> String {
+        match self {
+            Column::Id => "u32".to_string(),
+            Column::Username | Column::Email => format!("char({})", widths.width(self)),
+        }
+    }
+
+    // The column's bit in the row flags, set when it holds NULL
+    fn null_flag(self) -> u8 {
+        1 << self as u8
+    }
+
+    // Bytes of the column within a serialized row
+    fn range(self) -> Range<usize> {
+        match self {
+            Column::Id => ID_OFFSET..ID_OFFSET + ID_SIZE,
+            Column::Username => USERNAME_OFFSET..USERNAME_OFFSET + USERNAME_SIZE,
+            Column::Email => EMAIL_OFFSET..EMAIL_OFFSET + EMAIL_SIZE,
+        }
+    }
+}
+
+// How many bytes of each text column a row may use, as declared by create
+// table and recorded in the file header. Rows are still stored at the full
+// COLUMN_*_SIZE widths, so these can only narrow a column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TextWidths {
+    username: usize,
+    email: usize,
+}
+
+impl Default for TextWidths {
+    fn default() -> Self {
+        Self { username: COLUMN_USERNAME_SIZE, email: COLUMN_EMAIL_SIZE }
+    }
+}
+
+impl TextWidths {
+    fn width(self, column: Column) -> usize {
+        match column {
+            Column::Id => ID_SIZE,
+            Column::Username => self.username,
+            Column::Email => self.email,
+        }
+    }
+
+    // Whether both of the row's values fit their declared widths
+    fn fits(self, row: &Row) -> bool {
+        trim_nulls(&row.username).len() <= self.username
+            && trim_nulls(&row.email).len() <= self.email
+    }
+}
+
+/// One row of the table. The text columns are stored at their full width
+/// and padded with NUL bytes; get_username and get_email trim the padding.
+/// A NULL text column is stored as all padding, so its getter returns an
+/// empty string; username_is_null and email_is_null tell the two apart.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct Row {
+    /// The row's key
+    pub id: u32,
+    pub username: [u8; COLUMN_USERNAME_SIZE],
+    pub email: [u8; COLUMN_EMAIL_SIZE],
+    // Column::null_flag bits of the columns holding NULL
+    flags: u8,
+}
+
+impl Row {
+    fn serialize_row(&self, destination: &mut [u8]) {
+        assert!(destination.len() >= ROW_SIZE, "Destination buffer too small");
+
+        //serialze the Id
+        destination[Column::Id.range()].copy_from_slice(&self.id.to_le_bytes());
+
+        //serilaize the username
+        destination[Column::Username.range()].copy_from_slice(&self.username);
+
+        //serialize the email
+        destination[Column::Email.range()].copy_from_slice(&self.email);
+
+        destination[ROW_FLAGS_OFFSET] = self.flags;
+    }
+
+    fn deserialize(source: &[u8]) -> Self {
+        assert!(source.len() >= ROW_SIZE, "Source buffer too small");
+
+        let mut id_bytes = [0u8; 4];
+        id_bytes.copy_from_slice(&source[Column::Id.range()]);
+        let id = u32::from_le_bytes(id_bytes);
+
+        let mut username = [0u8; USERNAME_SIZE];
+        username.copy_from_slice(&source[Column::Username.range()]);
+
+        let mut email = [0u8; EMAIL_SIZE];
+        email.copy_from_slice(&source[Column::Email.range()]);
+
+        Self { id, username, email, flags: source[ROW_FLAGS_OFFSET] }
+    }
+
+    fn is_null(&self, column: Column) -> bool {
+        self.flags & column.null_flag() != 0
+    }
+
+    // A text column's bytes up to the first null; the id has none
+    fn text_bytes(&self, column: Column) -> &[u8] {
+        match column {
+            Column::Id => &[],
+            Column::Username => trim_nulls(&self.username),
+            Column::Email => trim_nulls(&self.email),
+        }
+    }
+
+    // Each indexed column's hash, as row_index_hashes gives for the
+    // serialized row
+    fn index_hashes(&self) -> [u32; INDEXED_COLUMNS.len()] {
+        INDEXED_COLUMNS.map(|column| index_hash(self.text_bytes(column)))
+    }
+
+    /// Whether the username is NULL rather than a string, possibly empty
+    pub fn username_is_null(&self) -> bool {
+        self.is_null(Column::Username)
+    }
+
+    /// Whether the email is NULL rather than a string, possibly empty
+    pub fn email_is_null(&self) -> bool {
+        self.is_null(Column::Email)
+    }
+
+    /// The username up to its NUL padding, with invalid UTF-8 replaced
+    pub fn get_username(&self) -> String {
+        // Find the first null byte or use the entire array
+        let end = self.username.iter().position(|&x| x == 0).unwrap_or(self.username.len());
+        String::from_utf8_lossy(&self.username[..end]).to_string()
+    }
+    
+    /// The email up to its NUL padding, with invalid UTF-8 replaced
+    pub fn get_email(&self) -> String {
+        // Find the first null byte or use the entire array
+        let end = self.email.iter().position(|&x| x == 0).unwrap_or(self.email.len());
+        String::from_utf8_lossy(&self.email[..end]).to_string()
+    }
+}
+
+fn serialize_row(row: &Row, destination: &mut [u8]) {
+    row.serialize_row(destination);
+}
+
+// Borrowed view of a serialized row that decodes nothing up front. Each
+// accessor reads only its own column, so filtering on id never touches the
+// text columns.
+struct RowRef<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> RowRef<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        assert!(bytes.len() >= ROW_SIZE, "Source buffer too small");
+        Self { bytes }
+    }
+
+    fn id(&self) -> u32 {
+        get_u32_at(self.bytes, Column::Id.range().start)
+    }
+
+    // Column bytes up to the first null, not UTF-8 decoded
+    fn username_bytes(&self) -> &'a [u8] {
+        trim_nulls(&self.bytes[Column::Username.range()])
+    }
+
+    fn email_bytes(&self) -> &'a [u8] {
+        trim_nulls(&self.bytes[Column::Email.range()])
+    }
+
+    fn text_bytes(&self, column: Column) -> &'a [u8] {
+        trim_nulls(&self.bytes[column.range()])
+    }
+
+    fn is_null(&self, column: Column) -> bool {
+        self.bytes[ROW_FLAGS_OFFSET] & column.null_flag() != 0
+    }
+
+    fn is_tombstone(&self) -> bool {
+        is_tombstone(self.bytes)
+    }
+}
+
+// Input arrives as a Rust String so rows written by this program are always
+// valid UTF-8; this catches files produced by other tools or corrupted
+fn check_row_utf8(row: &RowRef, page_num: usize, cell_num: usize) -> Result<(), DbError> {
+    let columns = [
+        (Column::Username, row.username_bytes()),
+        (Column::Email, row.email_bytes()),
+    ];
+    for (column, bytes) in columns {
+        if std::str::from_utf8(bytes).is_err() {
+            return Err(DbError::InvalidUtf8 {
+                page_num,
+                cell_num,
+                column: column.name(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn trim_nulls(column: &[u8]) -> &[u8] {
+    let end = column.iter().position(|&x| x == 0).unwrap_or(column.len());
+    &column[..end]
+}
+
+// Whether a serialized row was soft-deleted
+fn is_tombstone(value: &[u8]) -> bool {
+    value[ROW_FLAGS_OFFSET] & ROW_TOMBSTONE != 0
+}
+
+#[derive(Clone)]
+struct Statement {
+    statement_type: StatementType,
+    row_to_insert: Option<Row>,
+    predicate: Option<Predicate>,
+    // insert into <name> / select from <name>: the named table to run on,
+    // None for the main table
+    table: Option<String>,
+    // select ... order by <column>: Column::Id, the key order, unless the
+    // statement names username
+    order_by: Column,
+    // select ... order by <column> desc
+    descending: bool,
+    // select ... limit <n>: most rows to print
+    limit: Option<usize>,
+    // Values cut to their column width under .truncate, for run_input to
+    // report before running the statement
+    warnings: Vec<String>,
+}
+
+// A WHERE condition on a single column
+#[derive(Debug, Clone)]
+enum Predicate {
+    IdEquals(u32),
+    IdBetween(u32, u32),
+    UsernameEquals(String),
+    EmailEquals(String),
+    // email like '<prefix>%'
+    EmailStartsWith(String),
+}
+
+impl Predicate {
+    // Inclusive key range for predicates that can be answered by a bounded
+    // scan from table_find
+    fn id_bounds(&self) -> Option<(u32, u32)> {
+        match self {
+            Predicate::IdEquals(id) => Some((*id, *id)),
+            Predicate::IdBetween(lo, hi) => Some((*lo, *hi)),
+            _ => None,
+        }
+    }
+
+    // Only the filtered column is read from the row. As in SQL, a NULL
+    // value matches no comparison, not even with an empty string.
+    fn matches(&self, row: &RowRef, settings: &Settings) -> bool {
+        match self {
+            Predicate::IdEquals(id) => row.id() == *id,
+            Predicate::IdBetween(lo, hi) => (*lo..=*hi).contains(&row.id()),
+            Predicate::UsernameEquals(username) => {
+                !row.is_null(Column::Username)
+                    && text_equals(row.username_bytes(), username, settings.username_nocase)
+            }
+            Predicate::EmailEquals(email) => {
+                !row.is_null(Column::Email)
+                    && text_equals(row.email_bytes(), email, settings.email_nocase)
+            }
+            Predicate::EmailStartsWith(prefix) => {
+                let email = row.email_bytes();
+                !row.is_null(Column::Email)
+                    && email.len() >= prefix.len()
+                    && text_equals(&email[..prefix.len()], prefix, settings.email_nocase)
+            }
+        }
+    }
+}
+
+// Byte equality for text columns, optionally folding ASCII case only
+fn text_equals(column: &[u8], value: &str, nocase: bool) -> bool {
+    if nocase {
+        column.eq_ignore_ascii_case(value.as_bytes())
+    } else {
+        column == value.as_bytes()
+    }
+}
+
+fn parse_predicate(clause: &str) -> Option<Predicate> {
+    if let Ok((lo, hi)) = scan_fmt!(clause, "id between {} and {}", u32, u32) {
+        return Some(Predicate::IdBetween(lo, hi));
+    }
+    if let Ok(id) = scan_fmt!(clause, "id = {}", u32) {
+        return Some(Predicate::IdEquals(id));
+    }
+    // Only prefix matching, '<prefix>%', is supported so far. Any other
+    // pattern, including one using _ or no wildcard at all, is a syntax
+    // error rather than a match that quietly means something else.
+    if let Some(pattern) = clause.strip_prefix("email like ") {
+        let pattern = pattern.trim().strip_prefix('\'')?.strip_suffix('\'')?;
+        let prefix = pattern.strip_suffix('%')?;
+        if prefix.contains(['%', '_']) {
+            return None;
+        }
+        return Some(Predicate::EmailStartsWith(prefix.to_string()));
+    }
+
+    let (column, value) = clause.split_once('=')?;
+    let value = value.trim();
+    let value = value
+        .strip_prefix('\'')
+        .and_then(|v| v.strip_suffix('\''))
+        .unwrap_or(value)
+        .to_string();
+
+    match column.trim() {
+        "username" => Some(Predicate::UsernameEquals(value)),
+        "email" => Some(Predicate::EmailEquals(value)),
+        _ => None,
+    }
+}
+
+// Helper function to indent output based on depth
+fn indent(out: &mut dyn Write, level: usize) {
+    for _ in 0..level {
+        out!(out, "  "); // Two spaces per indent level
+    }
+}
+
+// Recursive function to print the B-tree starting from any page
+fn print_tree(
+    pager: &mut Pager,
+    out: &mut dyn Write,
+    page_num: usize,
+    indentation_level: usize,
+) -> Result<(), DbError> {
+    // First, collect all the data we need from the node
+    let (node_type, num_keys, keys, children, right_child) = {
+        let node = get_page(pager, page_num)?;
+        let node_type = node_type_checked(node, page_num)?;
+        
+        match node_type {
+            NodeType::Leaf => {
+                let num_keys = leaf_node_num_cells(node);
+                let mut keys = Vec::new();
+                for i in 0..num_keys {
+                    keys.push(leaf_node_key(node, i as usize));
+                }
+                (node_type, num_keys, keys, Vec::new(), 0)
+            }
+            NodeType::Internal => {
+                let num_keys = internal_node_num_keys(node);
+                let mut children = Vec::new();
+                let mut keys = Vec::new();
+                
+                for i in 0..num_keys {
+                    children.push(internal_node_child(node, i as usize)?);
+                    keys.push(internal_node_key(node, i as usize));
+                }
+                let right_child = internal_node_right_child(node);
+                
+                (node_type, num_keys, keys, children, right_child)
+            }
+        }
+    };
+
+    // Now process the data without holding any borrows
+    match node_type {
+        NodeType::Leaf => {
+            indent(out, indentation_level);
+            outln!(out, "- leaf (size {})", num_keys);
+
+            for key in keys {
+                indent(out, indentation_level + 1);
+                outln!(out, "- {}", key);
+            }
+        }
+
+        NodeType::Internal => {
+            indent(out, indentation_level);
+            outln!(out, "- internal (size {})", num_keys);
+
+            // Process children and keys
+            for i in 0..num_keys as usize {
+                let child = children[i];
+                
+                if child == INVALID_PAGE_NUM {
+                    indent(out, indentation_level + 1);
+                    outln!(out, "- <empty child>");
+                    continue;
+                }
+
+                print_tree(pager, out, child as usize, indentation_level + 1)?;
+                indent(out, indentation_level + 1);
+                outln!(out, "- key {}", keys[i]);
+            }
+
+            // Handle right child
+            if right_child != INVALID_PAGE_NUM {
+                print_tree(pager, out, right_child as usize, indentation_level + 1)?;
+            } else {
+                indent(out, indentation_level + 1);
+                outln!(out, "- <empty right child>");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Print the tree as a Graphviz digraph, renderable with `dot -Tpng`.
+// Internal nodes are shaded, child edges are solid and next-leaf links
+// are dashed.
+fn print_tree_dot(
+    pager: &mut Pager,
+    out: &mut dyn Write,
+    root_page_num: usize,
+) -> Result<(), DbError> {
+    outln!(out, "digraph btree {{");
+    outln!(out, "  node [shape=record];");
+
+    let mut visited = HashSet::new();
+    print_node_dot(pager, out, root_page_num, &mut visited)?;
+
+    outln!(out, "}}");
+    Ok(())
+}
+
+fn print_node_dot(
+    pager: &mut Pager,
+    out: &mut dyn Write,
+    page_num: usize,
+    visited: &mut HashSet<usize>,
+) -> Result<(), DbError> {
+    // Don't loop forever if a damaged tree points back at a visited page
+    if !visited.insert(page_num) {
+        return Ok(());
+    }
+
+    let (node_type, keys, children, next_leaf) = {
+        let node = get_page(pager, page_num)?;
+        let node_type = node_type_checked(node, page_num)?;
+
+        match node_type {
+            NodeType::Leaf => {
+                let keys: Vec<u32> = (0..leaf_node_num_cells(node) as usize)
+                    .map(|i| leaf_node_key(node, i))
+                    .collect();
+                (node_type, keys, Vec::new(), get_leaf_node_next_leaf(node))
+            }
+            NodeType::Internal => {
+                let num_keys = internal_node_num_keys(node) as usize;
+                let keys: Vec<u32> = (0..num_keys).map(|i| internal_node_key(node, i)).collect();
+                let mut children =
+                    (0..num_keys).map(|i| internal_node_child(node, i)).collect::<Result<Vec<_>, _>>()?;
+                children.push(internal_node_right_child(node));
+                (node_type, keys, children, 0)
+            }
+        }
+    };
+
+    let mut label = match node_type {
+        NodeType::Leaf => format!("leaf {}", page_num),
+        NodeType::Internal => format!("internal {}", page_num),
+    };
+    for key in &keys {
+        label.push_str(&format!("|{}", key));
+    }
+
+    match node_type {
+        NodeType::Leaf => {
+            outln!(out, "  page{} [label=\"{}\"];", page_num, label);
+            if next_leaf != NO_LEAF {
+                outln!(out, "  page{} -> page{} [style=dashed];", page_num, next_leaf);
+            }
+        }
+        NodeType::Internal => {
+            outln!(
+                out,
+                "  page{} [label=\"{}\", style=filled, fillcolor=lightgrey];",
+                page_num, label
+            );
+            for child in children {
+                if child == INVALID_PAGE_NUM {
+                    continue;
+                }
+                outln!(out, "  page{} -> page{};", page_num, child);
+                print_node_dot(pager, out, child as usize, visited)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Space and capacity depend on the open file's page size
+fn print_constants(out: &mut dyn Write, page_size: usize) {
+    outln!(out, "ROW_SIZE: {}", ROW_SIZE);
+    outln!(out, "COMMON_NODE_HEADER_SIZE: {}", COMMON_NODE_HEADER_SIZE);
+    outln!(out, "LEAF_NODE_HEADER_SIZE: {}", LEAF_NODE_HEADER_SIZE);
+    outln!(out, "LEAF_NODE_CELL_SIZE: {}", LEAF_NODE_CELL_SIZE);
+    outln!(out, "LEAF_NODE_SPACE_FOR_CELLS: {}", page_size - LEAF_NODE_HEADER_SIZE);
+    outln!(out, "LEAF_NODE_MAX_CELLS: {}", leaf_node_max_cells(page_size));
+}
+
+// The table layout, read from the column descriptors the file header is
+// written and checked against
+fn print_schema(table: &mut Table) {
+    for column in Column::ALL {
+        outln!(table.out, "{} {}", column.name(), column.type_name(table.pager.text_widths));
+    }
+    let row_size: usize =
+        Column::ALL.iter().map(|column| column.range().len()).sum::<usize>() + ROW_FLAGS_SIZE;
+    outln!(table.out, "ROW_SIZE: {}", row_size);
+    outln!(table.out, "LEAF_NODE_MAX_CELLS: {}", table.leaf_max_cells);
+}
+
+fn do_meta_command(input: &InputBuffer, table: &mut Table) -> MetaCommandResult {
+    match input.buffer.trim() {
+        ".exit" => MetaCommandResult::Exit,
+        ".btree" => {
+            outln!(table.out, "Tree:");
+            if let Err(e) = print_tree(&mut table.pager, &mut table.out, table.root_page_num, 0) {
+                outln!(table.out, "Error: {}.", e);
+            }
+            MetaCommandResult::Success
+        }
+        ".dot" => {
+            if let Err(e) = print_tree_dot(&mut table.pager, &mut table.out, table.root_page_num) {
+                outln!(table.out, "Error: {}.", e);
+            }
+            MetaCommandResult::Success
+        }
+        ".constants" => {
+            outln!(table.out, "Constants:");
+            print_constants(&mut table.out, usable_page_size(&table.pager));
+            MetaCommandResult::Success
+        }
+        ".schema" => {
+            print_schema(table);
+            MetaCommandResult::Success
+        }
+        ".version" => {
+            let build = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            match file_format_version(&mut table.pager) {
+                Ok(Some(version)) => outln!(table.out, "{} (file format {})", build, version),
+                Ok(None) => outln!(table.out, "{} (in memory)", build),
+                Err(e) => outln!(table.out, "Error: {}.", e),
+            }
+            MetaCommandResult::Success
+        }
+        ".leaves" => {
+            let extremes = for_each_leaf_page(&mut table.pager, |page_num, leaf| {
+                outln!(table.out, "page {}: {} cells", page_num, leaf_node_num_cells(leaf));
+            })
+            .and_then(|()| table_leftmost_leaf(table))
+            .and_then(|leftmost| Ok((leftmost, table_rightmost_leaf(table)?)));
+            match extremes {
+                Ok((leftmost, rightmost)) => {
+                    outln!(table.out, "leftmost leaf: {}, rightmost leaf: {}", leftmost, rightmost)
+                }
+                Err(e) => outln!(table.out, "Error: {}.", e),
+            }
+            MetaCommandResult::Success
+        }
+        ".echo on" => {
+            table.settings.echo = true;
+            MetaCommandResult::Success
+        }
+        ".echo off" => {
+            table.settings.echo = false;
+            MetaCommandResult::Success
+        }
+        ".nocase username on" => {
+            table.settings.username_nocase = true;
+            MetaCommandResult::Success
+        }
+        ".nocase username off" => {
+            table.settings.username_nocase = false;
+            MetaCommandResult::Success
+        }
+        ".nocase email on" => {
+            table.settings.email_nocase = true;
+            MetaCommandResult::Success
+        }
+        ".nocase email off" => {
+            table.settings.email_nocase = false;
+            MetaCommandResult::Success
+        }
+        ".preallocation keep" => {
+            table.settings.keep_preallocated = true;
+            MetaCommandResult::Success
+        }
+        ".preallocation trim" => {
+            table.settings.keep_preallocated = false;
+            MetaCommandResult::Success
+        }
+        ".mode list" => {
+            table.settings.output_mode = OutputMode::List;
+            MetaCommandResult::Success
+        }
+        ".mode json" => {
+            table.settings.output_mode = OutputMode::Json;
+            MetaCommandResult::Success
+        }
+        ".mode hex" => {
+            table.settings.output_mode = OutputMode::Hex;
+            MetaCommandResult::Success
+        }
+        ".mode csv" => {
+            table.settings.output_mode = OutputMode::Csv;
+            MetaCommandResult::Success
+        }
+        ".utf8 strict" => {
+            table.settings.strict_utf8 = true;
+            MetaCommandResult::Success
+        }
+        ".utf8 lossy" => {
+            table.settings.strict_utf8 = false;
+            MetaCommandResult::Success
+        }
+        ".truncate on" => {
+            table.settings.truncate = true;
+            MetaCommandResult::Success
+        }
+        ".truncate off" => {
+            table.settings.truncate = false;
+            MetaCommandResult::Success
+        }
+        ".bounded on" => {
+            table.settings.bounded = true;
+            MetaCommandResult::Success
+        }
+        ".bounded off" => {
+            table.settings.bounded = false;
+            MetaCommandResult::Success
+        }
+        ".softdelete on" => {
+            table.settings.soft_delete = true;
+            MetaCommandResult::Success
+        }
+        ".softdelete off" => {
+            table.settings.soft_delete = false;
+            MetaCommandResult::Success
+        }
+        ".footer on" => {
+            table.settings.footer = true;
+            MetaCommandResult::Success
+        }
+        ".footer off" => {
+            table.settings.footer = false;
+            MetaCommandResult::Success
+        }
+        ".keys" => {
+            let mut out = table.take_out();
+            match table_keys(table) {
+                Ok(keys) => {
+                    for key in keys {
+                        match key {
+                            Ok(key) => outln!(out, "{}", key),
+                            Err(e) => outln!(out, "Error: {}.", e),
+                        }
+                    }
+                }
+                Err(e) => outln!(out, "Error: {}.", e),
+            }
+            table.out = out;
+            MetaCommandResult::Success
+        }
+        ".param clear" => {
+            table.settings.params.clear();
+            MetaCommandResult::Success
+        }
+        command if command.starts_with(".param set ") => {
+            let rest = command[".param set ".len()..].trim_start();
+            let (index, value) = rest.split_once(' ').unwrap_or((rest, ""));
+            match index.parse::<usize>() {
+                Ok(n) if n >= 1 => {
+                    let params = &mut table.settings.params;
+                    if params.len() < n {
+                        params.resize(n, None);
+                    }
+                    params[n - 1] = Some(value.to_string());
+                }
+                _ => outln!(table.out, "Usage: .param set <n> <value>"),
+            }
+            MetaCommandResult::Success
+        }
+        command if command.starts_with(".rows-in-page ") => {
+            match command[".rows-in-page ".len()..].trim().parse() {
+                Ok(page_num) => {
+                    if let Err(e) = print_rows_in_page(&mut table.pager, &mut table.out, page_num) {
+                        outln!(table.out, "Error: {}.", e);
+                    }
+                }
+                Err(_) => outln!(table.out, "Usage: .rows-in-page <page number>"),
+            }
+            MetaCommandResult::Success
+        }
+        command if command.starts_with(".pagedump ") => {
+            match command[".pagedump ".len()..].trim().parse() {
+                Ok(page_num) => print_page_dump(&mut table.pager, &mut table.out, page_num),
+                Err(_) => outln!(table.out, "Usage: .pagedump <page number>"),
+            }
+            MetaCommandResult::Success
+        }
+        ".begin" => {
+            if let Err(e) = begin_transaction(&mut table.pager) {
+                outln!(table.out, "Error: {}.", e);
+            }
+            MetaCommandResult::Success
+        }
+        ".commit" => {
+            if let Err(e) = commit_transaction(&mut table.pager) {
+                outln!(table.out, "Error: {}.", e);
+            }
+            MetaCommandResult::Success
+        }
+        ".rollback" => {
+            // The cached append target may be a page that no longer exists
+            table.rightmost_leaf = None;
+            if let Err(e) = rollback_transaction(&mut table.pager) {
+                outln!(table.out, "Error: {}.", e);
+            }
+            MetaCommandResult::Success
+        }
+        ".flush" => {
+            if let Err(e) = pager_sync(&mut table.pager) {
+                outln!(table.out, "Error: {}.", e);
+            }
+            MetaCommandResult::Success
+        }
+        ".stats" => {
+            if let Err(e) = print_tree_stats(table) {
+                outln!(table.out, "Error: {}.", e);
+            }
+            print_write_stats(&table.pager, &mut table.out);
+            MetaCommandResult::Success
+        }
+        ".verify" => {
+            match verify_tree(table) {
+                Ok(()) => outln!(table.out, "OK"),
+                Err(violation) => outln!(table.out, "Error: {}.", violation),
+            }
+            MetaCommandResult::Success
+        }
+        ".tables" => {
+            for named in &table.pager.tables {
+                let layout = if named.heap_page_num != 0 { " (heap)" } else { "" };
+                outln!(table.out, "{}{}", named.name, layout);
+            }
+            MetaCommandResult::Success
+        }
+        ".createindex email" | ".createindex username" => {
+            let column = if input.buffer.trim().ends_with("email") {
+                Column::Email
+            } else {
+                Column::Username
+            };
+            match create_index(table, column) {
+                Ok(rows) => outln!(table.out, "Indexed {} rows.", rows),
+                Err(e) => outln!(table.out, "Error: {}.", e),
+            }
+            MetaCommandResult::Success
+        }
+        ".vacuum" => {
+            match vacuum(table) {
+                Ok((before, after)) => {
+                    outln!(table.out, "Vacuumed {} pages into {}.", before, after)
+                }
+                Err(e) => outln!(table.out, "Error: {}.", e),
+            }
+            MetaCommandResult::Success
+        }
+        ".freelist" => {
+            match free_list_pages(&mut table.pager) {
+                Ok(pages) => {
+                    for page_num in &pages {
+                        outln!(table.out, "page {}", page_num);
+                    }
+                    let free_bytes = pages.len() * table.pager.page_size;
+                    outln!(table.out, "{} free pages ({} bytes)", pages.len(), free_bytes);
+                    if pages.len() != table.pager.free_count as usize {
+                        outln!(
+                            table.out,
+                            "Error: header counts {} free pages.",
+                            table.pager.free_count
+                        );
+                    }
+                }
+                Err(e) => outln!(table.out, "Error: {}.", e),
+            }
+            MetaCommandResult::Success
+        }
+        ".dbinfo" => {
+            if let Err(e) = print_dbinfo(&mut table.pager, &mut table.out) {
+                outln!(table.out, "Error: {}.", e);
+            }
+            MetaCommandResult::Success
+        }
+        ".dump sql" => {
+            let mut out = table.take_out();
+            let result = dump_sql(table, &mut out);
+            table.out = out;
+            if let Err(e) = result {
+                outln!(table.out, "Error: {}.", e);
+            }
+            MetaCommandResult::Success
+        }
+        command if command.starts_with(".dump sql ") => {
+            let filename = command[".dump sql ".len()..].trim();
+            let result = File::create(filename)
+                .map_err(DbError::from)
+                .and_then(|mut file| dump_sql(table, &mut file));
+            if let Err(e) = result {
+                outln!(table.out, "Error: {}.", e);
+            }
+            MetaCommandResult::Success
+        }
+        command if command.starts_with(".export ") => {
+            let filename = command[".export ".len()..].trim();
+            let result = File::create(filename)
+                .map_err(DbError::from)
+                .and_then(|mut file| export_csv(table, &mut file));
+            match result {
+                Ok(count) => outln!(table.out, "Exported {} rows.", count),
+                Err(e) => outln!(table.out, "Error: {}.", e),
+            }
+            MetaCommandResult::Success
+        }
+        command if command.starts_with(".import ") => {
+            let Some((options, filename)) = parse_import_args(&command[".import ".len()..])
+            else {
+                outln!(table.out, "Usage: .import [--resume <line>] [--strict] <file>");
+                return MetaCommandResult::Success;
+            };
+            let mut progress = ImportProgress::default();
+            let result = import_rows(table, filename, &options, &mut progress);
+            outln!(
+                table.out,
+                "Imported {} rows, skipped {}, up to line {}.",
+                progress.imported, progress.skipped, progress.last_line
+            );
+            if let Err(e) = result {
+                outln!(table.out, "Error: {} after line {}.", e, progress.last_line);
+            }
+            MetaCommandResult::Success
+        }
+        command if command.starts_with(".read ") => {
+            let filename = command[".read ".len()..].trim();
+            let script = match std::fs::read_to_string(filename) {
+                Ok(script) => script,
+                Err(e) => {
+                    outln!(table.out, "Error: {}.", e);
+                    return MetaCommandResult::Success;
+                }
+            };
+            for line in script.lines().filter(|line| !line.trim().is_empty()) {
+                let mut input_buffer = InputBuffer::new();
+                input_buffer.buffer = line.trim_end().to_string();
+                input_buffer.input_length = input_buffer.buffer.len();
+                if run_input(&input_buffer, table) {
+                    return MetaCommandResult::Exit;
+                }
+            }
+            MetaCommandResult::Success
+        }
+        _ => MetaCommandResult::UnrecognizedCommand,
+    }
+}
+
+// Walks the tree from the root to count internal and leaf pages and return
+// its depth, counting a lone root leaf as depth 1
+fn tree_shape(
+    pager: &mut Pager,
+    page_num: usize,
+    internal_pages: &mut usize,
+    leaf_pages: &mut usize,
+) -> Result<usize, DbError> {
+    let node = get_page(pager, page_num)?;
+    match node_type_checked(node, page_num)? {
+        NodeType::Leaf => {
+            *leaf_pages += 1;
+            Ok(1)
+        }
+        NodeType::Internal => {
+            *internal_pages += 1;
+            let num_keys = internal_node_num_keys(node) as usize;
+            let mut children = (0..num_keys)
+                .map(|i| internal_node_child(node, i).map(|child| child as usize))
+                .collect::<Result<Vec<_>, _>>()?;
+            children.push(internal_node_right_child(node) as usize);
+
+            let mut depth = 0;
+            for child in children {
+                depth = depth.max(tree_shape(pager, child, internal_pages, leaf_pages)?);
+            }
+            Ok(depth + 1)
+        }
+    }
+}
+
+// Quick health check after a bulk insert: the shape comes from walking the
+// tree and the row count from following the leaf chain
+fn print_tree_stats(table: &mut Table) -> Result<(), DbError> {
+    let mut internal_pages = 0;
+    let mut leaf_pages = 0;
+    let depth = tree_shape(
+        &mut table.pager,
+        table.root_page_num,
+        &mut internal_pages,
+        &mut leaf_pages,
+    )?;
+
+    let rows = count_rows(table, None)?.unwrap_or_default();
+
+    outln!(table.out, "tree depth: {}", depth);
+    outln!(table.out, "internal pages: {}", internal_pages);
+    outln!(table.out, "leaf pages: {}", leaf_pages);
+    outln!(table.out, "total rows: {}", rows);
+    Ok(())
+}
+
+// Pages are only written back when they are flushed, and always in full,
+// so the physical count stays at 0 until then and one small insert costs
+// a whole page
+fn print_write_stats(pager: &Pager, out: &mut dyn Write) {
+    outln!(out, "logical bytes written: {}", pager.logical_bytes_written);
+    outln!(out, "physical bytes written: {}", pager.physical_bytes_written);
+    match write_amplification(pager) {
+        Some(ratio) => outln!(out, "write amplification: {:.1}", ratio),
+        None => outln!(out, "write amplification: n/a"),
+    }
+    outln!(out, "cache misses: {}", pager.cache_misses);
+}
+
+// Physical over logical bytes written; None until both sides have
+// something to compare, since before the first flush a ratio of 0 would
+// read as no amplification at all
+fn write_amplification(pager: &Pager) -> Option<f64> {
+    if pager.logical_bytes_written == 0 || pager.physical_bytes_written == 0 {
+        return None;
+    }
+    Some(pager.physical_bytes_written as f64 / pager.logical_bytes_written as f64)
+}
+
+// One-glance storage summary as stable "key: value" lines. Utilization is
+// the bytes of row data over the bytes the file takes on disk, which
+// includes page headers, unused cell slots, soft-deleted rows and any
+// preallocated tail.
+fn print_dbinfo(pager: &mut Pager, out: &mut dyn Write) -> Result<(), DbError> {
+    let mut leaf_pages = 0;
+    let mut internal_pages = 0;
+    let mut heap_pages = 0;
+    let mut rows = 0;
+    let mut tombstones = 0;
+    // Each index's pages are counted on their own, so the rest describe
+    // the table
+    let mut index_pages = [HashSet::new(), HashSet::new()];
+    for (pages, column) in index_pages.iter_mut().zip(INDEXED_COLUMNS) {
+        let root_page_num = index_root(pager, column);
+        if root_page_num != 0 {
+            *pages = tree_pages(pager, root_page_num)?;
+        }
+    }
+
+    for page_num in 0..pager.num_pages {
+        let node = get_page(pager, page_num)?;
+        let in_index = index_pages.iter().any(|pages| pages.contains(&page_num));
+        if node[NODE_TYPE_OFFSET] == FREE_PAGE_TYPE || in_index {
+            continue;
+        }
+        // A heap table's rows are counted where they live; its leaves only
+        // hold locators, and nothing in a heap is ever soft-deleted
+        if node[NODE_TYPE_OFFSET] == HEAP_PAGE_TYPE {
+            heap_pages += 1;
+            rows += get_u32_at(node, HEAP_PAGE_NUM_ROWS_OFFSET) as usize;
+            continue;
+        }
+        match node_type_checked(node, page_num)? {
+            NodeType::Leaf if node[NODE_TYPE_OFFSET] == HEAP_LEAF_TYPE => leaf_pages += 1,
+            NodeType::Leaf => {
+                leaf_pages += 1;
+                for cell_num in 0..leaf_node_num_cells(node) as usize {
+                    if is_tombstone(leaf_node_value_checked(node, page_num, cell_num)?) {
+                        tombstones += 1;
+                    } else {
+                        rows += 1;
+                    }
+                }
+            }
+            NodeType::Internal => internal_pages += 1,
+        }
+    }
+
+    let file_bytes = pager.file_length.max(page_offset(pager, pager.num_pages));
+    let live_bytes = (rows * ROW_SIZE) as u64;
+    let utilization = if file_bytes == 0 {
+        0.0
+    } else {
+        live_bytes as f64 * 100.0 / file_bytes as f64
+    };
+
+    outln!(out, "file size: {}", file_bytes);
+    outln!(out, "page size: {}", pager.page_size);
+    outln!(out, "total pages: {}", pager.num_pages);
+    outln!(out, "leaf pages: {}", leaf_pages);
+    outln!(out, "internal pages: {}", internal_pages);
+    outln!(out, "heap pages: {}", heap_pages);
+    outln!(out, "free pages: {}", pager.free_count);
+    for (pages, column) in index_pages.iter().zip(INDEXED_COLUMNS) {
+        outln!(out, "{} index pages: {}", column.name(), pages.len());
+    }
+    outln!(out, "checksums: {}", if pager.checksums { "on" } else { "off" });
+    outln!(out, "total rows: {}", rows);
+    outln!(out, "deleted rows: {}", tombstones);
+    outln!(out, "utilization: {:.1}%", utilization);
+    Ok(())
+}
+
+// Raw bytes of any page, for looking at corruption: the node header
+// decoded, then 16 bytes per line as offset, hex and printable ASCII.
+// The type byte is read directly so free and damaged pages dump too, and a
+// page that fails its checksum is loaded anyway, then dropped from the
+// cache again so later reads still check it.
+fn print_page_dump(pager: &mut Pager, out: &mut dyn Write, page_num: usize) {
+    if page_num >= pager.num_pages {
+        outln!(out, "Error: Page {} out of range.", page_num);
+        return;
+    }
+    let was_cached = pager.pages[page_num].is_some();
+    let checksums = pager.checksums;
+    pager.checksums = false;
+    let loaded = get_page(pager, page_num).map(|node| node.to_vec());
+    pager.checksums = checksums;
+    if !was_cached {
+        pager.pages[page_num] = None;
+    }
+    let node = match loaded {
+        Ok(node) => node,
+        Err(e) => {
+            outln!(out, "Error: {}.", e);
+            return;
+        }
+    };
+    let node = node.as_slice();
+
+    outln!(out, "page: {}", page_num);
+    if checksums {
+        // Cached pages only get their checksum when written out
+        let state = if was_cached && pager.dirty.contains(&page_num) {
+            "not yet written"
+        } else if page_checksum_ok(node) {
+            "ok"
+        } else {
+            "mismatch"
+        };
+        outln!(out, "checksum: {}", state);
+    }
+    match node[NODE_TYPE_OFFSET] {
+        FREE_PAGE_TYPE => {
+            outln!(out, "type: free");
+            outln!(out, "next free: {}", get_u32_at(node, FREE_PAGE_NEXT_OFFSET));
+        }
+        HEAP_PAGE_TYPE => {
+            outln!(out, "type: heap");
+            outln!(out, "num_rows: {}", get_u32_at(node, HEAP_PAGE_NUM_ROWS_OFFSET));
+            outln!(out, "last heap page: {}", get_u32_at(node, HEAP_PAGE_LAST_OFFSET));
+        }
+        node_type => {
+            match node_type {
+                0 => outln!(out, "type: internal"),
+                1 => outln!(out, "type: leaf"),
+                HEAP_LEAF_TYPE => outln!(out, "type: heap leaf"),
+                _ => outln!(out, "type: unknown ({})", node_type),
+            }
+            outln!(out, "is_root: {}", is_node_root(node));
+            outln!(out, "parent: {}", node_parent(node));
+            if node_type == 0 {
+                outln!(out, "num_keys: {}", internal_node_num_keys(node));
+                outln!(out, "right child: {}", internal_node_right_child(node));
+            } else if node_type == 1 || node_type == HEAP_LEAF_TYPE {
+                outln!(out, "num_cells: {}", leaf_node_num_cells(node));
+                outln!(out, "next leaf: {}", get_leaf_node_next_leaf(node));
+                outln!(out, "prev leaf: {}", get_leaf_node_prev_leaf(node));
+            }
+        }
+    }
+
+    for (line, bytes) in node.chunks(16).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = bytes
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        outln!(out, "{:08x}  {:<47}  |{}|", line * 16, hex.join(" "), ascii);
+    }
+}
+
+// Every page of the tree rooted at root_page_num
+fn tree_pages(pager: &mut Pager, root_page_num: usize) -> Result<HashSet<usize>, DbError> {
+    let mut pages = HashSet::new();
+    let mut pending = vec![root_page_num];
+    while let Some(page_num) = pending.pop() {
+        if !pages.insert(page_num) {
+            continue;
+        }
+        let node = get_page(pager, page_num)?;
+        if node_type_checked(node, page_num)? == NodeType::Internal {
+            let num_keys = internal_node_num_keys(node) as usize;
+            for i in 0..num_keys {
+                pending.push(internal_node_child(node, i)? as usize);
+            }
+            pending.push(internal_node_right_child(node) as usize);
+        }
+    }
+    Ok(pages)
+}
+
+// Decoded view of one leaf page: each cell's key and row, in cell order,
+// soft-deleted rows included and marked as such
+fn print_rows_in_page(
+    pager: &mut Pager,
+    out: &mut dyn Write,
+    page_num: usize,
+) -> Result<(), DbError> {
+    if page_num >= pager.num_pages {
+        outln!(out, "Error: Page {} out of range.", page_num);
+        return Ok(());
+    }
+    let node = get_page(pager, page_num)?;
+    if node[NODE_TYPE_OFFSET] == FREE_PAGE_TYPE {
+        outln!(out, "Error: Page {} is free.", page_num);
+        return Ok(());
+    }
+    let is_heap_page = node[NODE_TYPE_OFFSET] == HEAP_PAGE_TYPE;
+    if is_heap_page || node_type_checked(node, page_num)? != NodeType::Leaf {
+        outln!(out, "Error: Page {} is not a leaf.", page_num);
+        return Ok(());
+    }
+
+    for cell_num in 0..leaf_node_num_cells(node) as usize {
+        let key = leaf_node_key_checked(get_page(pager, page_num)?, page_num, cell_num)?;
+        let value = leaf_row(pager, page_num, cell_num)?;
+        let row = Row::deserialize(value);
+        outln!(
+            out,
+            "{}: ({}, {}, {}){}",
+            key,
+            row.id,
+            list_field(&row.get_username()),
+            list_field(&row.get_email()),
+            if is_tombstone(value) { " deleted" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+// Bind the session's .param values to a prepared statement: values that
+// read as a u32 are bound as numbers, anything else as text. Parameters
+// that are not set stay unbound.
+fn bind_session_params(prepared: &mut PreparedStatement, params: &[Option<String>]) {
+    for n in 1..=prepared.placeholder_count() {
+        let Some(value) = params.get(n - 1).and_then(|v| v.as_ref()) else {
+            continue;
+        };
+        let bound = match value.parse::<u32>() {
+            Ok(number) => prepared.bind_u32(n, number),
+            Err(_) => prepared.bind_str(n, value),
+        };
+        bound.expect("every n up to placeholder_count is a placeholder");
+    }
+}
+
+// Replace each ? token with a bound parameter: the first ? takes ?1, the
+// second ?2 and so on, so 'insert ? ? ?' consumes parameters 1 to 3. Values
+// the grammar can't take as typed are spliced in as hex literals. Returns
+// the number of the first parameter that is not set.
+fn bind_params(input: &str, params: &[Option<String>]) -> Result<String, usize> {
+    let mut next = 0;
+    let mut tokens = Vec::new();
+    for token in input.split_whitespace() {
+        if token == "?" {
+            let value = params.get(next).and_then(|v| v.as_ref()).ok_or(next + 1)?;
+            tokens.push(dump_value(value.as_bytes()));
+            next += 1;
+        } else {
+            tokens.push(token.to_string());
+        }
+    }
+    Ok(tokens.join(" "))
+}
+
+// How a text value is written in a dump. Values the insert grammar can take
+// as typed are left alone; anything empty, containing whitespace or control
+// characters, not UTF-8, or that would read as a hex literal or as NULL is
+// written as x'..' so it loads back byte for byte.
+fn dump_value(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text)
+            if !text.is_empty()
+                && !text.starts_with("x'")
+                && !is_null_literal(text)
+                && !text.chars().any(|c| c.is_whitespace() || c.is_control()) =>
+        {
+            text.to_string()
+        }
+        _ => {
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("x'{}'", hex)
+        }
+    }
+}
+
+// Rebuild the table into a fresh file by inserting every row in key order,
+// which drops free pages and refills underfull nodes, and return the page
+// counts before and after. The copy is built as <file>-vacuum, in the temp
+// directory if one is set, and is synced before it is renamed over the
+// original, so a crash at any point leaves one complete file. If any row
+// can't be read the copy is deleted and the original is left as it was.
+// An in-memory table is rebuilt in memory.
+fn vacuum(table: &mut Table) -> Result<(usize, usize), DbError> {
+    if table.pager.transaction.is_some() {
+        return Err(DbError::TransactionOpen);
+    }
+    // Empty the log first: its frames hold page numbers of the old layout
+    pager_sync(&mut table.pager)?;
+
+    let path = table.pager.path.clone();
+    let temp_path = path.as_ref().map(|path| format!("{}-vacuum", path));
+    let scratch_path = path.as_ref().map(|path| vacuum_scratch_path(path, &table.settings));
+    for stale in [&temp_path, &scratch_path].into_iter().flatten() {
+        // Left behind by an earlier vacuum that did not finish
+        remove_vacuum_files(stale)?;
+    }
+
+    let pages_before = table.pager.num_pages;
+    let mut rebuilt = match vacuum_copy(table, scratch_path.as_deref()) {
+        Ok(rebuilt) => rebuilt,
+        Err(e) => {
+            if let Some(scratch_path) = &scratch_path {
+                let _ = remove_vacuum_files(scratch_path);
+            }
+            return Err(e);
+        }
+    };
+
+    if let (Some(path), Some(temp_path), Some(scratch_path)) = (&path, &temp_path, &scratch_path) {
+        // db_close syncs the copy and trims it to its pages
+        let moved = db_close(rebuilt)
+            .and_then(|()| vacuum_move(scratch_path, temp_path, path).map_err(DbError::from));
+        if let Err(e) = moved {
+            let _ = remove_vacuum_files(scratch_path);
+            let _ = remove_vacuum_files(temp_path);
+            return Err(e);
+        }
+        rebuilt = db_open(path, false, table.pager.page_size, table.pager.checksums)?;
+    }
+    rebuilt.pager.max_cached_pages = table.pager.max_cached_pages;
+    std::mem::swap(&mut table.pager, &mut rebuilt.pager);
+    table.rightmost_leaf = None;
+
+    Ok((pages_before, table.pager.num_pages))
+}
+
+// Open the copy at scratch_path (in memory when None) and insert every row
+// of table and of each named table into it. Any error, including a page
+// that can't be read, fails the whole copy.
+fn vacuum_copy(table: &mut Table, scratch_path: Option<&str>) -> Result<Table, DbError> {
+    let mut rebuilt = db_open(
+        scratch_path.unwrap_or(":memory:"),
+        false,
+        table.pager.page_size,
+        table.pager.checksums,
+    )?;
+    rebuilt.leaf_max_cells = table.leaf_max_cells;
+    rebuilt.internal_max_cells = table.internal_max_cells;
+    rebuilt.pack_appends = true;
+    set_text_widths(&mut rebuilt.pager, table.pager.text_widths)?;
+
+    copy_rows(table, &mut rebuilt)?;
+    for named in table.pager.tables.clone() {
+        let name = named.name.as_str();
+        create_named_table(&mut rebuilt, name, named.heap_page_num != 0)?;
+        in_named_table(Some(name), table, |table| {
+            in_named_table(Some(name), &mut rebuilt, |rebuilt| copy_rows(table, rebuilt))
+        })?;
+    }
+    for column in INDEXED_COLUMNS {
+        if index_root(&table.pager, column) != 0 {
+            create_index(&mut rebuilt, column)?;
+        }
+    }
+    Ok(rebuilt)
+}
+
+// Insert every live row of table into rebuilt, in key order
+fn copy_rows(table: &mut Table, rebuilt: &mut Table) -> Result<(), DbError> {
+    let mut cursor = table_start(table)?;
+    while !cursor.end_of_table {
+        // Soft-deleted rows are left behind, which is what frees their space
+        let value = cursor_value(&mut cursor)?;
+        if is_tombstone(value) {
+            cursor_advance(&mut cursor)?;
+            continue;
+        }
+        let statement = Statement {
+            statement_type: StatementType::Insert(OnConflict::Abort),
+            row_to_insert: Some(Row::deserialize(value)),
+            predicate: None,
+            table: None,
+            order_by: Column::Id,
+            descending: false,
+            limit: None,
+            warnings: Vec::new(),
+        };
+        execute_insert(&statement, rebuilt)?;
+        cursor_advance(&mut cursor)?;
+    }
+    Ok(())
+}
+
+// <file>-vacuum, either next to the database or in the temp directory
+fn vacuum_scratch_path(path: &str, settings: &Settings) -> String {
+    let temp_path = format!("{}-vacuum", path);
+    match (&settings.temp_dir, Path::new(&temp_path).file_name()) {
+        (Some(dir), Some(name)) => dir.join(name).to_string_lossy().into_owned(),
+        _ => temp_path,
+    }
+}
+
+// Rename the finished copy over the database. A rename is only atomic
+// within one filesystem, so a copy built in a temp directory on another
+// one is first copied to <file>-vacuum next to the database and synced.
+fn vacuum_move(scratch_path: &str, temp_path: &str, path: &str) -> io::Result<()> {
+    match std::fs::rename(scratch_path, path) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            std::fs::copy(scratch_path, temp_path)?;
+            File::open(temp_path)?.sync_all()?;
+            std::fs::rename(temp_path, path)?;
+            std::fs::remove_file(scratch_path)
+        }
+        result => result,
+    }
+}
+
+// Delete a vacuum copy and its log, if they exist
+fn remove_vacuum_files(temp_path: &str) -> io::Result<()> {
+    for file in [temp_path.to_string(), format!("{}-wal", temp_path)] {
+        match std::fs::remove_file(&file) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Write every row as an insert statement in key order, after a create table
+// if the text columns were narrowed, so that running the output through .read
+// on an empty database recreates the table
+fn dump_sql(table: &mut Table, out: &mut dyn Write) -> Result<(), DbError> {
+    let widths = table.pager.text_widths;
+    if widths != TextWidths::default() {
+        writeln!(
+            out,
+            "create table (id int, username text({}), email text({}))",
+            widths.username, widths.email
+        )?;
+    }
+    let mut cursor = table_start(table)?;
+
+    while !cursor.end_of_table {
+        let row = RowRef::new(cursor_value(&mut cursor)?);
+        if row.is_tombstone() {
+            cursor_advance(&mut cursor)?;
+            continue;
+        }
+        let [username, email] = [
+            (Column::Username, row.username_bytes()),
+            (Column::Email, row.email_bytes()),
+        ]
+        .map(|(column, bytes)| {
+            if row.is_null(column) {
+                "null".to_string()
+            } else {
+                dump_value(bytes)
+            }
+        });
+        writeln!(out, "insert {} {} {}", row.id(), username, email)?;
+        cursor_advance(&mut cursor)?;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+// Write every row as an 'id,username,email' CSV line in key order and
+// return how many were written. Text that is not UTF-8 is written lossily.
+fn export_csv(table: &mut Table, out: &mut dyn Write) -> Result<usize, DbError> {
+    let mut cursor = table_start(table)?;
+    let mut count = 0;
+
+    while !cursor.end_of_table {
+        let row = RowRef::new(cursor_value(&mut cursor)?);
+        if row.is_tombstone() {
+            cursor_advance(&mut cursor)?;
+            continue;
+        }
+        // NULL is an empty unquoted field
+        let [username, email] = [
+            (Column::Username, row.username_bytes()),
+            (Column::Email, row.email_bytes()),
+        ]
+        .map(|(column, bytes)| {
+            if row.is_null(column) {
+                String::new()
+            } else {
+                csv_field(&String::from_utf8_lossy(bytes))
+            }
+        });
+        writeln!(out, "{},{},{}", row.id(), username, email)?;
+        count += 1;
+        cursor_advance(&mut cursor)?;
+    }
+
+    out.flush()?;
+    Ok(count)
+}
+
+// RFC 4180 quoting: a field holding a comma, quote or line break is put in
+// double quotes, with any quotes inside doubled. So is one with spaces at
+// either end, which parse_csv_record would otherwise trim, and an empty
+// one, since an empty unquoted field is NULL.
+fn csv_field(value: &str) -> String {
+    if value.is_empty() || value.contains([',', '"', '\r', '\n']) || value.trim() != value {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Split one CSV record into fields, undoing csv_field's quoting. A quoted
+// field is kept exactly, line breaks and edge spaces included; an unquoted
+// one is trimmed, and is None (NULL) if nothing is left. Returns None for a
+// quote left open at the end of the record.
+fn parse_csv_record(record: &str) -> Option<Vec<Option<String>>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = record.chars().peekable();
+    let mut quoted = false;
+    let mut was_quoted = false;
+    let finish = |field: &mut String, was_quoted: bool| {
+        let field = std::mem::take(field);
+        if was_quoted {
+            Some(field)
+        } else {
+            Some(field.trim().to_string()).filter(|field| !field.is_empty())
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => {
+                quoted = true;
+                was_quoted = true;
+            }
+            ',' if !quoted => {
+                fields.push(finish(&mut field, was_quoted));
+                was_quoted = false;
+            }
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    fields.push(finish(&mut field, was_quoted));
+    Some(fields)
+}
+
+// How .import treats a file: the line to resume after, and whether a
+// duplicate id stops the import instead of being skipped
+#[derive(Debug, Default)]
+struct ImportOptions {
+    resume_after: usize,
+    strict: bool,
+}
+
+// Where an import got to. It lives with the caller, so the last line done is
+// still there to report when the import stops on an error.
+#[derive(Debug, Default)]
+struct ImportProgress {
+    imported: usize,
+    skipped: usize,
+    last_line: usize,
+}
+
+// Split '.import [--resume <line>] [--strict] <file>' into its options and
+// file name. Returns None for a bad or missing line number or no file name.
+fn parse_import_args(args: &str) -> Option<(ImportOptions, &str)> {
+    let mut options = ImportOptions::default();
+    let mut rest = args.trim();
+    loop {
+        if let Some(after) = rest.strip_prefix("--resume ") {
+            let after = after.trim_start();
+            let end = after.find(char::is_whitespace)?;
+            options.resume_after = after[..end].parse().ok()?;
+            rest = after[end..].trim_start();
+        } else if let Some(after) = rest.strip_prefix("--strict ") {
+            options.strict = true;
+            rest = after.trim_start();
+        } else {
+            break;
+        }
+    }
+    (!rest.is_empty()).then_some((options, rest))
+}
+
+// Insert one row per 'id,username,email' CSV line of a file, checked the
+// same way as an insert statement. Lines up to options.resume_after are
+// passed over. Lines with a bad value or a full table are skipped, and so are
+// duplicate ids unless the import is strict, which stops there with
+// DuplicateKey. progress.last_line is the last line handled, the one to
+// resume after; line numbers count blank lines so they match an editor's.
+fn import_rows(
+    table: &mut Table,
+    filename: &str,
+    options: &ImportOptions,
+    progress: &mut ImportProgress,
+) -> Result<(), DbError> {
+    let contents = std::fs::read_to_string(filename)?;
+    let truncate = table.settings.truncate;
+    let widths = table.pager.text_widths;
+    progress.last_line = options.resume_after;
+
+    // A quoted field may hold line breaks, so a line that leaves a quote
+    // open runs on into the next one. Line numbers stay the file's, and a
+    // record counts as done at its last line.
+    let mut pending = String::new();
+    let mut line_num = options.resume_after;
+    for line in contents.split_inclusive('\n').skip(options.resume_after) {
+        line_num += 1;
+        pending.push_str(line);
+        if pending.matches('"').count() % 2 == 1 {
+            continue;
+        }
+        let record = std::mem::take(&mut pending);
+        let record = record.strip_suffix('\n').unwrap_or(&record);
+        let record = record.strip_suffix('\r').unwrap_or(record);
+        if record.trim().is_empty() {
+            progress.last_line = line_num;
+            continue;
+        }
+        let fields = parse_csv_record(record).unwrap_or_default();
+        let mut warnings = Vec::new();
+        let row = match &fields[..] {
+            [Some(id), username, email] => match id.parse::<i64>() {
+                Ok(id) => {
+                    // CSV has its own NULL, so a field reading null is text
+                    let [username, email] = [username, email].map(|field| match field {
+                        None => "null".to_string(),
+                        Some(text) if is_null_literal(text) => dump_value(text.as_bytes()),
+                        Some(text) => text.clone(),
+                    });
+                    validate_row(id, &username, &email, widths, truncate, &mut warnings).ok()
+                }
+                Err(_) => None,
+            },
+            _ => None,
+        };
+        for warning in warnings {
+            outln!(table.out, "{}", warning);
+        }
+        let Some(row) = row else {
+            progress.skipped += 1;
+            progress.last_line = line_num;
+            continue;
+        };
+
+        let statement = Statement {
+            statement_type: StatementType::Insert(OnConflict::Abort),
+            row_to_insert: Some(row),
+            predicate: None,
+            table: None,
+            order_by: Column::Id,
+            descending: false,
+            limit: None,
+            warnings: Vec::new(),
+        };
+        match execute_insert(&statement, table)? {
+            ExecuteResult::Success => progress.imported += 1,
+            ExecuteResult::DuplicateKey if options.strict => return Err(DbError::DuplicateKey),
+            _ => progress.skipped += 1,
+        }
+        progress.last_line = line_num;
+    }
+
+    // A quote still open at the end of the file leaves one broken record
+    if !pending.is_empty() {
+        progress.skipped += 1;
+        progress.last_line = line_num;
+    }
+    Ok(())
+}
+
+// Bytes to store for a text value. A value written as x'48656c6c6f' is a
+// hex literal and stands for the bytes it encodes, which need not be UTF-8;
+// anything else is stored as typed. Returns None for a malformed literal
+// (odd number of digits or a non-hex digit) and for one encoding a NUL
+// byte, since columns are NUL-padded and the value would end there.
+fn column_value_bytes(value: &str) -> Option<Vec<u8>> {
+    let hex = match value.strip_prefix("x'").and_then(|v| v.strip_suffix('\'')) {
+        Some(hex) => hex,
+        None => return Some(value.as_bytes().to_vec()),
+    };
+
+    if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok().filter(|&b| b != 0))
+        .collect()
+}
+
+// Longest prefix of value that fits in width bytes. UTF-8 text is cut on a
+// character boundary; raw bytes from a hex literal are cut anywhere.
+fn truncate_value(value: &[u8], width: usize) -> &[u8] {
+    if value.len() <= width {
+        return value;
+    }
+    let mut end = width;
+    if std::str::from_utf8(value).is_ok() {
+        // Back up past continuation bytes (10xxxxxx) to a character start
+        while end > 0 && value[end] & 0xC0 == 0x80 {
+            end -= 1;
+        }
+    }
+    &value[..end]
+}
+
+// Build a row from an id and two text values, applying the checks every
+// way of adding a row shares: id range, hex literals and the declared column
+// widths (truncating instead when truncate is set). Errors are the
+// PrepareResult to report.
+fn validate_row(
+    id: i64,
+    username: &str,
+    email: &str,
+    widths: TextWidths,
+    truncate: bool,
+    warnings: &mut Vec<String>,
+) -> Result<Row, PrepareResult> {
+    // Check if id is negative FIRST
+    if id < 0 {
+        return Err(PrepareResult::NegativeId);
+    }
+
+    if id > u32::MAX as i64 {
+        return Err(PrepareResult::IdOutOfRange);
+    }
+
+    // Convert to u32 now that we know it's in range
+    let id = id as u32;
+
+    // The bare word null, in any case, stores NULL. The text "null" can
+    // still be written as a hex literal, which is how dump_value writes it.
+    let mut flags = 0;
+    let [username, email] = [(Column::Username, username), (Column::Email, email)].map(
+        |(column, value)| {
+            if is_null_literal(value) {
+                flags |= column.null_flag();
+                ""
+            } else {
+                value
+            }
+        },
+    );
+
+    // x'..' hex literals are stored as the raw bytes they encode
+    let username = column_value_bytes(username).ok_or(PrepareResult::InvalidHex)?;
+    let email = column_value_bytes(email).ok_or(PrepareResult::InvalidHex)?;
+
+    // Convert strings to fixed-size byte arrays
+    let mut username_bytes = [0u8; COLUMN_USERNAME_SIZE];
+    let mut email_bytes = [0u8; COLUMN_EMAIL_SIZE];
+
+    let (username, email) = if truncate {
+        let columns = [
+            ("username", username.as_slice(), widths.username),
+            ("email", email.as_slice(), widths.email),
+        ];
+        for (name, value, width) in columns {
+            if value.len() > width {
+                warnings.push(format!("Warning: {} truncated to {} bytes.", name, width));
+            }
+        }
+        (
+            truncate_value(&username, widths.username).to_vec(),
+            truncate_value(&email, widths.email).to_vec(),
+        )
+    } else {
+        (username, email)
+    };
+
+    // Check if username is too long
+    if username.len() > widths.username {
+        return Err(PrepareResult::StringTooLong);
+    }
+
+    // Check if email is too long
+    if email.len() > widths.email {
+        return Err(PrepareResult::StringTooLong);
+    }
+
+    // Copy the string bytes into the arrays
+    username_bytes[..username.len()].copy_from_slice(&username);
+    email_bytes[..email.len()].copy_from_slice(&email);
+
+    Ok(Row {
+        id,
+        username: username_bytes,
+        email: email_bytes,
+        flags,
+    })
+}
+
+fn is_null_literal(value: &str) -> bool {
+    value.eq_ignore_ascii_case("null")
+}
+
+// An insert or update parsed once, with ? placeholders for any of its
+// three values. Values are bound by placeholder number (the first ? is 1)
+// and checked when the statement is built, so the same PreparedStatement
+// can be bound and run again without parsing the text.
+struct PreparedStatement {
+    statement_type: StatementType,
+    // id, username and email as they would be typed: a literal from the
+    // text, a bound value, or None for a placeholder not bound yet
+    values: [Option<String>; 3],
+    // Which of values each placeholder fills, in order
+    placeholders: Vec<usize>,
+}
+
+impl PreparedStatement {
+    // None unless the input is '<insert|update> <id> <username> <email>',
+    // where insert may be 'insert or replace' or 'insert or ignore'
+    fn prepare(input: &str) -> Option<Self> {
+        let tokens: Vec<&str> = input.split_whitespace().collect();
+        let (statement_type, fields) = match tokens[..] {
+            ["insert", "or", "replace", ..] => {
+                (StatementType::Insert(OnConflict::Replace), &tokens[3..])
+            }
+            ["insert", "or", "ignore", ..] => {
+                (StatementType::Insert(OnConflict::Ignore), &tokens[3..])
+            }
+            ["insert", ..] => (StatementType::Insert(OnConflict::Abort), &tokens[1..]),
+            ["update", ..] => (StatementType::Update, &tokens[1..]),
+            _ => return None,
+        };
+        if fields.len() != 3 {
+            return None;
+        }
+
+        let mut values = [None, None, None];
+        let mut placeholders = Vec::new();
+        for (i, token) in fields.iter().enumerate() {
+            if *token == "?" {
+                placeholders.push(i);
+            } else {
+                values[i] = Some(token.to_string());
+            }
+        }
+        Some(PreparedStatement { statement_type, values, placeholders })
+    }
+
+    fn placeholder_count(&self) -> usize {
+        self.placeholders.len()
+    }
+
+    // Err carries a placeholder number the statement does not have
+    fn bind_u32(&mut self, n: usize, value: u32) -> Result<(), usize> {
+        self.bind(n, value.to_string())
+    }
+
+    fn bind_str(&mut self, n: usize, value: &str) -> Result<(), usize> {
+        // Written the way dump_value would so the text is stored byte for
+        // byte, even if it looks like a hex literal
+        self.bind(n, dump_value(value.as_bytes()))
+    }
+
+    fn bind_null(&mut self, n: usize) -> Result<(), usize> {
+        self.bind(n, "null".to_string())
+    }
+
+    fn bind(&mut self, n: usize, value: String) -> Result<(), usize> {
+        let slot = *n.checked_sub(1).and_then(|i| self.placeholders.get(i)).ok_or(n)?;
+        self.values[slot] = Some(value);
+        Ok(())
+    }
+
+    // The statement with the bound values, validated like a typed one
+    fn statement(&self, widths: TextWidths, truncate: bool) -> PrepareResult {
+        let mut values = [""; 3];
+        for (slot, value) in self.values.iter().enumerate() {
+            match value {
+                Some(value) => values[slot] = value,
+                None => {
+                    let n = self.placeholders.iter().position(|&s| s == slot).map_or(0, |i| i + 1);
+                    return PrepareResult::UnboundParameter(n);
+                }
+            }
+        }
+        let [id, username, email] = values;
+        let Ok(id) = id.parse::<i64>() else {
+            return PrepareResult::SyntaxError;
+        };
+
+        let mut warnings = Vec::new();
+        match validate_row(id, username, email, widths, truncate, &mut warnings) {
+            Ok(row) => PrepareResult::Success(Box::new(Statement {
+                statement_type: self.statement_type.clone(),
+                row_to_insert: Some(row),
+                predicate: None,
+                table: None,
+                order_by: Column::Id,
+                descending: false,
+                limit: None,
+                warnings,
+            })),
+            Err(result) => result,
+        }
+    }
+}
+
+// Words of the statement grammar, which can't name a column
+const RESERVED_WORDS: [&str; 21] = [
+    "select", "insert", "update", "delete", "create", "table", "where", "order", "by", "asc",
+    "desc", "limit", "and", "or", "between", "like", "count", "exists", "pragma", "into", "from",
+];
+
+// Everything wrong with the name in 'create table <name>': it has to fit
+// the directory entry and read as one word that is not a keyword
+fn table_name_problems(name: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    let starts_with_digit = name.starts_with(|c: char| c.is_ascii_digit());
+    if starts_with_digit || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        problems.push(format!(
+            "table name '{}' must be letters, digits and underscores, not starting with a digit",
+            name
+        ));
+    }
+    if name.len() > TABLE_NAME_SIZE {
+        problems.push(format!("table name '{}' is longer than {} bytes", name, TABLE_NAME_SIZE));
+    }
+    if RESERVED_WORDS.contains(&name) {
+        problems.push(format!("table name '{}' is reserved", name));
+    }
+    problems
+}
+
+// Parse 'create table (id int, username text(<n>), email text(<n>))'. The
+// columns are fixed, so only the two widths vary; each has to be at least 1
+// and at most the width the column is stored at. The whole schema is
+// checked before anything is reported, so every problem comes back at
+// once. None is a syntax error.
+fn parse_create_table(input: &str) -> Option<Result<TextWidths, PrepareResult>> {
+    let rest = input.strip_prefix("create table")?.trim();
+    let definitions = rest.strip_prefix('(')?.strip_suffix(')')?;
+    let mut columns = Vec::new();
+    for definition in definitions.split(',').map(str::trim) {
+        let (name, column_type) = definition.split_once(char::is_whitespace)?;
+        columns.push((name, column_type.trim()));
+    }
+
+    let mut problems = Vec::new();
+    let mut widths = TextWidths::default();
+    let mut seen = HashSet::new();
+    for &(name, column_type) in &columns {
+        if RESERVED_WORDS.contains(&name) {
+            problems.push(format!("column name '{}' is reserved", name));
+        }
+        if !seen.insert(name) {
+            problems.push(format!("column '{}' is defined more than once", name));
+        }
+        let column = Column::ALL.into_iter().find(|column| column.name() == name);
+        if column_type == "int" {
+            continue;
+        }
+        let width = column_type
+            .strip_prefix("text(")
+            .and_then(|width| width.strip_suffix(')'))
+            .and_then(|width| width.trim().parse::<usize>().ok());
+        let Some(width) = width else {
+            problems.push(format!("column '{}' has unknown type '{}'", name, column_type));
+            continue;
+        };
+        let max_width = match column {
+            Some(column @ (Column::Username | Column::Email)) => column.range().len(),
+            _ => usize::MAX,
+        };
+        if width == 0 {
+            problems.push(format!("column '{}' must be at least 1 byte wide", name));
+        } else if width > max_width {
+            problems.push(format!("column '{}' is wider than {} bytes", name, max_width));
+        }
+        match column {
+            Some(Column::Username) => widths.username = width,
+            Some(Column::Email) => widths.email = width,
+            _ => {}
+        }
+    }
+
+    // Only the order and kinds of the fixed columns can be declared
+    let layout = columns.iter().map(|&(name, column_type)| (name, column_type == "int"));
+    let expected = Column::ALL.map(|column| (column.name(), matches!(column, Column::Id)));
+    if !layout.eq(expected) {
+        problems.push("the columns must be id int, username text(n) and email text(n)".to_string());
+    }
+
+    if problems.is_empty() {
+        Some(Ok(widths))
+    } else {
+        Some(Err(PrepareResult::InvalidSchema(problems)))
+    }
+}
+
+// Parse the '<id> <username> <email>' tail shared by insert and update
+fn prepare_row_statement(
+    input: &str,
+    keyword: &str,
+    statement_type: StatementType,
+    widths: TextWidths,
+    truncate: bool,
+) -> PrepareResult {
+    // Parse as i64 first so negative numbers and ids past u32::MAX can be
+    // told apart from genuine syntax errors
+    let parsed = scan_fmt!(&input[keyword.len()..], "{} {} {}", i64, String, String);
+
+    match parsed {
+        Ok((id, username, email)) => {
+            let mut warnings = Vec::new();
+            let row = match validate_row(id, &username, &email, widths, truncate, &mut warnings) {
+                Ok(row) => row,
+                Err(result) => return result,
+            };
+
+            let statement = Statement {
+                statement_type,
+                row_to_insert: Some(row),
+                predicate: None,
+                table: None,
+                order_by: Column::Id,
+                descending: false,
+                limit: None,
+                warnings,
+            };
+            PrepareResult::Success(Box::new(statement))
+        }
+        Err(_) => {
+            // A well-formed statement whose all-digit id still failed to
+            // parse has overflowed i64
+            let tokens: Vec<&str> = input.split_whitespace().collect();
+            if tokens.len() == 4 {
+                return id_parse_error(tokens[1]);
+            }
+            PrepareResult::SyntaxError
+        }
+    }
+}
+
+// Why an id token failed to parse as a u32: a whole number outside the
+// range is reported as such, anything else is a syntax error
+fn id_parse_error(token: &str) -> PrepareResult {
+    let digits = token.strip_prefix('-').unwrap_or(token);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return PrepareResult::SyntaxError;
+    }
+    if token.starts_with('-') {
+        PrepareResult::NegativeId
+    } else {
+        PrepareResult::IdOutOfRange
+    }
+}
+
+// 'delete where id = <x>' matches select's WHERE syntax; 'delete <x>' is
+// shorthand for it, and both prepare the same statement
+fn prepare_delete(input: &str) -> PrepareResult {
+    let id = match scan_fmt!(input, "delete where id = {}", u32) {
+        Ok(id) => id,
+        Err(_) => match scan_fmt!(input, "delete {}", u32) {
+            Ok(id) => id,
+            Err(_) => {
+                let token = scan_fmt!(input, "delete where id = {}", String)
+                    .or_else(|_| scan_fmt!(input, "delete {}", String));
+                return token.map_or(PrepareResult::SyntaxError, |token| id_parse_error(&token));
+            }
+        },
+    };
+
+    PrepareResult::Success(Box::new(Statement {
+        statement_type: StatementType::Delete,
+        row_to_insert: None,
+        predicate: Some(Predicate::IdEquals(id)),
+        table: None,
+        order_by: Column::Id,
+        descending: false,
+        limit: None,
+        warnings: Vec::new(),
+    }))
+}
+
+fn prepare_statement(
+    input_buffer: &InputBuffer,
+    settings: &Settings,
+    widths: TextWidths,
+) -> PrepareResult {
+    let input = input_buffer.buffer.trim();
+
+    // insert into <name> ... and select from <name> ... parse as the same
+    // statement without the table, then carry the name to run it on
+    for (keyword, preposition) in [("insert", " into "), ("select", " from ")] {
+        let Some(rest) = input.strip_prefix(keyword).and_then(|rest| rest.strip_prefix(preposition))
+        else {
+            continue;
+        };
+        let (name, rest) = rest.trim_start().split_once(' ').unwrap_or((rest.trim_start(), ""));
+        let mut inner = InputBuffer::new();
+        inner.buffer = format!("{} {}", keyword, rest);
+        inner.input_length = inner.buffer.len();
+        return match prepare_statement(&inner, settings, widths) {
+            PrepareResult::Success(mut statement) => match statement.statement_type {
+                StatementType::Insert(OnConflict::Abort) | StatementType::Select => {
+                    statement.table = Some(name.to_string());
+                    PrepareResult::Success(statement)
+                }
+                _ => PrepareResult::SyntaxError,
+            },
+            result => result,
+        };
+    }
+
+    // The conflict clause has to be checked before a bare insert matches
+    for (keyword, on_conflict) in [
+        ("insert or replace", OnConflict::Replace),
+        ("insert or ignore", OnConflict::Ignore),
+        ("insert", OnConflict::Abort),
+    ] {
+        if input.starts_with(keyword) {
+            let statement_type = StatementType::Insert(on_conflict);
+            return prepare_row_statement(input, keyword, statement_type, widths, settings.truncate);
+        }
+    }
+
+    if input.starts_with("update") {
+        let statement_type = StatementType::Update;
+        return prepare_row_statement(input, "update", statement_type, widths, settings.truncate);
+    }
+
+    // create table <name> [heap]: one word, where the schema form has '('
+    if let Some(rest) = input.strip_prefix("create table ").map(str::trim) {
+        let (name, heap) = match rest.strip_suffix(" heap") {
+            Some(name) => (name.trim_end(), true),
+            None => (rest, false),
+        };
+        if !name.starts_with('(') && !name.contains(char::is_whitespace) {
+            let problems = table_name_problems(name);
+            if !problems.is_empty() {
+                return PrepareResult::InvalidSchema(problems);
+            }
+            return PrepareResult::Success(Box::new(Statement {
+                statement_type: StatementType::CreateNamedTable(name.to_string(), heap),
+                row_to_insert: None,
+                predicate: None,
+                table: None,
+                order_by: Column::Id,
+                descending: false,
+                limit: None,
+                warnings: Vec::new(),
+            }));
+        }
+    }
+
+    if input.starts_with("create table") {
+        return match parse_create_table(input) {
+            Some(Ok(widths)) => PrepareResult::Success(Box::new(Statement {
+                statement_type: StatementType::CreateTable(widths),
+                row_to_insert: None,
+                predicate: None,
+                table: None,
+                order_by: Column::Id,
+                descending: false,
+                limit: None,
+                warnings: Vec::new(),
+            })),
+            Some(Err(result)) => result,
+            None => PrepareResult::SyntaxError,
+        };
+    }
+
+    if input.starts_with("delete") {
+        return prepare_delete(input);
+    }
+
+    // select [where <predicate>] [order by id|username asc|desc] [limit <n>]
+    let select_rest = input.strip_prefix("select");
+    if let Some(mut rest) = select_rest.filter(|rest| {
+        rest.is_empty()
+            || rest.starts_with(" where")
+            || rest.starts_with(" order by")
+            || rest.starts_with(" limit")
+    }) {
+        // Clauses are taken off the end, last one first
+        let mut limit = None;
+        if let Some((front, count)) = format!(" {}", rest).rsplit_once(" limit ") {
+            match count.trim().parse::<usize>() {
+                Ok(count) => limit = Some(count),
+                Err(_) => return PrepareResult::SyntaxError,
+            }
+            rest = &rest[..front.len().saturating_sub(1)];
+        }
+
+        let mut order_by = Column::Id;
+        let mut descending = false;
+        let direction = match rest.strip_suffix(" desc") {
+            Some(front) => Some((front, true)),
+            None => rest.strip_suffix(" asc").map(|front| (front, false)),
+        };
+        if let Some((front, desc)) = direction {
+            for column in [Column::Id, Column::Username] {
+                if let Some(front) = front.strip_suffix(&format!("order by {}", column.name())) {
+                    (rest, order_by, descending) = (front, column, desc);
+                    break;
+                }
+            }
+        }
+
+        let rest = rest.trim();
+        let predicate = if rest.is_empty() {
+            None
+        } else {
+            match rest.strip_prefix("where").and_then(|clause| parse_predicate(clause.trim())) {
+                Some(predicate) => Some(predicate),
+                None => return PrepareResult::SyntaxError,
+            }
+        };
+
+        let statement = Statement {
+            statement_type: StatementType::Select,
+            row_to_insert: None,
+            predicate,
+            table: None,
+            order_by,
+            descending,
+            limit,
+            warnings: Vec::new(),
+        };
+        return PrepareResult::Success(Box::new(statement));
+    }
+
+    if input.starts_with("select exists") {
+        let id = match scan_fmt!(input, "select exists {}", u32) {
+            Ok(id) => id,
+            Err(_) => {
+                let token = scan_fmt!(input, "select exists {}", String);
+                return token.map_or(PrepareResult::SyntaxError, |token| id_parse_error(&token));
+            }
+        };
+
+        let statement = Statement {
+            statement_type: StatementType::Exists,
+            row_to_insert: None,
+            predicate: Some(Predicate::IdEquals(id)),
+            table: None,
+            order_by: Column::Id,
+            descending: false,
+            limit: None,
+            warnings: Vec::new(),
+        };
+        return PrepareResult::Success(Box::new(statement));
+    }
+
+    if input.starts_with("pragma max_scan") {
+        let limit = match scan_fmt!(input, "pragma max_scan = {}", usize) {
+            Ok(limit) => limit,
+            Err(_) => return PrepareResult::SyntaxError,
+        };
+
+        let statement = Statement {
+            statement_type: StatementType::SetMaxScan(limit),
+            row_to_insert: None,
+            predicate: None,
+            table: None,
+            order_by: Column::Id,
+            descending: false,
+            limit: None,
+            warnings: Vec::new(),
+        };
+        return PrepareResult::Success(Box::new(statement));
+    }
+
+    if input.starts_with("pragma cache_size") {
+        let pages = match scan_fmt!(input, "pragma cache_size = {}", usize) {
+            Ok(pages) => pages,
+            Err(_) => return PrepareResult::SyntaxError,
+        };
+
+        let statement = Statement {
+            statement_type: StatementType::SetCacheSize(pages),
+            row_to_insert: None,
+            predicate: None,
+            table: None,
+            order_by: Column::Id,
+            descending: false,
+            limit: None,
+            warnings: Vec::new(),
+        };
+        return PrepareResult::Success(Box::new(statement));
+    }
+
+    if let Ok(column) = scan_fmt!(input, "select count(distinct {})", String) {
+        let name = column.trim_end_matches(')');
+        let column = match Column::ALL.into_iter().find(|column| column.name() == name) {
+            Some(column) => column,
+            None => return PrepareResult::SyntaxError,
+        };
+
+        let statement = Statement {
+            statement_type: StatementType::CountDistinct(column),
+            row_to_insert: None,
+            predicate: None,
+            table: None,
+            order_by: Column::Id,
+            descending: false,
+            limit: None,
+            warnings: Vec::new(),
+        };
+        return PrepareResult::Success(Box::new(statement));
+    }
+
+    // 'select count' is shorthand for 'select count(*)'
+    let count_rest = input
+        .strip_prefix("select count(*)")
+        .or_else(|| input.strip_prefix("select count"));
+    if let Some(rest) = count_rest {
+        let rest = rest.trim();
+        let predicate = if rest.is_empty() {
+            None
+        } else {
+            match rest.strip_prefix("where").and_then(|clause| parse_predicate(clause.trim())) {
+                Some(predicate) => Some(predicate),
+                None => return PrepareResult::SyntaxError,
+            }
+        };
+
+        let statement = Statement {
+            statement_type: StatementType::Count,
+            row_to_insert: None,
+            predicate,
+            table: None,
+            order_by: Column::Id,
+            descending: false,
+            limit: None,
+            warnings: Vec::new(),
+        };
+        return PrepareResult::Success(Box::new(statement));
+    }
+
+    PrepareResult::UnrecognizedStatement
+}
+
+// Fast path for sequential inserts: when the key sorts after every key in
+// the rightmost leaf and that leaf has room, the insert position is the end
+// of that leaf. The leaf is cached between inserts; after a split clears
+// the cache, table_end finds it again by following right children only.
+fn append_position(table: &mut Table, key: u32) -> Option<(usize, usize)> {
+    let page_num = match table.rightmost_leaf {
+        Some(page_num) => page_num,
+        None => table_end(table).ok()?.page_num,
+    };
+    let node = get_page(&mut table.pager, page_num).ok()?;
+    let num_cells = leaf_node_num_cells(node) as usize;
+
+    if num_cells >= table.leaf_max_cells {
+        return None;
+    }
+    if num_cells == 0 {
+        // Only an empty root leaf is known to hold the whole key range
+        if page_num != table.root_page_num {
+            return None;
+        }
+    } else if key <= leaf_node_key(node, num_cells - 1) {
+        return None;
+    }
+
+    Some((page_num, num_cells))
+}
+
+/* Secondary Indexes */
+
+// FNV-1a over the ASCII-lowercased value, so values that differ only in
+// case share a bucket and an index also serves .nocase lookups
+fn index_hash(value: &[u8]) -> u32 {
+    value.iter().fold(0x811c_9dc5u32, |hash, &b| {
+        (hash ^ b.to_ascii_lowercase() as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+// Each indexed column's hash for a serialized row, in INDEXED_COLUMNS order
+fn row_index_hashes(row: RowRef) -> [u32; INDEXED_COLUMNS.len()] {
+    INDEXED_COLUMNS.map(|column| index_hash(row.text_bytes(column)))
+}
+
+// Root page of the column's index, 0 when it has none
+fn index_root(pager: &Pager, column: Column) -> usize {
+    pager.index_roots[column as usize] as usize
+}
+
+// The bucket for hash as (count, ids), or None when there is no cell for it
+fn index_bucket(
+    table: &mut Table,
+    root_page_num: usize,
+    hash: u32,
+) -> Result<Option<(u32, Vec<u32>)>, DbError> {
+    let cursor = tree_find(table, root_page_num, hash as usize)?;
+    let (page_num, cell_num) = (cursor.page_num, cursor.cell_num);
+    let node = get_page(&mut cursor.table.pager, page_num)?;
+    if cell_num >= leaf_node_num_cells(node) as usize || leaf_node_key(node, cell_num) != hash {
+        return Ok(None);
+    }
+
+    let bucket = leaf_node_value_checked(node, page_num, cell_num)?;
+    let count = get_u32_at(bucket, 0);
+    let ids = (0..(count as usize).min(INDEX_BUCKET_IDS))
+        .map(|i| get_u32_at(bucket, (i + 1) * size_of::<u32>()))
+        .collect();
+    Ok(Some((count, ids)))
+}
+
+// Store a bucket, adding a cell for hash if it has none yet
+fn write_index_bucket(
+    table: &mut Table,
+    root_page_num: usize,
+    hash: u32,
+    count: u32,
+    ids: &[u32],
+) -> Result<(), DbError> {
+    let mut bucket = [0u8; LEAF_NODE_VALUE_SIZE];
+    set_u32_at(&mut bucket, 0, count);
+    for (i, &id) in ids.iter().enumerate() {
+        set_u32_at(&mut bucket, (i + 1) * size_of::<u32>(), id);
+    }
+
+    let mut cursor = tree_find(table, root_page_num, hash as usize)?;
+    let (page_num, cell_num) = (cursor.page_num, cursor.cell_num);
+    let node = get_page_mut(&mut cursor.table.pager, page_num)?;
+    if cell_num < leaf_node_num_cells(node) as usize && leaf_node_key(node, cell_num) == hash {
+        let value_offset = leaf_node_cell_offset(node, cell_num) + LEAF_NODE_KEY_SIZE;
+        node[value_offset..value_offset + LEAF_NODE_VALUE_SIZE].copy_from_slice(&bucket);
+        return Ok(());
+    }
+    leaf_node_insert(&mut cursor, hash, &bucket)
+}
+
+fn index_add(table: &mut Table, column: Column, hash: u32, id: u32) -> Result<(), DbError> {
+    let root_page_num = index_root(&table.pager, column);
+    if root_page_num == 0 {
+        return Ok(());
+    }
+    let bucket = index_bucket(table, root_page_num, hash)?;
+    let (count, mut ids) = bucket.unwrap_or((0, Vec::new()));
+    if count == INDEX_OVERFLOW {
+        return Ok(());
+    }
+    let Err(position) = ids.binary_search(&id) else {
+        return Ok(());
+    };
+    if ids.len() == INDEX_BUCKET_IDS {
+        return write_index_bucket(table, root_page_num, hash, INDEX_OVERFLOW, &[]);
+    }
+    ids.insert(position, id);
+    write_index_bucket(table, root_page_num, hash, ids.len() as u32, &ids)
+}
+
+// An emptied bucket keeps its cell; the index never shrinks
+fn index_remove(table: &mut Table, column: Column, hash: u32, id: u32) -> Result<(), DbError> {
+    let root_page_num = index_root(&table.pager, column);
+    if root_page_num == 0 {
+        return Ok(());
+    }
+    let Some((count, mut ids)) = index_bucket(table, root_page_num, hash)? else {
+        return Ok(());
+    };
+    if count == INDEX_OVERFLOW {
+        return Ok(());
+    }
+    if let Ok(position) = ids.binary_search(&id) {
+        ids.remove(position);
+        write_index_bucket(table, root_page_num, hash, ids.len() as u32, &ids)?;
+    }
+    Ok(())
+}
+
+// Add a row's id to every index, under the hashes from row_index_hashes
+fn index_add_row(
+    table: &mut Table,
+    hashes: [u32; INDEXED_COLUMNS.len()],
+    id: u32,
+) -> Result<(), DbError> {
+    for (column, hash) in INDEXED_COLUMNS.into_iter().zip(hashes) {
+        index_add(table, column, hash, id)?;
+    }
+    Ok(())
+}
+
+fn index_remove_row(
+    table: &mut Table,
+    hashes: [u32; INDEXED_COLUMNS.len()],
+    id: u32,
+) -> Result<(), DbError> {
+    for (column, hash) in INDEXED_COLUMNS.into_iter().zip(hashes) {
+        index_remove(table, column, hash, id)?;
+    }
+    Ok(())
+}
+
+// Ids of the rows that may hold this value in the column, in ascending
+// order, or None when the table has to be scanned instead: the column has
+// no index, or the bucket overflowed. Ids sharing only the hash are among
+// them, so callers still compare each row's value.
+fn index_lookup(
+    table: &mut Table,
+    column: Column,
+    value: &[u8],
+) -> Result<Option<Vec<u32>>, DbError> {
+    let root_page_num = index_root(&table.pager, column);
+    if root_page_num == 0 {
+        return Ok(None);
+    }
+    match index_bucket(table, root_page_num, index_hash(value))? {
+        Some((INDEX_OVERFLOW, _)) => Ok(None),
+        Some((_, ids)) => Ok(Some(ids)),
+        None => Ok(Some(Vec::new())),
+    }
+}
+
+// .createindex <column>: give the index an empty root leaf, add every row
+// to it, then record the root in the header. Pages are synced before the
+// header names them, so a crash in between loses only the unused pages.
+// Returns how many rows were indexed.
+fn create_index(table: &mut Table, column: Column) -> Result<usize, DbError> {
+    if index_root(&table.pager, column) != 0 {
+        return Err(DbError::IndexExists { column: column.name() });
+    }
+    if table.pager.transaction.is_some() {
+        return Err(DbError::TransactionOpen);
+    }
+
+    let mut entries = Vec::new();
+    let mut cursor = table_start(table)?;
+    while !cursor.end_of_table {
+        let row = RowRef::new(cursor_value(&mut cursor)?);
+        if !row.is_tombstone() {
+            entries.push((index_hash(row.text_bytes(column)), row.id()));
+        }
+        cursor_advance(&mut cursor)?;
+    }
+
+    let root_page_num = get_unused_page_num(&mut table.pager)?;
+    let root = get_page_mut(&mut table.pager, root_page_num)?;
+    initialize_leaf_node(root);
+    set_node_root(root, true);
+    table.pager.index_roots[column as usize] = root_page_num as u32;
+    for &(hash, id) in &entries {
+        index_add(table, column, hash, id)?;
+    }
+
+    pager_sync(&mut table.pager)?;
+    let pager = &mut table.pager;
+    let header_offset = index_root_header_offset(column);
+    if let (Some(file), true) = (pager.file_descriptor.as_mut(), pager.data_offset > 0) {
+        file.seek(SeekFrom::Start(header_offset as u64))?;
+        file.write_all(&(root_page_num as u32).to_le_bytes())?;
+        file.sync_all()?;
+    }
+    Ok(entries.len())
+}
+
+// Where the file header records the root of the column's index
+fn index_root_header_offset(column: Column) -> usize {
+    match column {
+        Column::Username => FILE_HEADER_USERNAME_INDEX_OFFSET,
+        Column::Email => FILE_HEADER_EMAIL_INDEX_OFFSET,
+        Column::Id => unreachable!("the id is the table's own key"),
+    }
+}
+
+fn execute_insert(statement: &Statement, table: &mut Table) -> Result<ExecuteResult, DbError> {
+    let row_to_insert = match &statement.row_to_insert {
+        Some(row) => row,
+        None => return Ok(ExecuteResult::TableFull),
+    };
+
+    let key_to_insert = row_to_insert.id;
+    let mut cursor = match append_position(table, key_to_insert) {
+        Some((page_num, cell_num)) => Cursor {
+            table,
+            page_num,
+            cell_num,
+            end_of_table: true,
+        },
+        None => table_find(table, key_to_insert as usize)?,
+    };
+
+    // Get page again to check for duplicate keys
+    let page_num = cursor.page_num;
+    let node = get_page(&mut cursor.table.pager, page_num)?;
+
+    let num_cells = leaf_node_num_cells(node);
+
+    // Remember the rightmost leaf for the next append; a split will clear it
+    if get_leaf_node_next_leaf(node) == NO_LEAF {
+        cursor.table.rightmost_leaf = Some(page_num);
+    }
+
+    let at_key = cursor.cell_num < num_cells as usize
+        && leaf_node_key(node, cursor.cell_num) == key_to_insert;
+    if at_key {
+        let cell_num = cursor.cell_num;
+        if is_tombstone(leaf_row(&mut cursor.table.pager, page_num, cell_num)?) {
+            // A soft-deleted row's cell is taken over by the new one
+            write_row_value(cursor.table, page_num, cell_num, row_to_insert)?;
+            index_add_row(cursor.table, row_to_insert.index_hashes(), row_to_insert.id)?;
+            return Ok(ExecuteResult::Success);
+        }
+        let StatementType::Insert(on_conflict) = statement.statement_type else {
+            return Ok(ExecuteResult::DuplicateKey);
+        };
+        return match on_conflict {
+            OnConflict::Abort => Ok(ExecuteResult::DuplicateKey),
+            OnConflict::Replace => execute_update(statement, cursor.table),
+            OnConflict::Ignore => Ok(ExecuteResult::Success),
+        };
+    }
+
+    // A bounded table never splits, so a full leaf means a full table
+    if cursor.table.settings.bounded && num_cells as usize >= cursor.table.leaf_max_cells {
+        return Ok(ExecuteResult::TableFull);
+    }
+
+    // A heap-organized table appends the row to its heap and keeps only the
+    // locator in the leaf
+    if cursor.table.heap_page_num != 0 {
+        let locator = heap_append(cursor.table, row_to_insert)?;
+        leaf_node_insert(&mut cursor, row_to_insert.id, &locator)?;
+        cursor.table.pager.logical_bytes_written += (LEAF_NODE_KEY_SIZE + HEAP_LOCATOR_SIZE) as u64;
+        return Ok(ExecuteResult::Success);
+    }
+
+    let mut value = [0u8; LEAF_NODE_VALUE_SIZE];
+    serialize_row(row_to_insert, &mut value);
+    leaf_node_insert(&mut cursor, row_to_insert.id, &value)?;
+    cursor.table.pager.logical_bytes_written += LEAF_NODE_CELL_SIZE as u64;
+    index_add_row(cursor.table, row_to_insert.index_hashes(), row_to_insert.id)?;
+
+    Ok(ExecuteResult::Success)
+}
+
+
+// A bare select walks every leaf from table_start. With an id predicate
+// the scan starts at table_find(lo), the first key >= lo, and stops at the
+// first key past hi, so a point lookup reads one root-to-leaf path. An
+// equality on an indexed text column fetches only the ids its index
+// gives; other text predicates scan the whole table and filter rows as
+// they go. Each matching row is passed to on_row in scan order.
+fn select_rows(
+    statement: &Statement,
+    table: &mut Table,
+    on_row: &mut dyn FnMut(Row),
+) -> Result<ExecuteResult, DbError> {
+    if let Column::Username = statement.order_by {
+        return select_by_username(statement, table, on_row);
+    }
+
+    let max_scan = table.settings.max_scan;
+    let predicate = statement.predicate.as_ref();
+    let (lo, hi) = predicate.and_then(Predicate::id_bounds).unwrap_or((0, u32::MAX));
+
+    // A username or email lookup goes through that column's index when
+    // there is one: the index gives the ids, then each row is fetched from
+    // the table by id
+    let indexed = match predicate {
+        Some(Predicate::UsernameEquals(username)) => Some((Column::Username, username)),
+        Some(Predicate::EmailEquals(email)) => Some((Column::Email, email)),
+        _ => None,
+    };
+    if let Some((column, value)) = indexed {
+        if let Some(ids) = index_lookup(table, column, value.as_bytes())? {
+            return select_ids(statement, table, ids, on_row);
+        }
+    }
+
+    let mut row_count = 0;
+    let mut scanned = 0;
+
+    if lo <= hi {
+        let descending = statement.descending;
+        let mut cursor = if descending {
+            // The insert position of hi + 1 is just past the last key <= hi,
+            // so one step back lands on it
+            let mut cursor = match hi.checked_add(1) {
+                Some(past_hi) => table_find(table, past_hi as usize)?,
+                None => table_end(table)?,
+            };
+            cursor_retreat(&mut cursor)?;
+            cursor
+        } else if predicate.is_some() {
+            let mut cursor = table_find(table, lo as usize)?;
+            cursor.end_of_table = false;
+            cursor_skip_empty_leaves(&mut cursor)?;
+            cursor
+        } else {
+            table_start(table)?
+        };
+
+        while !cursor.end_of_table {
+            if statement.limit.is_some_and(|limit| row_count >= limit) {
+                break;
+            }
+
+            let page_num = cursor.page_num;
+            let cell_num = cursor.cell_num;
+            // The range ends on the leaf's key, so a heap table doesn't
+            // fetch the row past the end
+            let node = get_page(&mut cursor.table.pager, page_num)?;
+            let key = leaf_node_key_checked(node, page_num, cell_num)?;
+            if descending && key < lo || !descending && key > hi {
+                break;
+            }
+            let slot = leaf_row(&mut cursor.table.pager, page_num, cell_num)?;
+            let row_ref = RowRef::new(slot);
+            // The key that ends the range is not counted as scanned
+            if max_scan.is_some_and(|limit| scanned >= limit) {
+                return Ok(ExecuteResult::ScanLimitExceeded);
+            }
+            scanned += 1;
+            let live = !row_ref.is_tombstone();
+            if live && predicate.is_none_or(|p| p.matches(&row_ref, &cursor.table.settings)) {
+                if cursor.table.settings.strict_utf8 {
+                    check_row_utf8(&row_ref, page_num, cell_num)?;
+                }
+                on_row(Row::deserialize(slot));
+                row_count += 1;
+            }
+            if descending {
+                cursor_retreat(&mut cursor)?;
+            } else {
+                cursor_advance(&mut cursor)?;
+            }
+        }
+    }
+
+    Ok(ExecuteResult::Success)
+}
+
+// select_rows for candidate ids from an index, given in ascending order.
+// Each id is looked up with table_find and its row still has to match the
+// predicate; max_scan counts the lookups.
+// order by username: the matching rows are gathered in id order, then
+// sorted by the username's bytes with a stable sort, so rows with equal
+// usernames keep ascending id order in both directions and the output is
+// the same every run. NULL usernames sort first, as the empty string does.
+fn select_by_username(
+    statement: &Statement,
+    table: &mut Table,
+    on_row: &mut dyn FnMut(Row),
+) -> Result<ExecuteResult, DbError> {
+    let by_id =
+        Statement { order_by: Column::Id, descending: false, limit: None, ..statement.clone() };
+    let mut rows = Vec::new();
+    let result = select_rows(&by_id, table, &mut |row| rows.push(row))?;
+    if !matches!(result, ExecuteResult::Success) {
+        return Ok(result);
+    }
+
+    rows.sort_by(|a, b| {
+        let order = a.text_bytes(Column::Username).cmp(b.text_bytes(Column::Username));
+        if statement.descending { order.reverse() } else { order }
+    });
+    rows.into_iter().take(statement.limit.unwrap_or(usize::MAX)).for_each(on_row);
+    Ok(ExecuteResult::Success)
+}
+
+fn select_ids(
+    statement: &Statement,
+    table: &mut Table,
+    mut ids: Vec<u32>,
+    on_row: &mut dyn FnMut(Row),
+) -> Result<ExecuteResult, DbError> {
+    let max_scan = table.settings.max_scan;
+    if statement.descending {
+        ids.reverse();
+    }
+
+    let mut row_count = 0;
+    for (scanned, id) in ids.into_iter().enumerate() {
+        if statement.limit.is_some_and(|limit| row_count >= limit) {
+            break;
+        }
+        if max_scan.is_some_and(|limit| scanned >= limit) {
+            return Ok(ExecuteResult::ScanLimitExceeded);
+        }
+
+        let cursor = table_find(table, id as usize)?;
+        let (page_num, cell_num) = (cursor.page_num, cursor.cell_num);
+        let node = get_page(&mut cursor.table.pager, page_num)?;
+        if cell_num >= leaf_node_num_cells(node) as usize || leaf_node_key(node, cell_num) != id {
+            continue;
+        }
+        let slot = leaf_row(&mut cursor.table.pager, page_num, cell_num)?;
+        if is_tombstone(slot) {
+            continue;
+        }
+
+        let row_ref = RowRef::new(slot);
+        let settings = &cursor.table.settings;
+        if statement.predicate.as_ref().is_none_or(|p| p.matches(&row_ref, settings)) {
+            if settings.strict_utf8 {
+                check_row_utf8(&row_ref, page_num, cell_num)?;
+            }
+            on_row(Row::deserialize(slot));
+            row_count += 1;
+        }
+    }
+
+    Ok(ExecuteResult::Success)
+}
+
+fn execute_select(statement: &Statement, table: &mut Table) -> Result<ExecuteResult, DbError> {
+    let output_mode = table.settings.output_mode;
+    let mut row_count = 0;
+    let mut out = table.take_out();
+    let result = select_rows(statement, table, &mut |row| {
+        print_row(&mut out, &row, output_mode);
+        row_count += 1;
+    });
+    table.out = out;
+    let result = result?;
+
+    // A count line would break the one-object-per-line JSON output
+    let footer = table.settings.footer && !matches!(output_mode, OutputMode::Json);
+    if footer && matches!(result, ExecuteResult::Success) {
+        outln!(table.out, "({} {})", row_count, if row_count == 1 { "row" } else { "rows" });
+    }
+    Ok(result)
+}
+
+fn print_row(out: &mut dyn Write, row: &Row, mode: OutputMode) {
+    outln!(out, "{}", format_row(row, mode));
+}
+
+// NULL is written the way each mode's readers expect: NULL in list and hex
+// output, null in JSON and an empty unquoted field in CSV, where an empty
+// string is "" instead
+fn format_row(row: &Row, mode: OutputMode) -> String {
+    let [username, email] = [
+        (Column::Username, row.get_username(), &row.username[..]),
+        (Column::Email, row.get_email(), &row.email[..]),
+    ]
+    .map(|(column, text, bytes)| {
+        if row.is_null(column) {
+            let null = match mode {
+                OutputMode::List | OutputMode::Hex => "NULL",
+                OutputMode::Json => "null",
+                OutputMode::Csv => "",
+            };
+            return null.to_string();
+        }
+        match mode {
+            OutputMode::List => list_field(&text),
+            OutputMode::Json => json_string(&text),
+            OutputMode::Csv => csv_field(&text),
+            OutputMode::Hex => hex_literal(bytes),
+        }
+    });
+    match mode {
+        OutputMode::List | OutputMode::Hex => format!("({}, {}, {})", row.id, username, email),
+        OutputMode::Json => format!(
+            "{{\"id\":{},\"username\":{},\"email\":{}}}",
+            row.id, username, email
+        ),
+        OutputMode::Csv => format!("{},{},{}", row.id, username, email),
+    }
+}
+
+// A column's stored bytes, up to the NUL padding, as an x'...' literal
+fn hex_literal(column: &[u8]) -> String {
+    let end = column.iter().position(|&b| b == 0).unwrap_or(column.len());
+    let digits: String = column[..end].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("x'{}'", digits)
+}
+
+// A text value for the (id, username, email) format, escaped so the line
+// always splits back into three fields: the delimiters ',' and ')' and the
+// backslash get a backslash, and control characters use their short
+// escape or \u00XX
+fn list_field(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ',' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// A JSON string literal: quotes and backslashes are escaped, and control
+// characters use their short escape or \u00XX
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Count the rows matching a predicate without deserializing them. Id
+// predicates scan only the matching key range; text predicates scan the
+// whole table but only compare the filtered column's bytes.
+fn count_where(table: &mut Table, predicate: &Predicate) -> Result<Option<usize>, DbError> {
+    let (lo, hi) = match predicate.id_bounds() {
+        Some((lo, hi)) if lo > hi => return Ok(Some(0)),
+        Some(bounds) => bounds,
+        None => (0, u32::MAX),
+    };
+
+    let max_scan = table.settings.max_scan;
+    let mut cursor = table_find(table, lo as usize)?;
+    let mut count = 0;
+    let mut scanned = 0;
+
+    loop {
+        let node = get_page(&mut cursor.table.pager, cursor.page_num)?;
+        // table_find can land one past the last cell of a leaf
+        if cursor.cell_num >= leaf_node_num_cells(node) as usize {
+            let next_page_num = get_leaf_node_next_leaf(node);
+            if next_page_num == NO_LEAF {
+                break;
+            }
+            cursor.page_num = next_page_num as usize;
+            cursor.cell_num = 0;
+            continue;
+        }
+
+        let key = leaf_node_key_checked(node, cursor.page_num, cursor.cell_num)?;
+        if key > hi {
+            break;
+        }
+        if max_scan.is_some_and(|limit| scanned >= limit) {
+            return Ok(None);
+        }
+        scanned += 1;
+        let value = leaf_node_value_checked(node, cursor.page_num, cursor.cell_num)?;
+        if !is_tombstone(value) && predicate.matches(&RowRef::new(value), &cursor.table.settings) {
+            count += 1;
+        }
+
+        cursor.cell_num += 1;
+    }
+
+    Ok(Some(count))
+}
+
+fn execute_count(statement: &Statement, table: &mut Table) -> Result<ExecuteResult, DbError> {
+    let max_scan = table.settings.max_scan;
+    let count = match &statement.predicate {
+        Some(predicate) => count_where(table, predicate)?,
+        None => count_rows(table, max_scan)?,
+    };
+    match count {
+        Some(count) => Ok(ExecuteResult::Count(count)),
+        None => Ok(ExecuteResult::ScanLimitExceeded),
+    }
+}
+
+// Row count from the leaf headers: sum num_cells along the leaf chain,
+// less the soft-deleted rows, without decoding any row; only each cell's
+// flags byte is read. None when the cells passed go over max_scan.
+fn count_rows(table: &mut Table, max_scan: Option<usize>) -> Result<Option<usize>, DbError> {
+    let mut count = 0;
+    let mut cells = 0;
+    let mut page_num = table_leftmost_leaf(table)?;
+    loop {
+        let node = get_page(&mut table.pager, page_num)?;
+        let num_cells = leaf_node_num_cells(node) as usize;
+        cells += num_cells;
+        if max_scan.is_some_and(|limit| cells > limit) {
+            return Ok(None);
+        }
+        for cell_num in 0..num_cells {
+            if !is_tombstone(leaf_node_value_checked(node, page_num, cell_num)?) {
+                count += 1;
+            }
+        }
+        match get_leaf_node_next_leaf(node) {
+            NO_LEAF => return Ok(Some(count)),
+            next_page_num => page_num = next_page_num as usize,
+        }
+    }
+}
+
+// Distinct values of a text column are collected in a set while scanning,
+// so memory grows with the number of distinct values, not the row count.
+// Ids are unique keys, so their distinct count is the row count.
+fn execute_count_distinct(column: Column, table: &mut Table) -> Result<ExecuteResult, DbError> {
+    let max_scan = table.settings.max_scan;
+    let mut cursor = table_start(table)?;
+    let mut distinct: HashSet<Vec<u8>> = HashSet::new();
+    let mut row_count = 0;
+
+    while !cursor.end_of_table {
+        if max_scan.is_some_and(|limit| row_count >= limit) {
+            return Ok(ExecuteResult::ScanLimitExceeded);
+        }
+        let row = RowRef::new(cursor_value(&mut cursor)?);
+        if row.is_tombstone() {
+            cursor_advance(&mut cursor)?;
+            continue;
+        }
+        // NULL is not a value, so like SQL it is not counted
+        match column {
+            Column::Id => {}
+            _ if row.is_null(column) => {}
+            Column::Username => {
+                distinct.insert(row.username_bytes().to_vec());
+            }
+            Column::Email => {
+                distinct.insert(row.email_bytes().to_vec());
+            }
+        }
+        row_count += 1;
+        cursor_advance(&mut cursor)?;
+    }
+
+    match column {
+        Column::Id => Ok(ExecuteResult::Count(row_count)),
+        _ => Ok(ExecuteResult::Count(distinct.len())),
+    }
+}
+
+// Remove the key's cell from its leaf by shifting the later cells left,
+// then merge the leaf into a neighbour if it is now underfull. Parent keys
+// stay valid as upper bounds for their subtrees. With .softdelete on the
+// row is only marked as a tombstone: the leaf keeps the cell and never
+// rebalances, and .vacuum clears the tombstones out in one pass.
+fn execute_delete(statement: &Statement, table: &mut Table) -> Result<ExecuteResult, DbError> {
+    let Some(Predicate::IdEquals(id)) = statement.predicate else {
+        return Ok(ExecuteResult::NotFound);
+    };
+
+    let cursor = table_find(table, id as usize)?;
+    let page_num = cursor.page_num;
+    let cell_num = cursor.cell_num;
+    let leaf_max_cells = cursor.table.leaf_max_cells;
+    let left_split_count = cursor.table.leaf_left_split_count();
+    let soft_delete = cursor.table.settings.soft_delete;
+    let node = get_page_mut(&mut cursor.table.pager, page_num)?;
+
+    let num_cells = leaf_node_num_cells(node) as usize;
+    if cell_num >= num_cells || leaf_node_key(node, cell_num) != id {
+        return Ok(ExecuteResult::NotFound);
+    }
+    let value = leaf_node_value_checked(node, page_num, cell_num)?;
+    if is_tombstone(value) {
+        return Ok(ExecuteResult::NotFound);
+    }
+    let hashes = row_index_hashes(RowRef::new(value));
+
+    if soft_delete {
+        let flags_offset =
+            leaf_node_cell_offset(node, cell_num) + LEAF_NODE_KEY_SIZE + ROW_FLAGS_OFFSET;
+        node[flags_offset] |= ROW_TOMBSTONE;
+        index_remove_row(cursor.table, hashes, id)?;
+        return Ok(ExecuteResult::Success);
+    }
+
+    node.copy_within(
+        leaf_node_cell_offset(node, cell_num + 1)..leaf_node_cell_offset(node, num_cells),
+        leaf_node_cell_offset(node, cell_num),
+    );
+    set_leaf_node_num_cells(node, num_cells as u32 - 1);
+
+    debug_assert_leaf_sorted(node, page_num, leaf_max_cells);
+
+    if num_cells - 1 < left_split_count && !is_node_root(node) {
+        leaf_node_merge(cursor.table, page_num)?;
+    }
+    index_remove_row(cursor.table, hashes, id)?;
+
+    Ok(ExecuteResult::Success)
+}
+
+// Where page_num hangs under its parent: the parent's page, how many keys
+// it has and which of its children page_num is (num_keys for the right
+// child)
+fn child_position(table: &mut Table, page_num: usize) -> Result<(usize, usize, usize), DbError> {
+    let parent_page_num = node_parent(get_page(&mut table.pager, page_num)?) as usize;
+    let parent = get_page(&mut table.pager, parent_page_num)?;
+    let num_keys = internal_node_num_keys(parent) as usize;
+    for child_num in 0..=num_keys {
+        if internal_node_child(parent, child_num)? as usize == page_num {
+            return Ok((parent_page_num, num_keys, child_num));
+        }
+    }
+    Err(DbError::CorruptNode { page_num: parent_page_num, cell_num: num_keys })
+}
+
+// The neighbour a child merges with or borrows from: the pair of adjacent
+// children (left, right) it belongs to, and the parent cell between them.
+// The right child pairs with its left neighbour, every other one with its
+// right neighbour.
+fn sibling_pair(
+    table: &mut Table,
+    parent_page_num: usize,
+    num_keys: usize,
+    child_num: usize,
+) -> Result<(usize, usize, usize), DbError> {
+    let left_num = if child_num < num_keys { child_num } else { child_num - 1 };
+    let parent = get_page(&mut table.pager, parent_page_num)?;
+    let left_page_num = internal_node_child(parent, left_num)? as usize;
+    let right_page_num = internal_node_child(parent, left_num + 1)? as usize;
+    Ok((left_num, left_page_num, right_page_num))
+}
+
+// After the right child of the pair at left_num was merged into the left
+// one: the left child takes over the right one's slot, and so its key, and
+// the left child's own cell goes
+fn remove_merged_child(
+    table: &mut Table,
+    parent_page_num: usize,
+    left_num: usize,
+    left_page_num: usize,
+) -> Result<(), DbError> {
+    let parent = get_page_mut(&mut table.pager, parent_page_num)?;
+    let num_keys = internal_node_num_keys(parent) as usize;
+    set_internal_node_child(parent, left_num + 1, left_page_num as u32);
+    parent.copy_within(
+        internal_node_cell_offset(left_num + 1)..internal_node_cell_offset(num_keys),
+        internal_node_cell_offset(left_num),
+    );
+    set_internal_node_num_keys(parent, num_keys as u32 - 1);
+    Ok(())
+}
+
+// Inverse of leaf_node_split_and_insert for a leaf that fell below the
+// left split count. When it and a neighbour under the same parent fit in
+// one node, the right leaf's cells move onto the end of the left one, the
+// left leaf takes over the right one's slot in the parent, and the right
+// page is freed. The parent has lost a key, so it is rebalanced in turn.
+fn leaf_node_merge(table: &mut Table, page_num: usize) -> Result<(), DbError> {
+    let (parent_page_num, num_keys, child_num) = child_position(table, page_num)?;
+    // An only child has no neighbour to merge with. Rebalancing keeps every
+    // internal node but the root at one key or more, and a root with none
+    // is collapsed, so this is only reached on a damaged tree.
+    if num_keys == 0 {
+        return Ok(());
+    }
+    let (left_num, left_page_num, right_page_num) =
+        sibling_pair(table, parent_page_num, num_keys, child_num)?;
+
+    let (right_cells, right_next_leaf) = {
+        let right = get_page(&mut table.pager, right_page_num)?;
+        if node_type_checked(right, right_page_num)? != NodeType::Leaf {
+            return Ok(());
+        }
+        let num_cells = leaf_node_num_cells(right) as usize;
+        let cells = leaf_node_cell_offset(right, 0)..leaf_node_cell_offset(right, num_cells);
+        (
+            right[cells].to_vec(),
+            get_leaf_node_next_leaf(right),
+        )
+    };
+    let leaf_max_cells = table.leaf_max_cells;
+    {
+        let left = get_page_mut(&mut table.pager, left_page_num)?;
+        let left_cells = leaf_node_num_cells(left) as usize;
+        let right_num_cells = right_cells.len() / leaf_node_cell_size(left);
+        if left_cells + right_num_cells > leaf_max_cells {
+            return Ok(());
+        }
+        let offset = leaf_node_cell_offset(left, left_cells);
+        left[offset..offset + right_cells.len()].copy_from_slice(&right_cells);
+        set_leaf_node_num_cells(left, (left_cells + right_num_cells) as u32);
+        set_leaf_node_next_leaf(left, right_next_leaf);
+        debug_assert_leaf_sorted(left, left_page_num, leaf_max_cells);
+    }
+    if right_next_leaf != NO_LEAF {
+        let next_node = get_page_mut(&mut table.pager, right_next_leaf as usize)?;
+        set_leaf_node_prev_leaf(next_node, left_page_num as u32);
+    }
+
+    remove_merged_child(table, parent_page_num, left_num, left_page_num)?;
+
+    // The freed page may have been the cached append target
+    table.rightmost_leaf = None;
+    free_page(&mut table.pager, right_page_num)?;
+
+    internal_node_rebalance(table, parent_page_num)
+}
+
+// Called on an internal node that has just lost a key. A root left with no
+// keys gives its page to its only child, so the tree loses a level. Any
+// other node below the minimum merges with a neighbour when the two and
+// the parent key between them fit in one node, which takes a key out of
+// the parent in turn; otherwise it borrows one child from the neighbour.
+fn internal_node_rebalance(table: &mut Table, page_num: usize) -> Result<(), DbError> {
+    let node = get_page(&mut table.pager, page_num)?;
+    let num_keys = internal_node_num_keys(node) as usize;
+    if is_node_root(node) {
+        if num_keys == 0 {
+            collapse_root(table, page_num)?;
+        }
+        return Ok(());
+    }
+    if num_keys >= table.internal_min_keys() {
+        return Ok(());
+    }
+
+    let (parent_page_num, parent_keys, child_num) = child_position(table, page_num)?;
+    if parent_keys == 0 {
+        return Ok(());
+    }
+    let (left_num, left_page_num, right_page_num) =
+        sibling_pair(table, parent_page_num, parent_keys, child_num)?;
+    let left_keys = internal_node_num_keys(get_page(&mut table.pager, left_page_num)?) as usize;
+    let right_keys = internal_node_num_keys(get_page(&mut table.pager, right_page_num)?) as usize;
+
+    if left_keys + right_keys < table.internal_max_cells {
+        internal_node_merge(table, parent_page_num, left_num, left_page_num, right_page_num)?;
+        internal_node_rebalance(table, parent_page_num)
+    } else if page_num == left_page_num {
+        internal_node_borrow_right(table, parent_page_num, left_num, left_page_num, right_page_num)
+    } else {
+        internal_node_borrow_left(table, parent_page_num, left_num, left_page_num, right_page_num)
+    }
+}
+
+// Append the right node's cells to the left one, with the parent key that
+// separated them as the key of the left node's old right child, then free
+// the right page and drop the left node's cell from the parent
+fn internal_node_merge(
+    table: &mut Table,
+    parent_page_num: usize,
+    left_num: usize,
+    left_page_num: usize,
+    right_page_num: usize,
+) -> Result<(), DbError> {
+    let separator = internal_node_key(get_page(&mut table.pager, parent_page_num)?, left_num);
+    let right = get_page(&mut table.pager, right_page_num)?.to_vec();
+    let right_keys = internal_node_num_keys(&right) as usize;
+    let moved = (0..=right_keys)
+        .map(|i| internal_node_child(&right, i))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    {
+        let left = get_page_mut(&mut table.pager, left_page_num)?;
+        let left_keys = internal_node_num_keys(left) as usize;
+        let old_right_child = internal_node_right_child(left);
+        set_u32_at(left, internal_node_cell_offset(left_keys), old_right_child);
+        set_internal_node_key(left, left_keys, separator);
+        let cells_start = internal_node_cell_offset(left_keys + 1);
+        let cells = &right[internal_node_cell_offset(0)..internal_node_cell_offset(right_keys)];
+        left[cells_start..cells_start + cells.len()].copy_from_slice(cells);
+        set_internal_node_num_keys(left, (left_keys + 1 + right_keys) as u32);
+        set_internal_node_right_child(left, internal_node_right_child(&right));
+    }
+    for child in moved {
+        set_node_parent(get_page_mut(&mut table.pager, child as usize)?, left_page_num as u32);
+    }
+
+    remove_merged_child(table, parent_page_num, left_num, left_page_num)?;
+    free_page(&mut table.pager, right_page_num)
+}
+
+// Move the right node's first child to the end of the left node. The
+// parent key between them comes down as the key of the left node's old
+// right child, and the right node's first key goes up in its place.
+fn internal_node_borrow_right(
+    table: &mut Table,
+    parent_page_num: usize,
+    left_num: usize,
+    left_page_num: usize,
+    right_page_num: usize,
+) -> Result<(), DbError> {
+    let separator = internal_node_key(get_page(&mut table.pager, parent_page_num)?, left_num);
+    let (moved, new_separator) = {
+        let right = get_page_mut(&mut table.pager, right_page_num)?;
+        let right_keys = internal_node_num_keys(right) as usize;
+        let moved = internal_node_child(right, 0)?;
+        let new_separator = internal_node_key(right, 0);
+        right.copy_within(
+            internal_node_cell_offset(1)..internal_node_cell_offset(right_keys),
+            internal_node_cell_offset(0),
+        );
+        set_internal_node_num_keys(right, right_keys as u32 - 1);
+        (moved, new_separator)
+    };
+    {
+        let left = get_page_mut(&mut table.pager, left_page_num)?;
+        let left_keys = internal_node_num_keys(left) as usize;
+        let old_right_child = internal_node_right_child(left);
+        set_u32_at(left, internal_node_cell_offset(left_keys), old_right_child);
+        set_internal_node_key(left, left_keys, separator);
+        set_internal_node_num_keys(left, left_keys as u32 + 1);
+        set_internal_node_right_child(left, moved);
+    }
+    let parent = get_page_mut(&mut table.pager, parent_page_num)?;
+    set_internal_node_key(parent, left_num, new_separator);
+    set_node_parent(get_page_mut(&mut table.pager, moved as usize)?, left_page_num as u32);
+    Ok(())
+}
+
+// Move the left node's right child to the front of the right node. The
+// parent key between them comes down as that child's key, and the left
+// node's last key goes up in its place.
+fn internal_node_borrow_left(
+    table: &mut Table,
+    parent_page_num: usize,
+    left_num: usize,
+    left_page_num: usize,
+    right_page_num: usize,
+) -> Result<(), DbError> {
+    let separator = internal_node_key(get_page(&mut table.pager, parent_page_num)?, left_num);
+    let (moved, new_separator) = {
+        let left = get_page_mut(&mut table.pager, left_page_num)?;
+        let left_keys = internal_node_num_keys(left) as usize;
+        let moved = internal_node_right_child(left);
+        let new_separator = internal_node_key(left, left_keys - 1);
+        let new_right_child = internal_node_child(left, left_keys - 1)?;
+        set_internal_node_num_keys(left, left_keys as u32 - 1);
+        set_internal_node_right_child(left, new_right_child);
+        (moved, new_separator)
+    };
+    {
+        let right = get_page_mut(&mut table.pager, right_page_num)?;
+        let right_keys = internal_node_num_keys(right) as usize;
+        right.copy_within(
+            internal_node_cell_offset(0)..internal_node_cell_offset(right_keys),
+            internal_node_cell_offset(1),
+        );
+        set_u32_at(right, internal_node_cell_offset(0), moved);
+        set_internal_node_key(right, 0, separator);
+        set_internal_node_num_keys(right, right_keys as u32 + 1);
+    }
+    let parent = get_page_mut(&mut table.pager, parent_page_num)?;
+    set_internal_node_key(parent, left_num, new_separator);
+    set_node_parent(get_page_mut(&mut table.pager, moved as usize)?, right_page_num as u32);
+    Ok(())
+}
+
+// A root with no keys has one child. The child's contents move up into the
+// root's page, which never changes, and the child's page is freed.
+fn collapse_root(table: &mut Table, root_page_num: usize) -> Result<(), DbError> {
+    let root = get_page(&mut table.pager, root_page_num)?;
+    let child_page_num = internal_node_child(root, 0)? as usize;
+    let child = get_page(&mut table.pager, child_page_num)?.to_vec();
+    let grandchildren = match node_type_checked(&child, child_page_num)? {
+        NodeType::Leaf => Vec::new(),
+        NodeType::Internal => (0..=internal_node_num_keys(&child) as usize)
+            .map(|i| internal_node_child(&child, i))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+
+    let root = get_page_mut(&mut table.pager, root_page_num)?;
+    root.copy_from_slice(&child);
+    set_node_root(root, true);
+    if grandchildren.is_empty() {
+        set_leaf_node_next_leaf(root, NO_LEAF);
+        set_leaf_node_prev_leaf(root, NO_LEAF);
+    }
+    for grandchild in grandchildren {
+        set_node_parent(get_page_mut(&mut table.pager, grandchild as usize)?, root_page_num as u32);
+    }
+
+    table.rightmost_leaf = None;
+    free_page(&mut table.pager, child_page_num)
+}
+
+// Overwrite the value bytes of an existing row; the cell's key and the
+// tree shape are left untouched
+fn execute_update(statement: &Statement, table: &mut Table) -> Result<ExecuteResult, DbError> {
+    let Some(row) = &statement.row_to_insert else {
+        return Ok(ExecuteResult::NotFound);
+    };
+
+    let cursor = table_find(table, row.id as usize)?;
+    let page_num = cursor.page_num;
+    let cell_num = cursor.cell_num;
+    let node = get_page_mut(&mut cursor.table.pager, page_num)?;
+
+    if cell_num >= leaf_node_num_cells(node) as usize {
+        return Ok(ExecuteResult::NotFound);
+    }
+    // Only the value is rewritten, so the key found must be the row's id
+    if leaf_node_key(node, cell_num) != row.id {
+        return Ok(ExecuteResult::NotFound);
+    }
+
+    let old_value = leaf_node_value_checked(node, page_num, cell_num)?;
+    if is_tombstone(old_value) {
+        return Ok(ExecuteResult::NotFound);
+    }
+    let old_hashes = row_index_hashes(RowRef::new(old_value));
+    write_row_value(cursor.table, page_num, cell_num, row)?;
+
+    let new_hashes = row.index_hashes();
+    for (i, column) in INDEXED_COLUMNS.into_iter().enumerate() {
+        if new_hashes[i] != old_hashes[i] {
+            index_remove(cursor.table, column, old_hashes[i], row.id)?;
+            index_add(cursor.table, column, new_hashes[i], row.id)?;
+        }
+    }
+
+    Ok(ExecuteResult::Success)
+}
+
+// Overwrite the value of the cell with row, clearing any tombstone; the
+// key stays as it is
+fn write_row_value(
+    table: &mut Table,
+    page_num: usize,
+    cell_num: usize,
+    row: &Row,
+) -> Result<(), DbError> {
+    let node = get_page_mut(&mut table.pager, page_num)?;
+    let value_offset = leaf_node_cell_offset(node, cell_num) + LEAF_NODE_KEY_SIZE;
+    serialize_row(row, &mut node[value_offset..value_offset + LEAF_NODE_VALUE_SIZE]);
+    table.pager.logical_bytes_written += LEAF_NODE_VALUE_SIZE as u64;
+    Ok(())
+}
+
+// create table: set the text column widths. Only an empty table can change
+// them, since stored rows might not fit the new ones. Statements cached
+// under the old widths are dropped.
+fn execute_create_table(widths: TextWidths, table: &mut Table) -> Result<ExecuteResult, DbError> {
+    if table.pager.transaction.is_some() {
+        return Err(DbError::TransactionOpen);
+    }
+    if !table_start(table)?.end_of_table {
+        return Err(DbError::TableNotEmpty);
+    }
+    set_text_widths(&mut table.pager, widths)?;
+    table.statement_cache.clear();
+    Ok(ExecuteResult::Success)
+}
+
+// Record the text column widths, writing them to the header straight away
+// as create_index does an index root
+fn set_text_widths(pager: &mut Pager, widths: TextWidths) -> Result<(), DbError> {
+    pager.text_widths = widths;
+    if let (Some(file), true) = (pager.file_descriptor.as_mut(), pager.data_offset > 0) {
+        let mut header = [0u8; FILE_HEADER_COLUMN_WIDTHS_END];
+        write_schema(&mut header, widths);
+        file.seek(SeekFrom::Start(FILE_HEADER_NUM_COLUMNS_OFFSET as u64))?;
+        file.write_all(&header[FILE_HEADER_NUM_COLUMNS_OFFSET..])?;
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+// The directory entries in a header, in the order they were created
+fn read_table_directory(header: &[u8]) -> Vec<NamedTable> {
+    let count = (get_u32_at(header, FILE_HEADER_TABLE_COUNT_OFFSET) as usize).min(MAX_TABLES);
+    (0..count)
+        .map(|i| {
+            let entry = &header[FILE_HEADER_TABLES_OFFSET + i * TABLE_ENTRY_SIZE..];
+            NamedTable {
+                name: String::from_utf8_lossy(trim_nulls(&entry[..TABLE_NAME_SIZE])).to_string(),
+                root_page_num: get_u32_at(entry, TABLE_NAME_SIZE),
+                heap_page_num: get_u32_at(entry, TABLE_NAME_SIZE + size_of::<u32>()),
+            }
+        })
+        .collect()
+}
+
+// create table <name> [heap]: give the table an empty root leaf, and a
+// heap table its first heap page too, and add it to the directory. Like
+// create_index, the pages are synced before the header names them.
+fn create_named_table(table: &mut Table, name: &str, heap: bool) -> Result<(), DbError> {
+    if table.pager.tables.iter().any(|named| named.name == name) {
+        return Err(DbError::TableExists { name: name.to_string() });
+    }
+    if table.pager.tables.len() == MAX_TABLES {
+        return Err(DbError::TooManyTables);
+    }
+    if table.pager.transaction.is_some() {
+        return Err(DbError::TransactionOpen);
+    }
+
+    let root_page_num = get_unused_page_num(&mut table.pager)?;
+    let root = get_page_mut(&mut table.pager, root_page_num)?;
+    initialize_leaf_node(root);
+    set_node_root(root, true);
+    let mut heap_page_num = 0;
+    if heap {
+        root[NODE_TYPE_OFFSET] = HEAP_LEAF_TYPE;
+        heap_page_num = get_unused_page_num(&mut table.pager)?;
+        initialize_heap_page(get_page_mut(&mut table.pager, heap_page_num)?, heap_page_num);
+    }
+    table.pager.tables.push(NamedTable {
+        name: name.to_string(),
+        root_page_num: root_page_num as u32,
+        heap_page_num: heap_page_num as u32,
+    });
+
+    pager_sync(&mut table.pager)?;
+    let pager = &mut table.pager;
+    let mut directory = vec![0u8; FILE_HEADER_SIZE - FILE_HEADER_TABLE_COUNT_OFFSET];
+    set_u32_at(&mut directory, 0, pager.tables.len() as u32);
+    for (i, named) in pager.tables.iter().enumerate() {
+        let entry = &mut directory[size_of::<u32>() + i * TABLE_ENTRY_SIZE..];
+        entry[..named.name.len()].copy_from_slice(named.name.as_bytes());
+        set_u32_at(entry, TABLE_NAME_SIZE, named.root_page_num);
+        set_u32_at(entry, TABLE_NAME_SIZE + size_of::<u32>(), named.heap_page_num);
+    }
+    if let (Some(file), true) = (pager.file_descriptor.as_mut(), pager.data_offset > 0) {
+        file.seek(SeekFrom::Start(FILE_HEADER_TABLE_COUNT_OFFSET as u64))?;
+        file.write_all(&directory)?;
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+// Run f against the named table, or the main table when name is None, by
+// pointing the table's root, and its heap for a heap table, at the named
+// one for the length of f. Named tables have no indexes, so the main
+// table's are set aside meanwhile.
+fn in_named_table<T>(
+    name: Option<&str>,
+    table: &mut Table,
+    f: impl FnOnce(&mut Table) -> Result<T, DbError>,
+) -> Result<T, DbError> {
+    let Some(name) = name else {
+        return f(table);
+    };
+    let Some(named) = table.pager.tables.iter().find(|named| named.name == name) else {
+        return Err(DbError::NoSuchTable { name: name.to_string() });
+    };
+
+    let heap_page_num = named.heap_page_num as usize;
+    let root_page_num = std::mem::replace(&mut table.root_page_num, named.root_page_num as usize);
+    let rightmost_leaf = table.rightmost_leaf.take();
+    let index_roots = std::mem::take(&mut table.pager.index_roots);
+    let leaf_max_cells = table.leaf_max_cells;
+    if heap_page_num != 0 {
+        table.heap_page_num = heap_page_num;
+        table.leaf_max_cells = table.heap_leaf_max_cells;
+    }
+    let result = f(table);
+    table.root_page_num = root_page_num;
+    table.rightmost_leaf = rightmost_leaf;
+    table.pager.index_roots = index_roots;
+    table.heap_page_num = 0;
+    table.leaf_max_cells = leaf_max_cells;
+    result
+}
+
+fn execute_exists(statement: &Statement, table: &mut Table) -> Result<ExecuteResult, DbError> {
+    match statement.predicate {
+        Some(Predicate::IdEquals(id)) => Ok(ExecuteResult::Exists(table_key_exists(table, id)?)),
+        _ => Ok(ExecuteResult::Success),
+    }
+}
+
+fn execute_statement(statement: &Statement, table: &mut Table) -> Result<ExecuteResult, DbError> {
+    let name = statement.table.as_deref();
+    in_statement(table, |table| in_named_table(name, table, |table| match statement.statement_type {
+        StatementType::Insert(_) => execute_insert(statement, table),
+        StatementType::Select => execute_select(statement, table),
+        StatementType::Count => execute_count(statement, table),
+        StatementType::Exists => execute_exists(statement, table),
+        StatementType::CountDistinct(column) => execute_count_distinct(column, table),
+        StatementType::Delete => execute_delete(statement, table),
+        StatementType::Update => execute_update(statement, table),
+        StatementType::CreateTable(widths) => execute_create_table(widths, table),
+        StatementType::CreateNamedTable(ref name, heap) => {
+            create_named_table(table, name, heap)?;
+            Ok(ExecuteResult::Success)
+        }
+        StatementType::SetMaxScan(limit) => {
+            table.settings.max_scan = (limit > 0).then_some(limit);
+            Ok(ExecuteResult::Success)
+        }
+        // Takes effect on the next cache miss
+        StatementType::SetCacheSize(pages) => {
+            table.pager.max_cached_pages = (pages > 0).then_some(pages);
+            Ok(ExecuteResult::Success)
+        }
+    }))
+}
+
+/// One line of REPL input, as handed to Database::run_input
+#[derive(Default)]
+pub struct InputBuffer {
+    buffer: String,
+    buffer_length: usize,
+    input_length: usize,
+}
+
+impl InputBuffer {
+    /// An empty buffer
+    pub fn new() -> Self {
+        Self {
+            buffer: String::new(),
+            buffer_length: 0,
+            input_length: 0,
+        }
+    }
+
+    /// Fill the buffer with one line of input, as read by the caller
+    pub fn set_input(&mut self, line: &str) {
+        let trimmed = line.trim_end();
+        self.input_length = trimmed.len();
+        self.buffer_length = line.len();
+        self.buffer = trimmed.to_string();
+    }
+}
+
+// Structural check behind .verify: walks the table's tree, then each
+// index and named table there is, and returns the first broken invariant,
+// naming the page it was found on
+fn verify_tree(table: &mut Table) -> Result<(), String> {
+    verify_btree(&mut table.pager, table.root_page_num)?;
+    for column in INDEXED_COLUMNS {
+        let root_page_num = index_root(&table.pager, column);
+        if root_page_num != 0 {
+            verify_btree(&mut table.pager, root_page_num)?;
+        }
+    }
+    for named in table.pager.tables.clone() {
+        verify_btree(&mut table.pager, named.root_page_num as usize)?;
+    }
+    Ok(())
+}
+
+// One tree of the file: its nodes from the root down, then its leaf chain
+fn verify_btree(pager: &mut Pager, root_page_num: usize) -> Result<(), String> {
+    let mut leaves = Vec::new();
+    let mut visited = HashSet::new();
+    verify_node(pager, root_page_num, None, (None, None), &mut leaves, &mut visited)?;
+
+    // The leaf chain must visit the same leaves, in the same order
+    let mut chain = Vec::new();
+    let mut page_num = leaves[0];
+    let mut prev_leaf = NO_LEAF;
+    loop {
+        if chain.len() > leaves.len() {
+            return Err(format!("page {}: leaf chain does not end", page_num));
+        }
+        chain.push(page_num);
+        let node = get_page(pager, page_num).map_err(|e| e.to_string())?;
+        if node_type_checked(node, page_num).map_err(|e| e.to_string())? != NodeType::Leaf {
+            return Err(format!("page {}: leaf chain reaches a non-leaf", page_num));
+        }
+        if get_leaf_node_prev_leaf(node) != prev_leaf {
+            return Err(format!(
+                "page {}: previous leaf is {}, expected {}",
+                page_num,
+                get_leaf_node_prev_leaf(node),
+                prev_leaf
+            ));
+        }
+        let next_leaf = get_leaf_node_next_leaf(node);
+        if next_leaf == NO_LEAF {
+            break;
+        }
+        prev_leaf = page_num as u32;
+        page_num = next_leaf as usize;
+    }
+    if chain != leaves {
+        let position = chain.iter().zip(&leaves).position(|(a, b)| a != b).unwrap_or(chain.len().min(leaves.len()));
+        let page_num = chain.get(position).or(leaves.get(position)).copied().unwrap_or(0);
+        return Err(format!("page {}: leaf chain is out of key order", page_num));
+    }
+
+    Ok(())
+}
+
+// Checks one node and its subtree. Every key must lie in bounds, which
+// are (exclusive lower, inclusive upper) from the keys in the parent. An
+// internal key is an upper bound for its child rather than exactly its max
+// key, since deletes don't shrink keys.
+fn verify_node(
+    pager: &mut Pager,
+    page_num: usize,
+    parent: Option<usize>,
+    bounds: (Option<u32>, Option<u32>),
+    leaves: &mut Vec<usize>,
+    visited: &mut HashSet<usize>,
+) -> Result<(), String> {
+    if !visited.insert(page_num) {
+        return Err(format!("page {}: reached twice", page_num));
+    }
+    let node = get_page(pager, page_num).map_err(|e| e.to_string())?;
+    let node_type = node_type_checked(node, page_num).map_err(|e| e.to_string())?;
+    if let Some(parent) = parent {
+        if is_node_root(node) {
+            return Err(format!("page {}: non-root node is marked root", page_num));
+        }
+        if node_parent(node) as usize != parent {
+            return Err(format!(
+                "page {}: parent pointer is {}, expected {}",
+                page_num,
+                node_parent(node),
+                parent
+            ));
+        }
+    }
+    let (lower, upper) = bounds;
+    let in_bounds = |key: u32| lower.is_none_or(|lower| key > lower) && upper.is_none_or(|upper| key <= upper);
+
+    // A count past what the page can hold would read past its end
+    let page_size = usable_page_size(pager);
+    let node = get_page(pager, page_num).map_err(|e| e.to_string())?;
+    match node_type {
+        NodeType::Leaf => {
+            let num_cells = leaf_node_num_cells(node) as usize;
+            let max_cells = if node[NODE_TYPE_OFFSET] == HEAP_LEAF_TYPE {
+                heap_leaf_max_cells(page_size)
+            } else {
+                leaf_node_max_cells(page_size)
+            };
+            if num_cells > max_cells {
+                return Err(format!("page {}: leaf node has {} cells", page_num, num_cells));
+            }
+            for cell_num in 0..num_cells {
+                let key = leaf_node_key_checked(node, page_num, cell_num).map_err(|e| e.to_string())?;
+                if cell_num > 0 && key <= leaf_node_key(node, cell_num - 1) {
+                    return Err(format!("page {}: leaf key {} is out of order", page_num, key));
+                }
+                if !in_bounds(key) {
+                    return Err(format!("page {}: leaf key {} is outside its parent's key range", page_num, key));
+                }
+            }
+            leaves.push(page_num);
+        }
+        NodeType::Internal => {
+            let num_keys = internal_node_num_keys(node) as usize;
+            if num_keys > internal_node_max_cells(page_size) {
+                return Err(format!("page {}: internal node has {} keys", page_num, num_keys));
+            }
+            let keys: Vec<u32> = (0..num_keys).map(|i| internal_node_key(node, i)).collect();
+            let children = (0..=num_keys)
+                .map(|i| internal_node_child(node, i).map(|child| child as usize))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("page {}: {}", page_num, e))?;
+            for (i, &key) in keys.iter().enumerate() {
+                if i > 0 && key <= keys[i - 1] {
+                    return Err(format!("page {}: internal key {} is out of order", page_num, key));
+                }
+                if !in_bounds(key) {
+                    return Err(format!("page {}: internal key {} is outside its parent's key range", page_num, key));
+                }
+            }
+            for (i, &child) in children.iter().enumerate() {
+                let child_lower = if i == 0 { lower } else { Some(keys[i - 1]) };
+                let child_upper = if i == num_keys { upper } else { Some(keys[i]) };
+                verify_node(pager, child, Some(page_num), (child_lower, child_upper), leaves, visited)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Integrity check for scripts: open the file read-only, check every page
+/// header and the free-page count, then run the same tree check as
+/// .verify. Print each problem and a summary, and report whether the file
+/// is clean. Nothing is written, and a damaged file is reported, never
+/// panicked on.
+pub fn verify_database(filename: &str, out: &mut dyn Write) -> bool {
+    let mut table = match db_open(filename, true, DEFAULT_PAGE_SIZE, false) {
+        Ok(table) => table,
+        Err(e) => {
+            outln!(out, "{}: {}", filename, e);
+            return false;
+        }
+    };
+    let mut problems = Vec::new();
+    let mut free_pages = 0;
+
+    for page_num in 0..table.pager.num_pages {
+        // An unreadable page or a checksum mismatch is one more problem
+        let node = match get_page(&mut table.pager, page_num) {
+            Ok(node) => node,
+            Err(e) => {
+                problems.push(e.to_string());
+                continue;
+            }
+        };
+        match node[NODE_TYPE_OFFSET] {
+            0 | 1 | HEAP_LEAF_TYPE | HEAP_PAGE_TYPE => {}
+            FREE_PAGE_TYPE => free_pages += 1,
+            other => problems.push(format!("page {}: unknown node type {}", page_num, other)),
+        }
+    }
+    if free_pages != table.pager.free_count {
+        problems.push(format!(
+            "header counts {} free pages but {} pages are free",
+            table.pager.free_count, free_pages
+        ));
+    }
+
+    if let Err(violation) = verify_tree(&mut table) {
+        problems.push(violation);
+    }
+    let num_rows = if problems.is_empty() {
+        table_keys(&mut table).map_or(0, |keys| keys.count())
+    } else {
+        0
+    };
+
+    for problem in &problems {
+        outln!(out, "{}", problem);
+    }
+    outln!(
+        out,
+        "{}: {} pages, {} rows, {} problems",
+        filename,
+        table.pager.num_pages,
+        num_rows,
+        problems.len()
+    );
+
+    problems.is_empty()
+}
+
+// prepare_statement through the table's statement cache. With .truncate on,
+// the same text can parse differently once it is turned off again, so those
+// parses are not cached.
+fn prepare_cached(input_buffer: &InputBuffer, table: &mut Table) -> PrepareResult {
+    let key = input_buffer.buffer.trim();
+    if let Some(statement) = table.statement_cache.get(key) {
+        return PrepareResult::Success(Box::new(statement.clone()));
+    }
+
+    let result = prepare_statement(input_buffer, &table.settings, table.pager.text_widths);
+    if let PrepareResult::Success(statement) = &result {
+        if !table.settings.truncate {
+            // Dropping everything keeps the cache bounded without tracking use
+            if table.statement_cache.len() >= STATEMENT_CACHE_SIZE {
+                table.statement_cache.clear();
+            }
+            table.statement_cache.insert(key.to_string(), (**statement).clone());
+        }
+    }
+    result
+}
+
+// Run one line of input, either a meta command or a statement, and print
+// its result. Returns true when the line asked to exit.
+fn run_input(input_buffer: &InputBuffer, table: &mut Table) -> bool {
+    if table.settings.echo {
+        outln!(table.out, "{}", input_buffer.buffer);
+    }
+    
+    if input_buffer.buffer.starts_with('.') {
+        match in_statement(table, |table| Ok(do_meta_command(input_buffer, table))) {
+            Ok(MetaCommandResult::Success) => return false,
+            Ok(MetaCommandResult::Exit) => return true,
+            Ok(MetaCommandResult::UnrecognizedCommand) => {
+                outln!(table.out, "Unrecognized command '{}'.", input_buffer.buffer);
+                return false;
+            }
+            Err(e) => {
+                outln!(table.out, "Error: {}.", e);
+                return false;
+            }
+        }
+    }
+
+    // Row statements with ? placeholders go through PreparedStatement and
+    // are bound from .param; other statements run with the bound values
+    // spliced into their text
+    let has_placeholders = input_buffer.buffer.split_whitespace().any(|token| token == "?");
+    let prepare_result = match PreparedStatement::prepare(&input_buffer.buffer) {
+        Some(mut prepared) if has_placeholders => {
+            bind_session_params(&mut prepared, &table.settings.params);
+            prepared.statement(table.pager.text_widths, table.settings.truncate)
+        }
+        _ if has_placeholders => match bind_params(&input_buffer.buffer, &table.settings.params) {
+            Ok(buffer) => {
+                let mut bound = InputBuffer::new();
+                bound.input_length = buffer.len();
+                bound.buffer = buffer;
+                prepare_cached(&bound, table)
+            }
+            Err(n) => PrepareResult::UnboundParameter(n),
+        },
+        _ => prepare_cached(input_buffer, table),
+    };
+
+    match prepare_result {
+        PrepareResult::Success(statement) => {
+            for warning in &statement.warnings {
+                outln!(table.out, "{}", warning);
+            }
+            let result = execute_statement(&statement, table);
+            match result {
+                Ok(ExecuteResult::Success) => {
+                    outln!(table.out, "Executed successfully.");
+                }
+                Ok(ExecuteResult::DuplicateKey) => {
+                    outln!(table.out, "Error: Duplicate key.");
+                }
+                Ok(ExecuteResult::TableFull) => {
+                    outln!(table.out, "Error: Table full.");
+                }
+                Ok(ExecuteResult::ScanLimitExceeded) => {
+                    outln!(table.out, "Error: Scan limit exceeded.");
+                }
+                Ok(ExecuteResult::NotFound) => {
+                    outln!(table.out, "Error: Key not found.");
+                }
+                Ok(ExecuteResult::Count(count)) => {
+                    outln!(table.out, "Count: {}", count);
+                    outln!(table.out, "Executed successfully.");
+                }
+                Ok(ExecuteResult::Exists(found)) => {
+                    outln!(table.out, "{}", found as u8);
+                    outln!(table.out, "Executed successfully.");
+                }
+                // The statement failed but the database stays open
+                Err(e) => {
+                    outln!(table.out, "Error: {}.", e);
+                }
+            }
+        }
+        PrepareResult::NegativeId => {
+            outln!(table.out, "Error: ID must be positive.");
+        }
+        PrepareResult::IdOutOfRange => {
+            outln!(table.out, "Error: ID out of range.");
+        }
+        PrepareResult::InvalidHex => {
+            outln!(table.out, "Error: Invalid hex literal.");
+        }
+        PrepareResult::StringTooLong => {
+            outln!(table.out, "Error: String too long.");
+        }
+        PrepareResult::SyntaxError => {
+            outln!(table.out, "Syntax error. Could not parse statement.");
+        }
+        PrepareResult::UnboundParameter(n) => {
+            outln!(table.out, "Error: Parameter {} is not set.", n);
+        }
+        PrepareResult::InvalidSchema(problems) => {
+            for problem in problems {
+                outln!(table.out, "Error: {}.", problem);
+            }
+        }
+        PrepareResult::UnrecognizedStatement => {
+            outln!(
+                table.out,
+                "Unrecognized keyword at start of '{}'",
+                input_buffer.buffer
+            );
+        }
+    }
+
+    false
+}
+
+/* Library Interface */
+
+/// What a statement run through Database::execute produced
+#[derive(Debug)]
+pub enum QueryResult {
+    /// The rows a select matched, in the order it returned them; empty for
+    /// statements that change the table or a setting
+    Rows(Vec<Row>),
+    /// `select count`, with or without a where clause or distinct
+    Count(usize),
+    /// `select exists <id>`: whether the table has a row with that id
+    Exists(bool),
+}
+
+impl QueryResult {
+    /// The rows of a select, or none for a count or exists
+    pub fn into_rows(self) -> Vec<Row> {
+        match self {
+            QueryResult::Rows(rows) => rows,
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// An open database, the crate's public entry point. The binary is a REPL
+/// built on Database::run_input; programs embedding the crate use execute.
+pub struct Database {
+    table: Table,
+}
+
+impl Database {
+    /// Open the file at path, creating it if needed; ":memory:" opens a
+    /// database with no file
+    pub fn open(path: &str) -> Result<Database, DbError> {
+        Database::open_with_page_size(path, DEFAULT_PAGE_SIZE)
+    }
+
+    /// The page size only applies when the file is created
+    pub fn open_with_page_size(path: &str, page_size: usize) -> Result<Database, DbError> {
+        Database::open_with_options(path, page_size, false)
+    }
+
+    /// Like open_with_page_size; checksums gives a newly created file a
+    /// CRC-32 on every page, checked whenever the page is read back
+    pub fn open_with_options(path: &str, page_size: usize, checksums: bool) -> Result<Database, DbError> {
+        if !valid_page_size(page_size) {
+            return Err(DbError::InvalidPageSize { page_size });
+        }
+        Ok(Database {
+            table: db_open(path, false, page_size, checksums)?,
+        })
+    }
+
+    /// Run one statement. A select returns its rows, select count the count
+    /// and select exists whether the key is there; other statements return
+    /// no rows. Nothing is printed. Meta commands are not statements; use
+    /// run_input.
+    pub fn execute(&mut self, sql: &str) -> Result<QueryResult, DbError> {
+        let mut rows = Vec::new();
+        match self.execute_each(sql, &mut |row| rows.push(row))? {
+            QueryResult::Rows(_) => Ok(QueryResult::Rows(rows)),
+            result => Ok(result),
+        }
+    }
+
+    /// Like execute, but each row of a select goes to on_row as the scan
+    /// reaches it instead of being collected, so memory stays bounded
+    /// however many rows match. A select then returns no rows itself.
+    pub fn execute_each(
+        &mut self,
+        sql: &str,
+        on_row: &mut dyn FnMut(Row),
+    ) -> Result<QueryResult, DbError> {
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.buffer = sql.trim().to_string();
+        input_buffer.input_length = input_buffer.buffer.len();
+
+        let statement = match prepare_cached(&input_buffer, &mut self.table) {
+            PrepareResult::Success(statement) => statement,
+            result => return Err(prepare_error(result)),
+        };
+        let result = match statement.statement_type {
+            StatementType::Select => {
+                let name = statement.table.as_deref();
+                let table = &mut self.table;
+                in_named_table(name, table, |table| select_rows(&statement, table, on_row))?
+            }
+            _ => execute_statement(&statement, &mut self.table)?,
+        };
+        query_result(result)
+    }
+
+    /// Parse an insert or update whose id, username and email may each be
+    /// a `?` placeholder, such as `insert ? ? ?`, for running many times
+    /// with Prepared::execute without parsing the text again
+    pub fn prepare(&self, sql: &str) -> Result<Prepared, DbError> {
+        match PreparedStatement::prepare(sql.trim()) {
+            Some(statement) => Ok(Prepared { statement }),
+            None => Err(DbError::InvalidStatement(
+                "only '<insert|update> <id> <username> <email>' can be prepared".to_string(),
+            )),
+        }
+    }
+
+    /// Overwrite the username and email of the row with this id in place,
+    /// leaving its key and the tree's shape alone. row.id is not used.
+    /// Returns false, changing nothing, when there is no such row; unlike
+    /// insert or replace it never adds one. A value longer than create table
+    /// declared its column is rejected.
+    pub fn replace(&mut self, id: u32, row: &Row) -> Result<bool, DbError> {
+        if !self.table.pager.text_widths.fits(row) {
+            return Err(prepare_error(PrepareResult::StringTooLong));
+        }
+        let statement = Statement {
+            statement_type: StatementType::Update,
+            row_to_insert: Some(Row { id, ..row.clone() }),
+            predicate: None,
+            table: None,
+            order_by: Column::Id,
+            descending: false,
+            limit: None,
+            warnings: Vec::new(),
+        };
+        match in_statement(&mut self.table, |table| execute_update(&statement, table))? {
+            ExecuteResult::NotFound => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    /// Whether a row with this id exists, found by descending the tree
+    /// without reading the row
+    pub fn exists(&mut self, id: u32) -> Result<bool, DbError> {
+        table_key_exists(&mut self.table, id)
+    }
+
+    /// Every key in ascending order, read from the leaf cells without
+    /// touching the row values. Borrows the database like rows().
+    pub fn keys(&mut self) -> Result<KeyIter<'_>, DbError> {
+        table_keys(&mut self.table)
+    }
+
+    /// How many rows match a WHERE clause, given without the 'where', such
+    /// as "id between 3 and 9" or "username = alice". Id clauses scan only
+    /// their key range and no row is deserialized.
+    pub fn count_where(&mut self, clause: &str) -> Result<usize, DbError> {
+        let predicate = parse_predicate(clause.trim())
+            .ok_or_else(|| prepare_error(PrepareResult::SyntaxError))?;
+        count_where(&mut self.table, &predicate)?.ok_or(DbError::ScanLimitExceeded)
+    }
+
+    /// Page number of the leaf holding the smallest keys, where an
+    /// ascending scan starts
+    pub fn leftmost_leaf(&mut self) -> Result<usize, DbError> {
+        table_leftmost_leaf(&mut self.table)
+    }
+
+    /// Page number of the leaf holding the largest keys, where a
+    /// descending scan starts
+    pub fn rightmost_leaf(&mut self) -> Result<usize, DbError> {
+        table_rightmost_leaf(&mut self.table)
+    }
+
+    /// Every row in key order. The iterator borrows the database, so it has
+    /// to be dropped before the next execute.
+    pub fn rows(&mut self) -> Result<RowIter<'_>, DbError> {
+        table_rows(&mut self.table)
+    }
+
+    /// One line of REPL input, a meta command or a statement, with its
+    /// result written to the set_output writer. Returns true when the line
+    /// asked to exit.
+    pub fn run_input(&mut self, input_buffer: &InputBuffer) -> bool {
+        run_input(input_buffer, &mut self.table)
+    }
+
+    /// Where run_input writes results, rows and messages. Until this is
+    /// called they are discarded; the library never prints on its own.
+    pub fn set_output(&mut self, out: Box<dyn Write>) {
+        self.table.out = out;
+    }
+
+    /// Read up to limit pages (all of them when None) into the cache, but
+    /// no more than pragma cache_size allows
+    pub fn warm_cache(&mut self, limit: Option<usize>) -> Result<(), DbError> {
+        warm_cache(&mut self.table.pager, limit)
+    }
+
+    /// Build .vacuum's copy in dir instead of next to the database file
+    pub fn set_temp_dir(&mut self, dir: &str) {
+        self.table.settings.temp_dir = Some(PathBuf::from(dir));
+    }
+
+    /// Write every change back to the file and close it. The database is
+    /// consumed, so using it afterwards does not compile:
+    ///
+    /// ```compile_fail
+    /// let mut db = database::Database::open(":memory:").unwrap();
+    /// db.close().unwrap();
+    /// db.execute("select").unwrap();
+    /// ```
+    pub fn close(self) -> Result<(), DbError> {
+        db_close(self.table)
+    }
+}
+
+/// A value for one `?` placeholder of a Prepared statement
+#[derive(Debug, Clone, Copy)]
+pub enum Param<'a> {
+    /// An id
+    Int(u32),
+    /// A username or email, stored byte for byte as given
+    Text(&'a str),
+    /// A NULL username or email
+    Null,
+}
+
+/// An insert or update parsed once by Database::prepare. Each execute
+/// binds a full set of values to its placeholders and runs it.
+pub struct Prepared {
+    statement: PreparedStatement,
+}
+
+impl Prepared {
+    /// How many `?` placeholders the statement has
+    pub fn placeholder_count(&self) -> usize {
+        self.statement.placeholder_count()
+    }
+
+    /// Bind params to the placeholders in order, the first to the first
+    /// `?`, and run the statement on db. There must be exactly one param
+    /// per placeholder. The values are checked as a typed statement's
+    /// would be.
+    pub fn execute(
+        &mut self,
+        db: &mut Database,
+        params: &[Param],
+    ) -> Result<QueryResult, DbError> {
+        if params.len() != self.placeholder_count() {
+            return Err(DbError::InvalidStatement(format!(
+                "expected {} parameters, got {}",
+                self.placeholder_count(),
+                params.len()
+            )));
+        }
+        for (i, param) in params.iter().enumerate() {
+            let bound = match *param {
+                Param::Int(number) => self.statement.bind_u32(i + 1, number),
+                Param::Text(text) => self.statement.bind_str(i + 1, text),
+                Param::Null => self.statement.bind_null(i + 1),
+            };
+            bound.expect("every n up to placeholder_count is a placeholder");
+        }
+
+        let widths = db.table.pager.text_widths;
+        let statement = match self.statement.statement(widths, db.table.settings.truncate) {
+            PrepareResult::Success(statement) => statement,
+            result => return Err(prepare_error(result)),
+        };
+        query_result(execute_statement(&statement, &mut db.table)?)
+    }
+}
+
+// A statement's outcome as Database::execute returns it; the outcomes the
+// REPL reports as errors become DbErrors
+fn query_result(result: ExecuteResult) -> Result<QueryResult, DbError> {
+    match result {
+        ExecuteResult::Success => Ok(QueryResult::Rows(Vec::new())),
+        ExecuteResult::Count(count) => Ok(QueryResult::Count(count)),
+        ExecuteResult::Exists(found) => Ok(QueryResult::Exists(found)),
+        ExecuteResult::DuplicateKey => Err(DbError::DuplicateKey),
+        ExecuteResult::TableFull => Err(DbError::TableFull),
+        ExecuteResult::NotFound => Err(DbError::KeyNotFound),
+        ExecuteResult::ScanLimitExceeded => Err(DbError::ScanLimitExceeded),
+    }
+}
+
+// The reason a statement failed to prepare, as Database::execute reports it
+fn prepare_error(result: PrepareResult) -> DbError {
+    let reason = match result {
+        PrepareResult::Success(_) => unreachable!("not an error"),
+        PrepareResult::NegativeId => "ID must be positive".to_string(),
+        PrepareResult::IdOutOfRange => "ID out of range".to_string(),
+        PrepareResult::InvalidHex => "invalid hex literal".to_string(),
+        PrepareResult::StringTooLong => "string too long".to_string(),
+        PrepareResult::SyntaxError => "syntax error".to_string(),
+        PrepareResult::UnboundParameter(n) => format!("parameter {} is not set", n),
+        PrepareResult::InvalidSchema(problems) => problems.join("; "),
+        PrepareResult::UnrecognizedStatement => "unrecognized keyword".to_string(),
+    };
+    DbError::InvalidStatement(reason)
+}
+
+/// The row layout the REPL prints on startup
+pub fn print_row_layout(out: &mut dyn Write) {
+    outln!(out, "ID_SIZE: {}", ID_SIZE);
+    outln!(out, "USERNAME_SIZE: {}", USERNAME_SIZE);
+    outln!(out, "EMAIL_SIZE: {}", EMAIL_SIZE);
+    outln!(out, "ID_OFFSET: {}", ID_OFFSET);
+    outln!(out, "USERNAME_OFFSET: {}", USERNAME_OFFSET);
+    outln!(out, "EMAIL_OFFSET: {}", EMAIL_OFFSET);
+    outln!(out, "ROW_FLAGS_OFFSET: {}", ROW_FLAGS_OFFSET);
+    outln!(out, "ROW_SIZE: {}", ROW_SIZE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+    use proptest::prelude::*;
+    use std::collections::BTreeMap;
+
+    // An in-memory database with small nodes, so a few dozen rows already
+    // split leaves and internal nodes several levels deep
+    fn small_node_db(leaf_max_cells: usize, internal_max_cells: usize) -> Database {
+        let mut db = Database::open(":memory:").unwrap();
+        db.table.leaf_max_cells = leaf_max_cells;
+        db.table.internal_max_cells = internal_max_cells;
+        db
+    }
+
+    fn select(db: &mut Database, sql: &str) -> Vec<Row> {
+        db.execute(sql).unwrap().into_rows()
+    }
+
+    fn ids(rows: &[Row]) -> Vec<u32> {
+        rows.iter().map(|row| row.id).collect()
+    }
+
+    fn prepare(input: &str) -> PrepareResult {
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.set_input(input);
+        prepare_statement(&input_buffer, &Settings::default(), TextWidths::default())
+    }
+
+    fn prepared_id(input: &str) -> Option<u32> {
+        match prepare(input) {
+            PrepareResult::Success(statement) => statement.row_to_insert.map(|row| row.id),
+            _ => None,
+        }
+    }
+
+    // A missing or unset child pointer is an error for the caller, not an
+    // exit of the whole process
+    #[test]
+    fn invalid_child_is_an_error() {
+        let mut node = vec![0u8; DEFAULT_PAGE_SIZE];
+        initialize_internal_node(&mut node);
+        assert!(matches!(
+            internal_node_child(&node, 0),
+            Err(DbError::InvalidChild { child_num: 0, num_keys: 0 })
+        ));
+
+        set_internal_node_num_keys(&mut node, 1);
+        set_internal_node_child(&mut node, 0, INVALID_PAGE_NUM);
+        set_internal_node_right_child(&mut node, 7);
+        assert!(matches!(internal_node_child(&node, 0), Err(DbError::InvalidChild { .. })));
+        assert_eq!(internal_node_child(&node, 1).unwrap(), 7);
+        assert!(matches!(
+            internal_node_child(&node, 2),
+            Err(DbError::InvalidChild { child_num: 2, num_keys: 1 })
+        ));
+    }
+
+    // The whole u32 range is a valid id; below it is NegativeId and above
+    // it IdOutOfRange, never a syntax error
+    #[test]
+    fn insert_id_limits() {
+        assert_eq!(prepared_id("insert 0 a b"), Some(0));
+        assert_eq!(prepared_id("insert 2147483648 a b"), Some(i32::MAX as u32 + 1));
+        assert_eq!(prepared_id("insert 4294967295 a b"), Some(u32::MAX));
+        assert!(matches!(prepare("insert -1 a b"), PrepareResult::NegativeId));
+        assert!(matches!(prepare("insert 4294967296 a b"), PrepareResult::IdOutOfRange));
+        assert!(matches!(prepare("insert 999999999999 a b"), PrepareResult::IdOutOfRange));
+
+        let mut db = Database::open(":memory:").unwrap();
+        db.execute("insert 4294967295 a b").unwrap();
+        db.execute("insert 2147483648 c d").unwrap();
+        assert_eq!(ids(&select(&mut db, "select")), vec![2147483648, u32::MAX]);
+    }
+
+    // A key that falls in the right child has no cell to update, so the
+    // node, including the slot past its last cell, is left as it was
+    #[test]
+    fn update_rightmost_separator() {
+        let mut node = vec![0u8; DEFAULT_PAGE_SIZE];
+        initialize_internal_node(&mut node);
+        set_internal_node_num_keys(&mut node, 2);
+        for (i, (child, key)) in [(1, 10), (2, 20)].into_iter().enumerate() {
+            set_internal_node_child(&mut node, i, child);
+            set_internal_node_key(&mut node, i, key);
+        }
+        set_internal_node_right_child(&mut node, 3);
+        let before = node.clone();
+
+        update_internal_node_key(&mut node, 30, 35);
+        assert_eq!(node, before);
+
+        update_internal_node_key(&mut node, 20, 25);
+        assert_eq!(internal_node_key(&node, 1), 25);
+        assert_eq!(internal_node_num_keys(&node), 2);
+        assert_eq!(internal_node_right_child(&node), 3);
+    }
+
+    // Ascending and descending inserts keep splitting the rightmost and
+    // leftmost leaf under each parent, so separators get updated in the
+    // right child of every level; the tree verifies after each one
+    #[test]
+    fn splits_at_tree_edges_keep_tree_valid() {
+        for keys in [(1..=60).collect::<Vec<u32>>(), (1..=60).rev().collect()] {
+            let mut db = small_node_db(3, 3);
+            for key in keys {
+                db.execute(&format!("insert {} a b", key)).unwrap();
+                assert_eq!(verify_tree(&mut db.table), Ok(()), "after inserting {}", key);
+            }
+            assert_eq!(ids(&select(&mut db, "select")), (1..=60).collect::<Vec<_>>());
+        }
+    }
+
+    // The leaf chain runs through every leaf once and is ended by NO_LEAF
+    // on both sides, so forward and backward scans stop after the last row
+    #[test]
+    fn leaf_chain_is_terminated() {
+        let internal_node_at = |pager: &mut Pager, page_num| {
+            node_type_checked(get_page(pager, page_num).unwrap(), page_num).unwrap()
+                == NodeType::Internal
+        };
+        let mut db = small_node_db(3, 3);
+        for key in [40, 10, 30, 20, 50, 70, 60, 80, 90, 15, 25, 35] {
+            db.execute(&format!("insert {} a b", key)).unwrap();
+        }
+
+        let pager = &mut db.table.pager;
+        let mut page_num = db.table.root_page_num;
+        while internal_node_at(pager, page_num) {
+            page_num = internal_node_child(get_page(pager, page_num).unwrap(), 0).unwrap() as usize;
+        }
+        assert_eq!(get_leaf_node_prev_leaf(get_page(pager, page_num).unwrap()), NO_LEAF);
+        let mut leaves = 1;
+        loop {
+            let next_leaf = get_leaf_node_next_leaf(get_page(pager, page_num).unwrap());
+            if next_leaf == NO_LEAF {
+                break;
+            }
+            let next = get_page(pager, next_leaf as usize).unwrap();
+            assert_eq!(get_leaf_node_prev_leaf(next), page_num as u32);
+            page_num = next_leaf as usize;
+            leaves += 1;
+        }
+        assert!(leaves > 2);
+
+        let mut expected = vec![10, 15, 20, 25, 30, 35, 40, 50, 60, 70, 80, 90];
+        assert_eq!(ids(&select(&mut db, "select")), expected);
+        expected.reverse();
+        assert_eq!(ids(&select(&mut db, "select order by id desc")), expected);
+    }
+
+    // Splitting a full root leaf by inserting before, between and after
+    // its keys leaves exactly the old keys plus the new one, in order,
+    // spread over two leaves
+    #[test]
+    fn leaf_split_at_front_middle_and_end() {
+        let max_cells = leaf_node_max_cells(DEFAULT_PAGE_SIZE) as u32;
+        for new_key in [5, 10 * (max_cells / 2) + 5, 10 * (max_cells + 1)] {
+            let mut db = Database::open(":memory:").unwrap();
+            let mut expected: Vec<u32> = (1..=max_cells).map(|i| i * 10).collect();
+            for key in &expected {
+                db.execute(&format!("insert {} a b", key)).unwrap();
+            }
+            db.execute(&format!("insert {} a b", new_key)).unwrap();
+            expected.push(new_key);
+            expected.sort();
+
+            assert_eq!(ids(&select(&mut db, "select")), expected, "inserting {}", new_key);
+            assert_eq!(verify_tree(&mut db.table), Ok(()));
+            let pager = &mut db.table.pager;
+            let root = get_page(pager, db.table.root_page_num).unwrap();
+            assert_eq!(node_type_checked(root, 0).unwrap(), NodeType::Internal);
+            assert_eq!(internal_node_num_keys(root), 1);
+            let left = internal_node_child(root, 0).unwrap() as usize;
+            let right = internal_node_right_child(root) as usize;
+            let left_cells = leaf_node_num_cells(get_page(pager, left).unwrap());
+            let right_cells = leaf_node_num_cells(get_page(pager, right).unwrap());
+            assert!(left_cells > 0 && right_cells > 0);
+            assert_eq!(left_cells + right_cells, max_cells + 1);
+        }
+    }
+
+    fn run_meta(db: &mut Database, command: &str) {
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.set_input(command);
+        db.run_input(&input_buffer);
+    }
+
+    // A writer that can still be read after set_output has taken it
+    #[derive(Clone, Default)]
+    struct SharedOutput(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // What one line of REPL input writes
+    fn run_output(db: &mut Database, line: &str) -> String {
+        let output = SharedOutput::default();
+        db.set_output(Box::new(output.clone()));
+        run_meta(db, line);
+        db.set_output(Box::new(io::sink()));
+        let bytes = output.0.borrow().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    // A file kept at its preallocated length reopens cleanly, and later
+    // sessions fill the zeroed tail instead of growing the file past it
+    #[test]
+    fn preallocated_tail_is_reused() {
+        let path = env::temp_dir().join(format!("database-test-{}-prealloc.db", process::id()));
+        let path = path.to_str().unwrap();
+        let mut lengths = Vec::new();
+        for session in 0..3 {
+            let mut db = Database::open(path).unwrap();
+            run_meta(&mut db, ".preallocation keep");
+            for key in session * 40..session * 40 + 40 {
+                db.execute(&format!("insert {} a b", key)).unwrap();
+            }
+            db.close().unwrap();
+            lengths.push(std::fs::metadata(path).unwrap().len());
+        }
+
+        let mut db = Database::open(path).unwrap();
+        assert_eq!(ids(&select(&mut db, "select")), (0..120).collect::<Vec<_>>());
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+        for page_num in 0..db.table.pager.num_pages {
+            assert!(get_page(&mut db.table.pager, page_num).unwrap().iter().any(|&b| b != 0));
+        }
+        db.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(lengths[0], lengths[2]);
+    }
+
+    // x'..' values are stored as the bytes they encode and come back out
+    // of .mode hex as literals that insert the same bytes again; malformed
+    // literals and ones encoding NUL are rejected
+    #[test]
+    fn hex_literal_round_trip() {
+        assert!(matches!(prepare("insert 1 x'4' b"), PrepareResult::InvalidHex));
+        assert!(matches!(prepare("insert 1 x'zz' b"), PrepareResult::InvalidHex));
+        assert!(matches!(prepare("insert 1 a x'610062'"), PrepareResult::InvalidHex));
+
+        let mut db = Database::open(":memory:").unwrap();
+        db.execute("insert 1 x'48656c6c6f' x'ff01fe'").unwrap();
+        let row = select(&mut db, "select").remove(0);
+        assert_eq!(row.get_username(), "Hello");
+        assert_eq!(&row.email[..4], &[0xff, 0x01, 0xfe, 0]);
+
+        let printed = format_row(&row, OutputMode::Hex);
+        assert_eq!(printed, "(1, x'48656c6c6f', x'ff01fe')");
+        let fields = printed.trim_start_matches("(1, ").trim_end_matches(')').replace(',', "");
+        db.execute(&format!("insert 2 {}", fields)).unwrap();
+        let rows = select(&mut db, "select");
+        assert_eq!(rows[1].username, rows[0].username);
+        assert_eq!(rows[1].email, rows[0].email);
+    }
+
+    // Small inserts are counted as logical bytes at once, but the ratio is
+    // only reported once pages have been written, each one in full
+    #[test]
+    fn write_amplification_is_reported() {
+        let mut db = Database::open(":memory:").unwrap();
+        for key in 1..=3 {
+            db.execute(&format!("insert {} a b", key)).unwrap();
+        }
+        let pager = &mut db.table.pager;
+        assert!(pager.logical_bytes_written > 0);
+        assert_eq!(write_amplification(pager), None);
+
+        pager.physical_bytes_written = pager.page_size as u64;
+        let ratio = pager.page_size as f64 / pager.logical_bytes_written as f64;
+        assert_eq!(write_amplification(pager), Some(ratio));
+        assert!(ratio > 1.0);
+    }
+
+    // email like takes a '<prefix>%' pattern and nothing else
+    #[test]
+    fn like_accepts_only_prefix_patterns() {
+        let mut db = Database::open(":memory:").unwrap();
+        for (key, email) in [(1, "ann@x"), (2, "a_b@x"), (3, "bob@x"), (4, "anna@y")] {
+            db.execute(&format!("insert {} u {}", key, email)).unwrap();
+        }
+        assert_eq!(ids(&select(&mut db, "select where email like 'ann%'")), vec![1, 4]);
+        assert_eq!(ids(&select(&mut db, "select where email like '%'")), vec![1, 2, 3, 4]);
+        for pattern in ["'a_b%'", "'%x'", "'a%b%'", "'ann@x'", "ann%"] {
+            let sql = format!("select where email like {}", pattern);
+            assert!(matches!(prepare(&sql), PrepareResult::SyntaxError), "{}", sql);
+        }
+    }
+
+    fn count(db: &mut Database, sql: &str) -> usize {
+        match db.execute(sql).unwrap() {
+            QueryResult::Count(count) => count,
+            result => panic!("{} returned {:?}", sql, result),
+        }
+    }
+
+    // Aggregates come back from execute instead of being printed
+    #[test]
+    fn counts_are_returned() {
+        let mut db = small_node_db(3, 3);
+        for key in 1..=40 {
+            db.execute(&format!("insert {} u{} e{}@x{}", key, key % 5, key, key % 2)).unwrap();
+        }
+        assert_eq!(count(&mut db, "select count"), 40);
+        assert_eq!(count(&mut db, "select count(*) where id between 5 and 14"), 10);
+        assert_eq!(count(&mut db, "select count where id = 41"), 0);
+        assert_eq!(count(&mut db, "select count where username = u3"), 8);
+        assert_eq!(count(&mut db, "select count where email like 'e1%'"), 11);
+        assert_eq!(count(&mut db, "select count(distinct username)"), 5);
+        assert_eq!(count(&mut db, "select count(distinct id)"), 40);
+        assert!(db.execute("select").unwrap().into_rows().len() == 40);
+        assert!(db.execute("insert 41 a b").unwrap().into_rows().is_empty());
+    }
+
+    // Every key of a multi-leaf tree is found, including the first and
+    // last of each leaf, and the gaps between and around them are not
+    #[test]
+    fn exists_at_leaf_boundaries() {
+        let mut db = small_node_db(3, 3);
+        for key in (2..=60).step_by(2) {
+            db.execute(&format!("insert {} a b", key)).unwrap();
+        }
+        for id in 0..=62 {
+            let present = (2..=60).contains(&id) && id % 2 == 0;
+            assert_eq!(db.exists(id).unwrap(), present, "exists({})", id);
+            let result = db.execute(&format!("select exists {}", id)).unwrap();
+            assert!(matches!(result, QueryResult::Exists(found) if found == present));
+        }
+    }
+
+    // One prepared insert runs with many parameter sets; each set is
+    // checked like a typed statement and must fill every placeholder
+    #[test]
+    fn prepare_once_execute_many() {
+        let mut db = small_node_db(3, 3);
+        let mut insert = db.prepare("insert ? ? ?").unwrap();
+        assert_eq!(insert.placeholder_count(), 3);
+        for key in [5, 1, 9, 3, 7] {
+            let name = format!("user{}", key);
+            let email = format!("x'{}' has spaces", key);
+            let params = [Param::Int(key), Param::Text(&name), Param::Text(&email)];
+            insert.execute(&mut db, &params).unwrap();
+        }
+        let rows = select(&mut db, "select");
+        assert_eq!(ids(&rows), vec![1, 3, 5, 7, 9]);
+        assert_eq!(rows[0].get_username(), "user1");
+        assert_eq!(rows[0].get_email(), "x'1' has spaces");
+
+        let params = [Param::Int(5), Param::Text("again"), Param::Text("a@b")];
+        assert!(matches!(insert.execute(&mut db, &params), Err(DbError::DuplicateKey)));
+        let params = [Param::Text("-1"), Param::Text("a"), Param::Text("b")];
+        assert!(matches!(insert.execute(&mut db, &params), Err(DbError::InvalidStatement(_))));
+        let params = [Param::Int(2), Param::Text("a")];
+        assert!(matches!(insert.execute(&mut db, &params), Err(DbError::InvalidStatement(_))));
+
+        let mut update = db.prepare("update ? renamed ?").unwrap();
+        update.execute(&mut db, &[Param::Int(3), Param::Text("new@x")]).unwrap();
+        let rows = select(&mut db, "select where id = 3");
+        assert_eq!(rows[0].get_username(), "renamed");
+        assert_eq!(rows[0].get_email(), "new@x");
+
+        assert!(db.prepare("select").is_err());
+    }
+
+    // replace rewrites an existing row's values, keeping the email index
+    // in step, and reports a missing id without inserting it
+    #[test]
+    fn replace_existing_and_missing_rows() {
+        let mut db = small_node_db(3, 3);
+        for key in 1..=10 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+        }
+        run_meta(&mut db, ".createindex email");
+        assert_ne!(index_root(&db.table.pager, Column::Email), 0);
+
+        let mut row = select(&mut db, "select where id = 7").remove(0);
+        row.username = [0; COLUMN_USERNAME_SIZE];
+        row.username[..3].copy_from_slice(b"zed");
+        row.email = [0; COLUMN_EMAIL_SIZE];
+        row.email[..6].copy_from_slice(b"zed@yy");
+        row.id = 99;
+        assert!(db.replace(4, &row).unwrap());
+        let rows = select(&mut db, "select where email = zed@yy");
+        assert_eq!(ids(&rows), vec![4]);
+        assert_eq!(rows[0].get_username(), "zed");
+        assert!(select(&mut db, "select where email = e4@x").is_empty());
+
+        assert!(!db.replace(11, &row).unwrap());
+        assert!(!db.exists(11).unwrap());
+        assert!(!db.exists(99).unwrap());
+        assert_eq!(count(&mut db, "select count"), 10);
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+    }
+
+    // Pages emptied by deletes show up on the free list, which matches the
+    // header count, and inserts take them back off it
+    #[test]
+    fn deleted_pages_are_on_the_free_list() {
+        let mut db = small_node_db(3, 3);
+        for key in 1..=12 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+        }
+        assert_eq!(free_list_pages(&mut db.table.pager).unwrap(), Vec::<usize>::new());
+
+        for key in 1..=9 {
+            db.execute(&format!("delete {}", key)).unwrap();
+        }
+        let listed = free_list_pages(&mut db.table.pager).unwrap();
+        let mut free = Vec::new();
+        for page_num in 0..db.table.pager.num_pages {
+            if get_page(&mut db.table.pager, page_num).unwrap()[NODE_TYPE_OFFSET] == FREE_PAGE_TYPE
+            {
+                free.push(page_num);
+            }
+        }
+        assert!(!listed.is_empty());
+        assert_eq!(listed.len(), db.table.pager.free_count as usize);
+        let mut sorted = listed.clone();
+        sorted.sort();
+        assert_eq!(sorted, free);
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+
+        db.execute("insert 1 u1 e1@x").unwrap();
+        db.execute("insert 2 u2 e2@x").unwrap();
+        db.execute("insert 3 u3 e3@x").unwrap();
+        db.execute("insert 4 u4 e4@x").unwrap();
+        let after = free_list_pages(&mut db.table.pager).unwrap();
+        assert!(after.len() < listed.len());
+        assert_eq!(after.len(), db.table.pager.free_count as usize);
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+    }
+
+    // A strict import stops at a duplicate id and says which line it got to;
+    // resuming after that line with the file fixed finishes the job, and
+    // re-running from the start skips what is already there
+    #[test]
+    fn import_resumes_after_a_failure() {
+        let path = env::temp_dir().join(format!("database-test-{}-import.csv", process::id()));
+        let filename = path.to_str().unwrap();
+        let mut db = small_node_db(3, 3);
+        db.execute("insert 4 u4 e4@x").unwrap();
+
+        std::fs::write(&path, "1,u1,e1@x\n2,u2,e2@x\n\n4,u4,e4@x\n5,u5,e5@x\n6,u6,e6@x\n")
+            .unwrap();
+        let strict = ImportOptions { resume_after: 0, strict: true };
+        let mut progress = ImportProgress::default();
+        let result = import_rows(&mut db.table, filename, &strict, &mut progress);
+        assert!(matches!(result, Err(DbError::DuplicateKey)));
+        assert_eq!((progress.imported, progress.skipped, progress.last_line), (2, 0, 3));
+
+        std::fs::write(&path, "1,u1,e1@x\n2,u2,e2@x\n\n40,u4,e4@x\n5,u5,e5@x\n6,u6,e6@x\n")
+            .unwrap();
+        let resume = ImportOptions { resume_after: progress.last_line, strict: true };
+        let mut progress = ImportProgress::default();
+        import_rows(&mut db.table, filename, &resume, &mut progress).unwrap();
+        assert_eq!((progress.imported, progress.skipped, progress.last_line), (3, 0, 6));
+        assert_eq!(ids(&select(&mut db, "select")), vec![1, 2, 4, 5, 6, 40]);
+
+        let mut progress = ImportProgress::default();
+        import_rows(&mut db.table, filename, &ImportOptions::default(), &mut progress).unwrap();
+        assert_eq!((progress.imported, progress.skipped, progress.last_line), (0, 5, 6));
+        assert_eq!(count(&mut db, "select count"), 6);
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+
+        assert!(parse_import_args("--resume 3 --strict data.csv").is_some_and(|(options, file)| {
+            options.resume_after == 3 && options.strict && file == "data.csv"
+        }));
+        assert!(parse_import_args("--resume x data.csv").is_none());
+        assert!(parse_import_args("--resume 3").is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // A quoted field can hold line breaks, so one record can span several
+    // lines; the line count still follows the file, and a quote left open
+    // at the end makes the rest of the file one skipped record
+    #[test]
+    fn import_reads_records_across_lines() {
+        let path = env::temp_dir().join(format!("database-test-{}-multiline.csv", process::id()));
+        let filename = path.to_str().unwrap();
+        let mut db = Database::open(":memory:").unwrap();
+        let csv = "1,\"two\r\nlines\",e1@x\r\n2,\"say \"\"hi\"\", twice\",e2@x\n\
+                   3,\"never closed,e3@x\n4,u4,e4@x\n";
+        std::fs::write(&path, csv).unwrap();
+
+        let mut progress = ImportProgress::default();
+        import_rows(&mut db.table, filename, &ImportOptions::default(), &mut progress).unwrap();
+        assert_eq!((progress.imported, progress.skipped, progress.last_line), (2, 1, 5));
+        let rows = select(&mut db, "select");
+        assert_eq!(ids(&rows), vec![1, 2]);
+        assert_eq!(rows[0].get_username(), "two\r\nlines");
+        assert_eq!(rows[1].get_username(), "say \"hi\", twice");
+
+        let resume = ImportOptions { resume_after: 2, strict: false };
+        let mut progress = ImportProgress::default();
+        import_rows(&mut db.table, filename, &resume, &mut progress).unwrap();
+        assert_eq!((progress.imported, progress.skipped, progress.last_line), (0, 2, 5));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // .export quotes whatever .import would otherwise split or trim, so
+    // values with commas, quotes, line breaks and edge spaces come back
+    // byte for byte
+    #[test]
+    fn export_then_import_keeps_every_value() {
+        let path = env::temp_dir().join(format!("database-test-{}-export.csv", process::id()));
+        let filename = path.to_str().unwrap();
+        let values = ["plain", "a,b", "say \"hi\"", "two\r\nlines", "  padded ", "\n"];
+        let mut db = Database::open(":memory:").unwrap();
+        let widths = db.table.pager.text_widths;
+        for (id, value) in values.iter().enumerate() {
+            let email = format!("{}@x", value);
+            let row = validate_row(id as i64, value, &email, widths, false, &mut Vec::new());
+            db.execute(&format!("insert {} u e", id)).unwrap();
+            assert!(db.replace(id as u32, &row.ok().unwrap()).unwrap());
+        }
+        let mut file = File::create(&path).unwrap();
+        assert_eq!(export_csv(&mut db.table, &mut file).unwrap(), values.len());
+
+        let mut copy = Database::open(":memory:").unwrap();
+        let mut progress = ImportProgress::default();
+        import_rows(&mut copy.table, filename, &ImportOptions::default(), &mut progress).unwrap();
+        assert_eq!((progress.imported, progress.skipped), (values.len(), 0));
+        let rows = select(&mut copy, "select");
+        assert_eq!(ids(&rows), (0..values.len() as u32).collect::<Vec<_>>());
+        for (row, value) in rows.iter().zip(values) {
+            assert_eq!(row.get_username(), value);
+            assert_eq!(row.get_email(), format!("{}@x", value));
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // create table narrows the text columns of an empty table; the widths
+    // bound every way of adding a row and survive closing the file
+    #[test]
+    fn create_table_widths_are_enforced_and_kept() {
+        let path = env::temp_dir().join(format!("database-test-{}-widths.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let mut db = Database::open(filename).unwrap();
+        assert!(db.execute("create table (id int, username text(0), email text(8))").is_err());
+        assert!(db.execute("create table (id int, username text(33), email text(8))").is_err());
+        assert!(db.execute("create table (id int, name text(4), email text(8))").is_err());
+        db.execute("create table (id int, username text(4), email text(8))").unwrap();
+        db.execute("insert 1 abcd a@b.c").unwrap();
+        assert!(matches!(db.execute("insert 2 abcde a@b.c"), Err(DbError::InvalidStatement(_))));
+        assert!(matches!(
+            db.execute("create table (id int, username text(8), email text(8))"),
+            Err(DbError::TableNotEmpty)
+        ));
+
+        let mut row = select(&mut db, "select").remove(0);
+        row.username[..5].copy_from_slice(b"abcde");
+        assert!(db.replace(1, &row).is_err());
+        db.close().unwrap();
+
+        let mut db = Database::open(filename).unwrap();
+        assert_eq!(db.table.pager.text_widths, TextWidths { username: 4, email: 8 });
+        assert!(db.execute("insert 2 abcde a@b.c").is_err());
+        db.execute("insert 2 abc a@b.c").unwrap();
+        assert_eq!(count(&mut db, "select count"), 2);
+        db.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // With three cells a node, deleting every key in random order has to
+    // merge and borrow at every level and collapse the root back to a
+    // leaf; the tree stays valid throughout and takes inserts afterwards
+    #[test]
+    fn delete_everything_then_insert_again() {
+        let mut db = small_node_db(3, 3);
+        let mut state = 273;
+        let mut keys: Vec<u32> = (1..200).collect();
+        for i in (1..keys.len()).rev() {
+            keys.swap(i, next_random(&mut state) as usize % (i + 1));
+        }
+        for key in &keys {
+            db.execute(&format!("insert {} a b", key)).unwrap();
+        }
+        for i in (1..keys.len()).rev() {
+            keys.swap(i, next_random(&mut state) as usize % (i + 1));
+        }
+        for key in &keys {
+            db.execute(&format!("delete {}", key)).unwrap();
+            assert_eq!(verify_tree(&mut db.table), Ok(()), "after deleting {}", key);
+        }
+
+        let root = get_page(&mut db.table.pager, db.table.root_page_num).unwrap();
+        assert_eq!(node_type_checked(root, 0).unwrap(), NodeType::Leaf);
+        assert!(matches!(
+            get_node_max_key(&mut db.table.pager, db.table.root_page_num),
+            Err(DbError::EmptyNode { .. })
+        ));
+        for key in 1..100 {
+            db.execute(&format!("insert {} a b", key)).unwrap();
+        }
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+        assert_eq!(ids(&select(&mut db, "select")), (1..100).collect::<Vec<_>>());
+    }
+
+    // A page whose type byte is neither leaf, internal nor free is reported
+    // as an error by every whole-file walk instead of panicking, and a
+    // free page is skipped by them and turned away by .page
+    #[test]
+    fn page_walks_report_bad_node_types() {
+        let mut db = small_node_db(3, 3);
+        for key in 1..10 {
+            db.execute(&format!("insert {} a b", key)).unwrap();
+        }
+        let pager = &mut db.table.pager;
+        let free = get_unused_page_num(pager).unwrap();
+        get_page_mut(pager, free).unwrap();
+        free_page(pager, free).unwrap();
+        let mut leaves = Vec::new();
+        for_each_leaf_page(pager, |page_num, _| leaves.push(page_num)).unwrap();
+        assert!(!leaves.contains(&free));
+        let mut out = Vec::new();
+        assert!(print_dbinfo(pager, &mut out).is_ok());
+        out.clear();
+        assert!(print_rows_in_page(pager, &mut out, free).is_ok());
+        assert_eq!(String::from_utf8(out).unwrap(), format!("Error: Page {} is free.\n", free));
+
+        let leaf = leaves[1];
+        get_page_mut(pager, leaf).unwrap()[NODE_TYPE_OFFSET] = 7;
+        let bad_type = |e| matches!(e, DbError::NodeTypeInvalid { node_type: 7, .. });
+        assert!(for_each_leaf_page(pager, |_, _| ()).is_err_and(bad_type));
+        assert!(print_dbinfo(pager, &mut io::sink()).is_err_and(bad_type));
+        assert!(print_rows_in_page(pager, &mut io::sink(), leaf).is_err_and(bad_type));
+    }
+
+    // A panic part way through a statement loses only that statement: the
+    // rows inserted before it reach the file as the table is dropped, and
+    // the pages the panicking statement scribbled on do not
+    #[test]
+    fn panic_keeps_earlier_statements() {
+        let path = env::temp_dir().join(format!("database-test-{}-panic.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let mut db = Database::open(filename).unwrap();
+        for key in 1..50 {
+            db.execute(&format!("insert {} a b", key)).unwrap();
+        }
+        let unwound = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+            in_statement(&mut db.table, |table| -> Result<(), DbError> {
+                let root_page_num = table.root_page_num;
+                get_page_mut(&mut table.pager, root_page_num).unwrap().fill(0xff);
+                let new_page = get_unused_page_num(&mut table.pager).unwrap();
+                get_page_mut(&mut table.pager, new_page).unwrap().fill(0xff);
+                panic!("half way through a statement");
+            })
+        }));
+        assert!(unwound.is_err());
+
+        let mut db = Database::open(filename).unwrap();
+        assert_eq!(ids(&select(&mut db, "select")), (1..50).collect::<Vec<_>>());
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+        db.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // --verify runs the same tree check as .verify, so a wrong parent
+    // pointer is caught, and an impossible key count is reported instead
+    // of read past the end of its page
+    #[test]
+    fn verify_database_checks_the_tree() {
+        let path = env::temp_dir().join(format!("database-test-{}-verify.db", process::id()));
+        let filename = path.to_str().unwrap();
+        for corruption in 0..3 {
+            let _ = std::fs::remove_file(&path);
+            let mut db = Database::open(filename).unwrap();
+            for key in 1..50 {
+                db.execute(&format!("insert {} a b", key)).unwrap();
+            }
+            let pager = &mut db.table.pager;
+            let leaf = internal_node_child(get_page(pager, 0).unwrap(), 0).unwrap() as usize;
+            match corruption {
+                1 => set_node_parent(get_page_mut(pager, leaf).unwrap(), 7),
+                2 => set_internal_node_num_keys(get_page_mut(pager, 0).unwrap(), 60000),
+                _ => {}
+            }
+            db.close().unwrap();
+            let clean = verify_database(filename, &mut io::sink());
+            assert_eq!(clean, corruption == 0, "corruption {}", corruption);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // With a three-page cache, a statement that changes more pages than
+    // that keeps them all in memory and writes nothing until it is over;
+    // then they are synced together and the cache drops back to its cap
+    #[test]
+    fn dirty_pages_stay_cached_until_the_statement_ends() {
+        let path = env::temp_dir().join(format!("database-test-{}-evict.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut db = Database::open(filename).unwrap();
+        for key in 0..300 {
+            db.execute(&format!("insert {} a b", key)).unwrap();
+        }
+        db.close().unwrap();
+
+        let mut db = Database::open(filename).unwrap();
+        db.table.pager.max_cached_pages = Some(3);
+        let before = std::fs::read(&path).unwrap();
+        let cached = |pager: &Pager| pager.pages.iter().filter(|page| page.is_some()).count();
+        in_statement(&mut db.table, |table| {
+            for key in (0..300).step_by(10) {
+                let PrepareResult::Success(statement) = prepare(&format!("update {} c d", key))
+                else {
+                    panic!("update {} does not parse", key);
+                };
+                execute_statement(&statement, table)?;
+            }
+            assert!(cached(&table.pager) > 3);
+            assert_eq!(std::fs::read(&path).unwrap(), before);
+            Ok(())
+        })
+        .unwrap();
+        assert!(cached(&db.table.pager) <= 3);
+        assert_ne!(std::fs::read(&path).unwrap(), before);
+        db.close().unwrap();
+
+        let mut db = Database::open(filename).unwrap();
+        let rows = select(&mut db, "select");
+        assert_eq!(ids(&rows), (0..300).collect::<Vec<_>>());
+        for row in rows {
+            let expected = if row.id % 10 == 0 { "c" } else { "a" };
+            assert_eq!(row.get_username(), expected, "row {}", row.id);
+        }
+        db.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // A scan through a cache of two pages evicts and rereads pages at
+    // every step, in both directions, and still sees each row exactly once
+    #[test]
+    fn eviction_during_a_scan_keeps_every_row_once() {
+        let path = env::temp_dir().join(format!("database-test-{}-scan.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut db = Database::open(filename).unwrap();
+        db.table.leaf_max_cells = 3;
+        db.table.internal_max_cells = 3;
+        let mut state = 233;
+        let mut keys: Vec<u32> = (0..300).collect();
+        for i in (1..keys.len()).rev() {
+            keys.swap(i, next_random(&mut state) as usize % (i + 1));
+        }
+        for key in keys {
+            db.execute(&format!("insert {} a b", key)).unwrap();
+        }
+        db.close().unwrap();
+
+        let expected: Vec<u32> = (0..300).collect();
+        let mut db = Database::open(filename).unwrap();
+        db.table.pager.max_cached_pages = Some(2);
+        assert_eq!(ids(&select(&mut db, "select")), expected);
+        let descending = ids(&select(&mut db, "select order by id desc"));
+        assert!(descending.iter().eq(expected.iter().rev()));
+        let pager = &db.table.pager;
+        assert!(pager.pages.iter().filter(|page| page.is_some()).count() <= 2);
+        assert!(pager.num_pages > 50);
+        db.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // run_input writes everything, statement warnings included, to the
+    // writer the caller supplied, and nothing at all before one is set
+    #[test]
+    fn output_goes_to_the_supplied_writer() {
+        let mut db = Database::open(":memory:").unwrap();
+        run_meta(&mut db, "insert 1 a b");
+        assert_eq!(run_output(&mut db, "select"), "(1, a, b)\nExecuted successfully.\n");
+        assert_eq!(run_output(&mut db, ".truncate on"), "");
+
+        let long_name = "n".repeat(COLUMN_USERNAME_SIZE + 1);
+        assert_eq!(
+            run_output(&mut db, &format!("insert 2 {} b", long_name)),
+            "Warning: username truncated to 32 bytes.\nExecuted successfully.\n"
+        );
+        assert_eq!(run_output(&mut db, ".bogus"), "Unrecognized command '.bogus'.\n");
+    }
+
+    // .version reports the format the open file's header records, not the
+    // one this build writes
+    #[test]
+    fn version_reads_the_file_header() {
+        let path = env::temp_dir().join(format!("database-test-{}-version.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+        let build = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+        let mut db = Database::open(filename).unwrap();
+        let expected = format!("{} (file format {})\n", build, FORMAT_VERSION);
+        assert_eq!(run_output(&mut db, ".version"), expected);
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(FILE_HEADER_VERSION_OFFSET as u64)).unwrap();
+        file.write_all(&7u32.to_le_bytes()).unwrap();
+        assert_eq!(run_output(&mut db, ".version"), format!("{} (file format 7)\n", build));
+        db.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let mut db = Database::open(":memory:").unwrap();
+        assert_eq!(run_output(&mut db, ".version"), format!("{} (in memory)\n", build));
+    }
+
+    // The table and its email index are two B-trees in one file: each
+    // splits on its own, neither reaches into the other's pages, and
+    // .btree draws whichever tree the table is rooted at
+    #[test]
+    fn two_trees_in_one_file() {
+        let mut db = small_node_db(3, 3);
+        for key in 1..40 {
+            db.execute(&format!("insert {} user{} e{}@x", key, key, key)).unwrap();
+        }
+        run_meta(&mut db, ".createindex email");
+        for key in 40..80 {
+            db.execute(&format!("insert {} user{} e{}@x", key, key, key)).unwrap();
+        }
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+        assert_eq!(ids(&select(&mut db, "select where email = 'e50@x'")), vec![50]);
+
+        let table_root = db.table.root_page_num;
+        let index_root = index_root(&db.table.pager, Column::Email);
+        assert_ne!(index_root, table_root);
+        let pager = &mut db.table.pager;
+        for root in [table_root, index_root] {
+            let node = get_page(pager, root).unwrap();
+            assert_eq!(node_type_checked(node, root).unwrap(), NodeType::Internal);
+        }
+        let table_pages = tree_pages(pager, table_root).unwrap();
+        let index_pages = tree_pages(pager, index_root).unwrap();
+        assert!(table_pages.is_disjoint(&index_pages));
+
+        let mut index_tree = b"Tree:\n".to_vec();
+        print_tree(pager, &mut index_tree, index_root, 0).unwrap();
+        db.table.root_page_num = index_root;
+        assert_eq!(run_output(&mut db, ".btree").into_bytes(), index_tree);
+    }
+
+    // Both delete forms prepare the same statement and remove the same row
+    #[test]
+    fn delete_shorthand_matches_where_form() {
+        for input in ["delete 5", "delete where id = 5"] {
+            match prepare(input) {
+                PrepareResult::Success(statement) => {
+                    assert!(matches!(statement.statement_type, StatementType::Delete));
+                    assert!(matches!(statement.predicate, Some(Predicate::IdEquals(5))));
+                }
+                _ => panic!("{} did not prepare", input),
+            }
+        }
+        assert!(matches!(prepare("delete where id = x"), PrepareResult::SyntaxError));
+        assert!(matches!(prepare("delete where id = -5"), PrepareResult::NegativeId));
+
+        let mut remaining = Vec::new();
+        for input in ["delete 5", "delete where id = 5"] {
+            let mut db = small_node_db(3, 3);
+            for key in 1..=10 {
+                db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+            }
+            db.execute(input).unwrap();
+            assert!(!db.exists(5).unwrap());
+            assert_eq!(verify_tree(&mut db.table), Ok(()));
+            remaining.push(ids(&select(&mut db, "select")));
+        }
+        assert_eq!(remaining[0], remaining[1]);
+        assert_eq!(remaining[0], vec![1, 2, 3, 4, 6, 7, 8, 9, 10]);
+    }
+
+    // keys() yields exactly the inserted ids, ascending, whatever order they
+    // went in
+    #[test]
+    fn keys_lists_inserted_ids_in_order() {
+        let mut db = small_node_db(3, 3);
+        assert_eq!(db.keys().unwrap().count(), 0);
+        let mut state = 0x2020_u64;
+        let mut expected = Vec::new();
+        for _ in 0..60 {
+            let key = (next_random(&mut state) % 500) as u32;
+            if expected.contains(&key) {
+                continue;
+            }
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+            expected.push(key);
+        }
+        expected.sort_unstable();
+        let keys: Vec<u32> = db.keys().unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(keys, expected);
+        assert_eq!(keys, ids(&select(&mut db, "select")));
+    }
+
+    // count_where agrees with the rows a filtered select returns, for id
+    // ranges and for text filters
+    #[test]
+    fn count_where_id_ranges_and_text_filters() {
+        let mut db = small_node_db(3, 3);
+        for key in 1..=40 {
+            let name = if key % 4 == 0 { "even4" } else { "other" };
+            db.execute(&format!("insert {} {} e{}@x", key, name, key)).unwrap();
+        }
+        assert_eq!(db.count_where("id = 7").unwrap(), 1);
+        assert_eq!(db.count_where("id = 41").unwrap(), 0);
+        assert_eq!(db.count_where("id between 5 and 14").unwrap(), 10);
+        assert_eq!(db.count_where("id between 35 and 90").unwrap(), 6);
+        assert_eq!(db.count_where("id between 9 and 3").unwrap(), 0);
+        assert_eq!(db.count_where("username = even4").unwrap(), 10);
+        assert_eq!(db.count_where("username = nobody").unwrap(), 0);
+        assert_eq!(db.count_where("email = e12@x").unwrap(), 1);
+        assert_eq!(db.count_where("email like 'e1%'").unwrap(), 11);
+        for clause in ["id between 5 and 14", "username = even4", "email like 'e1%'"] {
+            let rows = select(&mut db, &format!("select where {}", clause));
+            assert_eq!(db.count_where(clause).unwrap(), rows.len());
+            assert_eq!(count(&mut db, &format!("select count(*) where {}", clause)), rows.len());
+        }
+        assert!(db.count_where("name = x").is_err());
+
+        db.execute("pragma max_scan = 5").unwrap();
+        assert!(matches!(db.count_where("username = even4"), Err(DbError::ScanLimitExceeded)));
+        assert_eq!(db.count_where("id between 1 and 5").unwrap(), 5);
+    }
+
+    // The extreme leaves are the root while it is a leaf, and the two ends
+    // of the leaf chain once the tree has grown levels
+    #[test]
+    fn leftmost_and_rightmost_leaves() {
+        let mut db = small_node_db(3, 3);
+        db.execute("insert 5 u e@x").unwrap();
+        let root = db.table.root_page_num;
+        assert_eq!((db.leftmost_leaf().unwrap(), db.rightmost_leaf().unwrap()), (root, root));
+
+        for key in (10..300).step_by(7).rev() {
+            db.execute(&format!("insert {} u e@x", key)).unwrap();
+        }
+        let root = get_page(&mut db.table.pager, db.table.root_page_num).unwrap();
+        assert!(matches!(node_type_checked(root, 0), Ok(NodeType::Internal)));
+
+        let leftmost = db.leftmost_leaf().unwrap();
+        let node = get_page(&mut db.table.pager, leftmost).unwrap();
+        assert_eq!(get_leaf_node_prev_leaf(node), NO_LEAF);
+        assert_eq!(leaf_node_key(node, 0), 5);
+
+        let rightmost = db.rightmost_leaf().unwrap();
+        assert_ne!(leftmost, rightmost);
+        let node = get_page(&mut db.table.pager, rightmost).unwrap();
+        assert_eq!(get_leaf_node_next_leaf(node), NO_LEAF);
+        let last = leaf_node_num_cells(node) as usize - 1;
+        assert_eq!(leaf_node_key(node, last), db.keys().unwrap().last().unwrap().unwrap());
+    }
+
+    // Reopen a three-row file after overwriting its root leaf's num_cells
+    fn reopen_with_num_cells(path: &Path, num_cells: u32) -> Database {
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let mut db = Database::open(filename).unwrap();
+        for key in 1..=3 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+        }
+        let offset = page_offset(&db.table.pager, db.table.root_page_num);
+        db.close().unwrap();
+
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(offset + LEAF_NODE_NUM_CELLS_OFFSET as u64)).unwrap();
+        file.write_all(&num_cells.to_le_bytes()).unwrap();
+        drop(file);
+        Database::open(filename).unwrap()
+    }
+
+    // First cell of a default-sized page that runs past its end, counting
+    // len bytes of it
+    fn first_cell_past_page(len: usize) -> usize {
+        let leaf = vec![0u8; DEFAULT_PAGE_SIZE];
+        (0..).find(|&cell| leaf_node_cell_offset(&leaf, cell) + len > DEFAULT_PAGE_SIZE).unwrap()
+    }
+
+    // A num_cells inflated on disk until cells run off the page stops a
+    // key scan at the first such cell with a corrupt node error
+    #[test]
+    fn inflated_num_cells_stops_key_scan() {
+        let path = env::temp_dir().join(format!("database-test-{}-keys.db", process::id()));
+        let mut db = reopen_with_num_cells(&path, 100_000);
+        let root = db.table.root_page_num;
+        let keys = db.keys().unwrap().collect::<Result<Vec<_>, _>>();
+        // The scan also reads each row's flags, at the end of the cell, to
+        // pass over soft-deleted rows
+        let bad_cell = first_cell_past_page(LEAF_NODE_CELL_SIZE);
+        assert!(
+            matches!(keys, Err(DbError::CorruptCell { page_num, cell_num })
+                if (page_num, cell_num) == (root, bad_cell)),
+            "keys gave {:?}",
+            keys.map(|keys| keys.len())
+        );
+        assert!(db.execute("select count where id between 0 and 5").is_err());
+        assert!(!verify_database(path.to_str().unwrap(), &mut io::sink()));
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // A num_cells inflated on disk by just enough that the last cell's key
+    // fits in the page but its value does not: a row scan reports that cell
+    // as corrupt instead of deserializing past the page end
+    #[test]
+    fn inflated_num_cells_stops_row_scan() {
+        let path = env::temp_dir().join(format!("database-test-{}-rows.db", process::id()));
+        let bad_cell = first_cell_past_page(LEAF_NODE_CELL_SIZE);
+        assert!(bad_cell < first_cell_past_page(LEAF_NODE_KEY_SIZE));
+        let mut db = reopen_with_num_cells(&path, bad_cell as u32 + 1);
+        let root = db.table.root_page_num;
+        let rows = db.rows().unwrap().collect::<Result<Vec<_>, _>>();
+        assert!(
+            matches!(rows, Err(DbError::CorruptCell { page_num, cell_num })
+                if (page_num, cell_num) == (root, bad_cell)),
+            "rows gave {:?}",
+            rows.map(|rows| rows.len())
+        );
+        assert!(db.execute("select").is_err());
+        assert!(db.execute("select where username = u1").is_err());
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // for_each_leaf_page visits each leaf once, in page order, and nothing
+    // else: the same pages the leaf chain links, none of the internal ones
+    #[test]
+    fn for_each_leaf_page_visits_every_leaf() {
+        let mut db = small_node_db(3, 3);
+        for key in (1..60).rev() {
+            db.execute(&format!("insert {} a b", key)).unwrap();
+        }
+        let mut chain = Vec::new();
+        let mut page_num = db.leftmost_leaf().unwrap();
+        loop {
+            chain.push(page_num);
+            let next = get_leaf_node_next_leaf(get_page(&mut db.table.pager, page_num).unwrap());
+            if next == NO_LEAF {
+                break;
+            }
+            page_num = next as usize;
+        }
+        chain.sort_unstable();
+
+        let pager = &mut db.table.pager;
+        let mut visited = Vec::new();
+        let mut cells = 0;
+        for_each_leaf_page(pager, |page_num, leaf| {
+            visited.push(page_num);
+            cells += leaf_node_num_cells(leaf) as usize;
+        })
+        .unwrap();
+        assert_eq!(visited, chain);
+        assert_eq!(cells, 59);
+        assert!(visited.len() < pager.num_pages);
+        for page_num in 0..pager.num_pages {
+            let node = get_page(pager, page_num).unwrap();
+            let leaf = node_type_checked(node, page_num).unwrap() == NodeType::Leaf;
+            assert_eq!(leaf, visited.contains(&page_num), "page {}", page_num);
+        }
+    }
+
+    // .footer on ends a select's rows with their count, singular for one
+    // row; .footer off and JSON mode leave it out
+    #[test]
+    fn footer_counts_selected_rows() {
+        let mut db = Database::open(":memory:").unwrap();
+        db.execute("insert 1 a a@x").unwrap();
+        db.execute("insert 2 b b@x").unwrap();
+        let rows = "(1, a, a@x)\n(2, b, b@x)\n";
+        assert_eq!(run_output(&mut db, "select"), format!("{}Executed successfully.\n", rows));
+
+        run_meta(&mut db, ".footer on");
+        let expected = format!("{}(2 rows)\nExecuted successfully.\n", rows);
+        assert_eq!(run_output(&mut db, "select"), expected);
+        let expected = "(2, b, b@x)\n(1 row)\nExecuted successfully.\n";
+        assert_eq!(run_output(&mut db, "select where id = 2"), expected);
+        let expected = "(0 rows)\nExecuted successfully.\n";
+        assert_eq!(run_output(&mut db, "select where id = 9"), expected);
+
+        run_meta(&mut db, ".mode json");
+        assert!(!run_output(&mut db, "select").contains("rows)"));
+        run_meta(&mut db, ".mode list");
+        run_meta(&mut db, ".footer off");
+        assert_eq!(run_output(&mut db, "select"), format!("{}Executed successfully.\n", rows));
+    }
+
+    // With caps of 3 a few dozen inserts are enough for a root, a level of
+    // internal nodes and the leaves, and the result still verifies
+    #[test]
+    fn tiny_caps_build_a_three_level_tree() {
+        fn depth(db: &mut Database) -> usize {
+            let mut page_num = db.table.root_page_num;
+            let mut depth = 1;
+            loop {
+                let node = get_page(&mut db.table.pager, page_num).unwrap();
+                match node_type_checked(node, page_num).unwrap() {
+                    NodeType::Leaf => return depth,
+                    NodeType::Internal => page_num = internal_node_child(node, 0).unwrap() as usize,
+                }
+                depth += 1;
+            }
+        }
+
+        let mut db = small_node_db(3, 3);
+        let mut inserted = 0;
+        while depth(&mut db) < 3 {
+            inserted += 1;
+            assert!(inserted <= 30, "still {} levels after 30 rows", depth(&mut db));
+            db.execute(&format!("insert {} u{} e{}@x", inserted, inserted, inserted)).unwrap();
+        }
+        assert_eq!(depth(&mut db), 3);
+        assert_eq!(run_output(&mut db, ".verify"), "OK\n");
+        assert_eq!(ids(&select(&mut db, "select")), (1..=inserted).collect::<Vec<_>>());
+
+        // Every node respects the caps while the tree keeps growing
+        for key in inserted + 1..=inserted + 20 {
+            db.execute(&format!("insert {} u e", key)).unwrap();
+            assert_eq!(run_output(&mut db, ".verify"), "OK\n");
+        }
+        let pager = &mut db.table.pager;
+        for page_num in 0..pager.num_pages {
+            let node = get_page(pager, page_num).unwrap();
+            match node_type_checked(node, page_num).unwrap() {
+                NodeType::Leaf => assert!(leaf_node_num_cells(node) <= 3),
+                NodeType::Internal => assert!(internal_node_num_keys(node) <= 3),
+            }
+        }
+    }
+
+    // With .echo on each line is written, as typed, ahead of its result;
+    // .echo off itself is still echoed since echo was on when it ran
+    #[test]
+    fn echo_prints_each_line_before_its_result() {
+        let mut db = Database::open(":memory:").unwrap();
+        assert_eq!(run_output(&mut db, "insert 1 a a@x"), "Executed successfully.\n");
+        assert_eq!(run_output(&mut db, ".echo on"), "");
+        assert_eq!(
+            run_output(&mut db, "insert 2 b b@x"),
+            "insert 2 b b@x\nExecuted successfully.\n"
+        );
+        assert_eq!(
+            run_output(&mut db, "select where id = 1"),
+            "select where id = 1\n(1, a, a@x)\nExecuted successfully.\n"
+        );
+        let expected = "insert 1 a a@x\nError: Duplicate key.\n";
+        assert_eq!(run_output(&mut db, "insert 1 a a@x"), expected);
+        assert_eq!(run_output(&mut db, ".bogus"), ".bogus\nUnrecognized command '.bogus'.\n");
+        assert_eq!(run_output(&mut db, ".echo off"), ".echo off\n");
+        let expected = "(2, b, b@x)\nExecuted successfully.\n";
+        assert_eq!(run_output(&mut db, "select where id = 2"), expected);
+    }
+
+    // .nocase folds ASCII case in equality on its own column only, with or
+    // without the email index, and is off by default
+    #[test]
+    fn nocase_folds_ascii_case_per_column() {
+        let mut db = small_node_db(3, 3);
+        db.execute("insert 1 Alice A@X.com").unwrap();
+        db.execute("insert 2 alice a@x.com").unwrap();
+        db.execute("insert 3 ÉVE é@x.com").unwrap();
+        for key in 4..20 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+        }
+        assert_eq!(ids(&select(&mut db, "select where email = a@x.com")), vec![2]);
+        assert_eq!(ids(&select(&mut db, "select where username = ALICE")), Vec::<u32>::new());
+
+        run_meta(&mut db, ".nocase email on");
+        assert_eq!(ids(&select(&mut db, "select where email = a@X.COM")), vec![1, 2]);
+        assert_eq!(db.count_where("email = A@x.Com").unwrap(), 2);
+        assert_eq!(ids(&select(&mut db, "select where email like 'A@%'")), vec![1, 2]);
+        // Only ASCII is folded
+        assert!(select(&mut db, "select where email = É@X.COM").is_empty());
+        // The username column keeps byte equality
+        assert_eq!(ids(&select(&mut db, "select where username = alice")), vec![2]);
+
+        run_meta(&mut db, ".createindex email");
+        assert_eq!(ids(&select(&mut db, "select where email = A@X.COM")), vec![1, 2]);
+        run_meta(&mut db, ".nocase email off");
+        assert_eq!(ids(&select(&mut db, "select where email = A@X.com")), vec![1]);
+
+        run_meta(&mut db, ".nocase username on");
+        assert_eq!(ids(&select(&mut db, "select where username = ALICE")), vec![1, 2]);
+        assert_eq!(ids(&select(&mut db, "select where username = éve")), Vec::<u32>::new());
+    }
+
+    // pragma max_scan aborts a select that would scan more rows than the
+    // limit, however few it returns; scans within the limit are untouched
+    #[test]
+    fn max_scan_aborts_long_scans() {
+        let mut db = small_node_db(3, 3);
+        for key in 1..=30 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+        }
+        db.execute("pragma max_scan = 10").unwrap();
+        let limited = |result| matches!(result, Err(DbError::ScanLimitExceeded));
+        assert!(limited(db.execute("select")));
+        assert!(limited(db.execute("select where username = u30")));
+        assert!(limited(db.execute("select where id between 5 and 20")));
+        assert!(limited(db.execute("select count where email = e1@x")));
+        let output = run_output(&mut db, "select where username = u30");
+        assert!(output.ends_with("Error: Scan limit exceeded.\n"), "{}", output);
+        assert!(!output.contains("(30,"));
+
+        assert_eq!(ids(&select(&mut db, "select where id between 5 and 14")).len(), 10);
+        assert_eq!(ids(&select(&mut db, "select where id = 25")), vec![25]);
+        db.execute("pragma max_scan = 30").unwrap();
+        assert_eq!(select(&mut db, "select").len(), 30);
+        db.execute("pragma max_scan = 29").unwrap();
+        assert!(limited(db.execute("select")));
+        db.execute("pragma max_scan = 0").unwrap();
+        assert_eq!(ids(&select(&mut db, "select where username = u30")), vec![30]);
+    }
+
+    // A .dump sql script read back into a fresh database gives the same
+    // widths and the same row bytes, including values the insert grammar
+    // only takes as hex literals
+    #[test]
+    fn dump_sql_then_read_reproduces_the_rows() {
+        let path = env::temp_dir().join(format!("database-test-{}-dump.sql", process::id()));
+        let script = path.to_str().unwrap();
+        let mut db = small_node_db(3, 3);
+        db.execute("create table (id int, username text(16), email text(64))").unwrap();
+        for key in 1..=25 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+        }
+        let mut row = select(&mut db, "select where id = 7").remove(0);
+        row.username = [0; COLUMN_USERNAME_SIZE];
+        row.username[..7].copy_from_slice(b"two wo\xff");
+        row.email = [0; COLUMN_EMAIL_SIZE];
+        row.email[..5].copy_from_slice(b"'q'\n,");
+        assert!(db.replace(7, &row).unwrap());
+        db.execute("delete 12").unwrap();
+        run_meta(&mut db, &format!(".dump sql {}", script));
+
+        let mut copy = small_node_db(3, 3);
+        run_meta(&mut copy, &format!(".read {}", script));
+        assert!(copy.table.pager.text_widths == db.table.pager.text_widths);
+        let (rows, copied) = (select(&mut db, "select"), select(&mut copy, "select"));
+        assert_eq!(ids(&copied), ids(&rows));
+        for (row, copied) in rows.iter().zip(&copied) {
+            assert_eq!((row.username, row.email), (copied.username, copied.email));
+        }
+        // Dumping the copy gives the same script, the same way .dump shows it
+        assert_eq!(run_output(&mut copy, ".dump sql"), std::fs::read_to_string(&path).unwrap());
+        assert_eq!(run_output(&mut copy, ".dump sql"), run_output(&mut db, ".dump sql"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // ? placeholders in REPL statements are bound from .param values, the
+    // nth ? from parameter n, and the values stay set for later statements
+    #[test]
+    fn params_bind_placeholders_in_order() {
+        let mut db = Database::open(":memory:").unwrap();
+        let done = "Executed successfully.\n";
+        assert_eq!(run_output(&mut db, "insert ? ? ?"), "Error: Parameter 1 is not set.\n");
+        run_meta(&mut db, ".param set 1 7");
+        run_meta(&mut db, ".param set 2 two words");
+        assert_eq!(run_output(&mut db, "insert ? ? ?"), "Error: Parameter 3 is not set.\n");
+        run_meta(&mut db, ".param set 3 x'41'@x");
+        assert_eq!(run_output(&mut db, "insert ? ? ?"), done);
+        let rows = select(&mut db, "select");
+        assert_eq!(ids(&rows), vec![7]);
+        assert_eq!(rows[0].get_username(), "two words");
+        assert_eq!(rows[0].get_email(), "x'41'@x");
+
+        // Each statement starts again from parameter 1
+        let expected = format!("(7, two words, x'41'@x)\n{}", done);
+        assert_eq!(run_output(&mut db, "select where id = ?"), expected);
+        run_meta(&mut db, ".param set 1 8");
+        assert_eq!(run_output(&mut db, "insert ? second ?"), done);
+        assert_eq!(select(&mut db, "select where id = 8")[0].get_email(), "two words");
+        run_meta(&mut db, ".param set 2 renamed");
+        assert_eq!(run_output(&mut db, "update 7 ? ?"), done);
+        assert_eq!(select(&mut db, "select where id = 7")[0].get_username(), "8");
+
+        assert_eq!(run_output(&mut db, ".param set x 1"), "Usage: .param set <n> <value>\n");
+        run_meta(&mut db, ".param clear");
+        assert_eq!(run_output(&mut db, "select where id = ?"), "Error: Parameter 1 is not set.\n");
+    }
+
+    // .dbinfo's counts agree with the file they describe: the size on disk,
+    // the leaves on the chain, every page accounted for once, and the rows
+    #[test]
+    fn dbinfo_matches_a_known_file() {
+        let path = env::temp_dir().join(format!("database-test-{}-dbinfo.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut db = Database::open(filename).unwrap();
+        db.table.leaf_max_cells = 3;
+        db.table.internal_max_cells = 3;
+        for key in 1..=40 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+        }
+        for key in 5..=25 {
+            db.execute(&format!("delete {}", key)).unwrap();
+        }
+        db.close().unwrap();
+
+        let mut db = Database::open(filename).unwrap();
+        let output = run_output(&mut db, ".dbinfo");
+        let info: HashMap<&str, &str> =
+            output.lines().filter_map(|line| line.split_once(": ")).collect();
+        let field = |name: &str| info[name].parse::<u64>().unwrap();
+
+        let file_size = std::fs::metadata(&path).unwrap().len();
+        assert_eq!(field("file size"), file_size);
+        assert_eq!(field("page size"), DEFAULT_PAGE_SIZE as u64);
+        let mut leaves = 0;
+        let mut page_num = db.leftmost_leaf().unwrap();
+        while page_num != NO_LEAF as usize {
+            leaves += 1;
+            page_num = get_leaf_node_next_leaf(get_page(&mut db.table.pager, page_num).unwrap())
+                as usize;
+        }
+        assert_eq!(field("leaf pages"), leaves);
+        assert!(field("internal pages") > 0);
+        assert!(field("free pages") > 0);
+        assert_eq!(field("username index pages"), 0);
+        assert_eq!(field("email index pages"), 0);
+        assert_eq!(
+            field("leaf pages") + field("internal pages") + field("free pages"),
+            field("total pages")
+        );
+        assert_eq!(field("total pages"), db.table.pager.num_pages as u64);
+        assert_eq!(info["checksums"], "off");
+        assert_eq!(field("total rows"), 19);
+        let utilization = 19.0 * ROW_SIZE as f64 * 100.0 / file_size as f64;
+        assert_eq!(info["utilization"], format!("{:.1}%", utilization));
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // Once warmed, a file's pages are all in the cache, so scans and
+    // lookups add no cache misses; a capped cache is warmed only to its cap
+    #[test]
+    fn warm_cache_leaves_nothing_to_miss() {
+        let path = env::temp_dir().join(format!("database-test-{}-warm.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut db = Database::open(filename).unwrap();
+        db.table.leaf_max_cells = 3;
+        db.table.internal_max_cells = 3;
+        for key in 1..=60 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+        }
+        db.close().unwrap();
+
+        let mut db = Database::open(filename).unwrap();
+        assert_eq!(db.table.pager.cache_misses, 0);
+        db.warm_cache(None).unwrap();
+        let num_pages = db.table.pager.num_pages;
+        assert_eq!(db.table.pager.cache_misses, num_pages as u64);
+        assert_eq!(select(&mut db, "select").len(), 60);
+        assert_eq!(ids(&select(&mut db, "select where username = u42")), vec![42]);
+        assert_eq!(count(&mut db, "select count"), 60);
+        assert_eq!(db.table.pager.cache_misses, num_pages as u64);
+        assert!(run_output(&mut db, ".stats").contains(&format!("cache misses: {}\n", num_pages)));
+        drop(db);
+
+        let mut db = Database::open(filename).unwrap();
+        db.execute("pragma cache_size = 3").unwrap();
+        db.warm_cache(None).unwrap();
+        let pager = &db.table.pager;
+        assert_eq!(pager.pages.iter().filter(|page| page.is_some()).count(), 3);
+        assert_eq!(pager.cache_misses, 3);
+        db.warm_cache(Some(2)).unwrap();
+        assert_eq!(db.table.pager.cache_misses, 3);
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // A bounded table turns away the insert that would split its full leaf
+    // and changes nothing; updates, and inserts after a delete, still fit
+    #[test]
+    fn bounded_table_rejects_the_overflowing_insert() {
+        let mut db = small_node_db(3, 3);
+        run_meta(&mut db, ".bounded on");
+        for key in [2, 4, 6] {
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+        }
+        assert!(matches!(db.execute("insert 5 u e@x"), Err(DbError::TableFull)));
+        assert!(matches!(db.execute("insert 9 u e@x"), Err(DbError::TableFull)));
+        assert_eq!(run_output(&mut db, "insert 1 u e@x"), "Error: Table full.\n");
+        assert_eq!(db.table.pager.num_pages, 1);
+        let root = get_page(&mut db.table.pager, db.table.root_page_num).unwrap();
+        assert!(matches!(node_type_checked(root, 0), Ok(NodeType::Leaf)));
+        assert_eq!(ids(&select(&mut db, "select")), vec![2, 4, 6]);
+
+        db.execute("update 4 renamed e4@x").unwrap();
+        db.execute("insert or replace 6 replaced e6@x").unwrap();
+        db.execute("delete 2").unwrap();
+        db.execute("insert 5 u e@x").unwrap();
+        assert_eq!(ids(&select(&mut db, "select")), vec![4, 5, 6]);
+        assert_eq!(db.table.pager.num_pages, 1);
+
+        run_meta(&mut db, ".bounded off");
+        db.execute("insert 7 u e@x").unwrap();
+        assert!(db.table.pager.num_pages > 1);
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+    }
+
+    // Random-order inserts and deletes leave every leaf strictly sorted and
+    // within its cap after each statement, and the debug tripwire catches a
+    // leaf that is not
+    #[test]
+    fn random_operations_keep_each_leaf_sorted() {
+        let mut db = small_node_db(4, 3);
+        let mut state = 0x248_u64;
+        let mut keys: Vec<u32> = (1..=120).collect();
+        for i in (1..keys.len()).rev() {
+            keys.swap(i, (next_random(&mut state) % (i as u64 + 1)) as usize);
+        }
+        for (i, key) in keys.iter().enumerate() {
+            db.execute(&format!("insert {} u e", key)).unwrap();
+            if i % 3 == 2 {
+                db.execute(&format!("delete {}", keys[i / 2])).unwrap();
+            }
+            for_each_leaf_page(&mut db.table.pager, |page_num, leaf| {
+                let num_cells = leaf_node_num_cells(leaf) as usize;
+                assert!(num_cells <= 4, "leaf {} has {} cells", page_num, num_cells);
+                let keys: Vec<u32> = (0..num_cells).map(|cell| leaf_node_key(leaf, cell)).collect();
+                let sorted = keys.windows(2).all(|pair| pair[0] < pair[1]);
+                assert!(sorted, "leaf {}: {:?}", page_num, keys);
+            })
+            .unwrap();
+        }
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+
+        if cfg!(debug_assertions) {
+            let mut leaf = vec![0u8; DEFAULT_PAGE_SIZE];
+            initialize_leaf_node(&mut leaf);
+            set_leaf_node_num_cells(&mut leaf, 2);
+            let first = leaf_node_cell_offset(&leaf, 0);
+            let second = leaf_node_cell_offset(&leaf, 1);
+            set_u32_at(&mut leaf, first, 9);
+            set_u32_at(&mut leaf, second, 3);
+            let tripped = std::panic::catch_unwind(|| debug_assert_leaf_sorted(&leaf, 5, 4));
+            assert!(tripped.is_err());
+        }
+    }
+
+    // Only dirty pages are written back: a session that only reads leaves
+    // the file as it was, and one update rewrites its own leaf and nothing
+    // else
+    #[test]
+    fn untouched_pages_stay_byte_identical() {
+        let path = env::temp_dir().join(format!("database-test-{}-dirty.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut db = Database::open(filename).unwrap();
+        db.table.leaf_max_cells = 3;
+        db.table.internal_max_cells = 3;
+        for key in 1..=40 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+        }
+        db.close().unwrap();
+        let before = std::fs::read(&path).unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        let mut db = Database::open(filename).unwrap();
+        db.warm_cache(None).unwrap();
+        assert_eq!(select(&mut db, "select").len(), 40);
+        db.close().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        assert_eq!(std::fs::metadata(&path).unwrap().modified().unwrap(), modified);
+
+        let mut db = Database::open(filename).unwrap();
+        let page_size = db.table.pager.page_size;
+        let data_offset = db.table.pager.data_offset as usize;
+        let leaf = table_find(&mut db.table, 17).unwrap().page_num;
+        db.execute("update 17 renamed e17@x").unwrap();
+        assert_eq!(db.table.pager.dirty, HashSet::from([leaf]));
+        db.close().unwrap();
+
+        let after = std::fs::read(&path).unwrap();
+        assert_eq!(after.len(), before.len());
+        assert_eq!(after[..data_offset], before[..data_offset]);
+        let changed: Vec<usize> = (0..(before.len() - data_offset) / page_size)
+            .filter(|page_num| {
+                let start = data_offset + page_num * page_size;
+                after[start..start + page_size] != before[start..start + page_size]
+            })
+            .collect();
+        assert_eq!(changed, vec![leaf]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // NO_LEAF can be page 0 only because page 0 stays the table's root: it
+    // is never a linked leaf, even after the tree collapses back to one
+    // leaf and grows again, and scans stop where the chain ends
+    #[test]
+    fn page_zero_is_never_a_linked_leaf() {
+        fn chain(db: &mut Database) -> Vec<usize> {
+            let mut leaves = vec![db.leftmost_leaf().unwrap()];
+            loop {
+                let leaf = get_page(&mut db.table.pager, *leaves.last().unwrap()).unwrap();
+                match get_leaf_node_next_leaf(leaf) {
+                    NO_LEAF => return leaves,
+                    next => leaves.push(next as usize),
+                }
+            }
+        }
+
+        let mut db = small_node_db(3, 3);
+        for round in 0..3 {
+            for key in 1..=30 {
+                db.execute(&format!("insert {} u e{}@x", key, key)).unwrap();
+            }
+            assert_eq!(db.table.root_page_num, 0);
+            let leaves = chain(&mut db);
+            assert!(leaves.len() > 2 && !leaves.contains(&0), "round {}: {:?}", round, leaves);
+            assert_eq!(db.keys().unwrap().count(), 30);
+            let backward = select(&mut db, "select order by id desc");
+            assert_eq!(ids(&backward), (1..=30).rev().collect::<Vec<_>>());
+
+            for key in 1..=30 {
+                db.execute(&format!("delete {}", key)).unwrap();
+            }
+            assert_eq!(chain(&mut db), vec![0]);
+            assert!(select(&mut db, "select").is_empty());
+        }
+    }
+
+    // A comma, newline or parenthesis in a value is escaped in list and JSON
+    // output, so each row stays on one parseable line, and survives a trip
+    // through .export and .import unchanged
+    #[test]
+    fn delimiters_in_values_are_escaped_and_round_trip() {
+        let path = env::temp_dir().join(format!("database-test-{}-escape.csv", process::id()));
+        let filename = path.to_str().unwrap();
+        let mut db = Database::open(":memory:").unwrap();
+        db.execute("insert 1 u e@x").unwrap();
+        let widths = db.table.pager.text_widths;
+        let row = validate_row(1, "smith, j\nr)", "a\\b,\"c\"@x", widths, false, &mut Vec::new());
+        assert!(db.replace(1, &row.ok().unwrap()).unwrap());
+
+        let output = run_output(&mut db, "select");
+        assert_eq!(output, "(1, smith\\, j\\nr\\), a\\\\b\\,\"c\"@x)\nExecuted successfully.\n");
+        run_meta(&mut db, ".mode json");
+        let output = run_output(&mut db, "select");
+        let expected = r#"{"id":1,"username":"smith, j\nr)","email":"a\\b,\"c\"@x"}"#;
+        assert_eq!(output, format!("{}\nExecuted successfully.\n", expected));
+
+        assert_eq!(run_output(&mut db, &format!(".export {}", filename)), "Exported 1 rows.\n");
+        let mut copy = Database::open(":memory:").unwrap();
+        let imported = run_output(&mut copy, &format!(".import {}", filename));
+        assert_eq!(imported, "Imported 1 rows, skipped 0, up to line 2.\n");
+        let rows = select(&mut copy, "select");
+        assert_eq!(rows[0].get_username(), "smith, j\nr)");
+        assert_eq!(rows[0].get_email(), "a\\b,\"c\"@x");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // execute hands a select's rows back instead of printing them, and
+    // execute_each streams the same rows one at a time without collecting
+    #[test]
+    fn execute_returns_rows_and_execute_each_streams_them() {
+        let mut db = small_node_db(3, 3);
+        let output = SharedOutput::default();
+        db.set_output(Box::new(output.clone()));
+        for key in (1..=20).rev() {
+            let result = db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+            assert!(result.into_rows().is_empty());
+        }
+        let rows = select(&mut db, "select where id between 5 and 9");
+        assert_eq!(ids(&rows), vec![5, 6, 7, 8, 9]);
+        assert_eq!(rows[2].get_username(), "u7");
+        assert_eq!(rows[2].get_email(), "e7@x");
+        assert!(matches!(db.execute("select count").unwrap(), QueryResult::Count(20)));
+        assert!(matches!(db.execute("select exists 21").unwrap(), QueryResult::Exists(false)));
+
+        let mut streamed = Vec::new();
+        let result = db.execute_each("select order by id desc", &mut |row| streamed.push(row.id));
+        assert!(result.unwrap().into_rows().is_empty());
+        assert_eq!(streamed, (1..=20).rev().collect::<Vec<_>>());
+        let mut seen = 0;
+        db.execute_each("select where username = u3", &mut |row| {
+            assert_eq!(row.id, 3);
+            seen += 1;
+        })
+        .unwrap();
+        assert_eq!(seen, 1);
+        assert!(output.0.borrow().is_empty());
+    }
+
+    // rows() is an ordinary iterator: it yields every row in key order
+    // across leaves, works with adapters, and can be dropped part way
+    #[test]
+    fn rows_iterator_works_with_adapters() {
+        let mut db = small_node_db(3, 3);
+        for key in [9, 3, 14, 1, 7, 12, 5, 10, 2, 8] {
+            db.execute(&format!("insert {} u{} e{}@x", key, key % 3, key)).unwrap();
+        }
+        let all: Vec<u32> = db.rows().unwrap().map(|row| row.unwrap().id).collect();
+        assert_eq!(all, vec![1, 2, 3, 5, 7, 8, 9, 10, 12, 14]);
+
+        let first = db.rows().unwrap().next().unwrap().unwrap();
+        assert_eq!((first.id, first.get_username().as_str()), (1, "u1"));
+        assert_eq!(first.get_email(), "e1@x");
+
+        let zeros: Vec<u32> = db
+            .rows()
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|row| row.get_username() == "u0")
+            .map(|row| row.id)
+            .take(3)
+            .collect();
+        assert_eq!(zeros, vec![3, 9, 12]);
+        assert_eq!(db.rows().unwrap().skip(8).count(), 2);
+
+        // The borrow has ended, so the database can change again
+        db.execute("delete 3").unwrap();
+        assert_eq!(db.rows().unwrap().count(), 9);
+        assert!(Database::open(":memory:").unwrap().rows().unwrap().next().is_none());
+    }
+
+    // Splitting a full leaf keeps exactly max + 1 cells, sorted and without
+    // duplicates, whether the new key goes in front, in the middle or at
+    // the end, for odd and even caps
+    #[test]
+    fn full_leaf_splits_at_front_middle_and_end() {
+        for max_cells in [3, 4, 7, leaf_node_max_cells(DEFAULT_PAGE_SIZE)] {
+            let full: Vec<u32> = (1..=max_cells as u32).map(|key| key * 10).collect();
+            let middle = full[max_cells / 2] - 5;
+            for new_key in [5, middle, full[max_cells - 1] + 5] {
+                let mut db = small_node_db(max_cells, 3);
+                for key in &full {
+                    db.execute(&format!("insert {} u{} e@x", key, key)).unwrap();
+                }
+                assert_eq!(db.table.pager.num_pages, 1);
+                db.execute(&format!("insert {} new e@x", new_key)).unwrap();
+
+                let mut expected = full.clone();
+                expected.push(new_key);
+                expected.sort_unstable();
+                let context = format!("cap {}, new key {}", max_cells, new_key);
+                let keys: Vec<u32> = db.keys().unwrap().map(Result::unwrap).collect();
+                assert_eq!(keys, expected, "{}", context);
+                assert_eq!(verify_tree(&mut db.table), Ok(()), "{}", context);
+
+                let (left, right) = (db.leftmost_leaf().unwrap(), db.rightmost_leaf().unwrap());
+                let pager = &mut db.table.pager;
+                let mut cells = |page_num| leaf_node_num_cells(get_page(pager, page_num).unwrap());
+                let counts = [left, right].map(|page_num| cells(page_num) as usize);
+                let split = (db.table.leaf_left_split_count(), db.table.leaf_right_split_count());
+                assert_eq!((counts[0], counts[1]), split, "{}", context);
+                let rows = select(&mut db, &format!("select where id = {}", new_key));
+                assert_eq!(rows[0].get_username(), "new", "{}", context);
+            }
+        }
+    }
+
+    // The id limits hold for every statement that takes an id, not just
+    // insert, and the REPL reports each side of the range by name
+    #[test]
+    fn id_limits_in_every_statement() {
+        let mut db = Database::open(":memory:").unwrap();
+        db.execute("insert 0 zero z@x").unwrap();
+        db.execute("insert 4294967295 max m@x").unwrap();
+        db.execute("update 4294967295 top t@x").unwrap();
+        assert_eq!(select(&mut db, "select where id = 4294967295")[0].get_username(), "top");
+        let exists = db.execute("select exists 4294967295").unwrap();
+        assert!(matches!(exists, QueryResult::Exists(true)));
+        assert_eq!(count(&mut db, "select count where id between 0 and 4294967295"), 2);
+        db.execute("delete 4294967295").unwrap();
+        db.execute("delete where id = 0").unwrap();
+        assert_eq!(count(&mut db, "select count"), 0);
+
+        for sql in ["update -1 a b", "delete -1", "delete where id = -1", "insert -5 a b"] {
+            assert!(matches!(prepare(sql), PrepareResult::NegativeId), "{}", sql);
+        }
+        for sql in [
+            "update 4294967296 a b",
+            "delete 4294967296",
+            "delete where id = 4294967296",
+            "insert 9223372036854775808 a b",
+        ] {
+            assert!(matches!(prepare(sql), PrepareResult::IdOutOfRange), "{}", sql);
+        }
+        assert_eq!(run_output(&mut db, "insert -1 a b"), "Error: ID must be positive.\n");
+        assert_eq!(run_output(&mut db, "delete 4294967296"), "Error: ID out of range.\n");
+        let syntax_error = "Syntax error. Could not parse statement.\n";
+        assert_eq!(run_output(&mut db, "insert 1x a b"), syntax_error);
+    }
+
+    // At the fanout a default page really has, enough rows split internal
+    // nodes more than once: the root gains internal children, each within
+    // the computed cap and at least half full, and every row reads back
+    #[test]
+    fn internal_nodes_split_at_real_fanout() {
+        let mut db = Database::open(":memory:").unwrap();
+        let max_keys = internal_node_max_cells(DEFAULT_PAGE_SIZE);
+        assert_eq!(db.table.internal_max_cells, max_keys);
+        let rows = 20_000;
+        let mut state = 0x293_u64;
+        let mut keys: Vec<u32> = (1..=rows).collect();
+        for i in (1..keys.len()).rev() {
+            keys.swap(i, (next_random(&mut state) % (i as u64 + 1)) as usize);
+        }
+        for key in &keys {
+            db.execute(&format!("insert {} u e", key)).unwrap();
+        }
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+        let read_back: Vec<u32> = db.keys().unwrap().map(Result::unwrap).collect();
+        assert_eq!(read_back, (1..=rows).collect::<Vec<_>>());
+
+        let pager = &mut db.table.pager;
+        let root = get_page(pager, 0).unwrap();
+        assert!(matches!(node_type_checked(root, 0), Ok(NodeType::Internal)));
+        let root_keys = internal_node_num_keys(root) as usize;
+        let children: Vec<usize> = (0..root_keys)
+            .map(|child| internal_node_child(root, child).unwrap() as usize)
+            .chain([internal_node_right_child(root) as usize])
+            .collect();
+        assert!(children.len() >= 3, "only {} internal children", children.len());
+        for child in children {
+            let node = get_page(pager, child).unwrap();
+            assert!(matches!(node_type_checked(node, child), Ok(NodeType::Internal)));
+            let num_keys = internal_node_num_keys(node) as usize;
+            assert!((max_keys / 2..=max_keys).contains(&num_keys), "{} keys", num_keys);
+        }
+    }
+
+    // .vacuum with a temp directory builds its copy there and moves it over
+    // the database: the same rows come back in a smaller file, and neither
+    // directory is left with a scratch file
+    #[test]
+    fn vacuum_through_temp_dir_compacts_the_file() {
+        let dir = env::temp_dir().join(format!("database-test-{}-vacuum-dir", process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let path = env::temp_dir().join(format!("database-test-{}-vacuum.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let mut db = Database::open(filename).unwrap();
+        db.table.leaf_max_cells = 3;
+        db.table.internal_max_cells = 3;
+        db.set_temp_dir(dir.to_str().unwrap());
+        for key in 1..=80 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+        }
+        run_meta(&mut db, ".createindex email");
+        for key in (1..=80).filter(|key| key % 4 != 0) {
+            db.execute(&format!("delete {}", key)).unwrap();
+        }
+        let rows = select(&mut db, "select");
+        let pages_before = db.table.pager.num_pages;
+        db.close().unwrap();
+        let size_before = std::fs::metadata(&path).unwrap().len();
+
+        let mut db = Database::open(filename).unwrap();
+        db.set_temp_dir(dir.to_str().unwrap());
+        let output = run_output(&mut db, ".vacuum");
+        let pages_after = db.table.pager.num_pages;
+        assert_eq!(output, format!("Vacuumed {} pages into {}.\n", pages_before, pages_after));
+        assert!(pages_after < pages_before);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        assert!(!Path::new(&format!("{}-vacuum", filename)).exists());
+        let vacuumed = select(&mut db, "select");
+        assert_eq!(ids(&vacuumed), ids(&rows));
+        for (row, vacuumed) in rows.iter().zip(&vacuumed) {
+            assert_eq!((row.username, row.email), (vacuumed.username, vacuumed.email));
+        }
+        assert_eq!(ids(&select(&mut db, "select where email = e40@x")), vec![40]);
+        db.close().unwrap();
+
+        assert!(std::fs::metadata(&path).unwrap().len() < size_before);
+        assert!(verify_database(filename, &mut io::sink()));
+        let mut db = Database::open(filename).unwrap();
+        assert_eq!(ids(&select(&mut db, "select")), ids(&rows));
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_dir(dir).unwrap();
+    }
+
+    // create table checks the whole schema before reporting, so each kind
+    // of problem is named and several come back together; nothing changes
+    #[test]
+    fn create_table_reports_every_schema_problem() {
+        fn problems(sql: &str) -> Vec<String> {
+            match prepare(sql) {
+                PrepareResult::InvalidSchema(problems) => problems,
+                _ => panic!("{} was not an invalid schema", sql),
+            }
+        }
+        let layout = "the columns must be id int, username text(n) and email text(n)";
+
+        let duplicate = problems("create table (id int, username text(8), username text(8))");
+        assert_eq!(duplicate, ["column 'username' is defined more than once", layout]);
+        let unknown = problems("create table (id int, username varchar(8), email blob)");
+        assert_eq!(
+            unknown,
+            [
+                "column 'username' has unknown type 'varchar(8)'",
+                "column 'email' has unknown type 'blob'"
+            ]
+        );
+        let wide = problems("create table (id int, username text(33), email text(0))");
+        assert_eq!(
+            wide,
+            [
+                "column 'username' is wider than 32 bytes",
+                "column 'email' must be at least 1 byte wide"
+            ]
+        );
+        let reserved = problems("create table (id int, where text(8), email text(8))");
+        assert_eq!(reserved, ["column name 'where' is reserved", layout]);
+        let layout_only = problems("create table (id int, email text(8), username text(8))");
+        assert_eq!(layout_only, [layout]);
+
+        let all = problems("create table (id blob, select text(999), select text(4))");
+        assert_eq!(all.len(), 5, "{:?}", all);
+
+        let mut db = Database::open(":memory:").unwrap();
+        let output = run_output(&mut db, "create table (id int, username text(40), email int)");
+        assert_eq!(
+            output,
+            format!("Error: column 'username' is wider than 32 bytes.\nError: {}.\n", layout)
+        );
+        assert!(db.table.pager.text_widths == TextWidths::default());
+        let error = db.execute("create table (id int, username text(0), email x)").unwrap_err();
+        assert!(error.to_string().contains("at least 1 byte wide; column 'email' has unknown"));
+        assert!(matches!(prepare("create table id int"), PrepareResult::SyntaxError));
+        assert!(matches!(prepare("create table (id)"), PrepareResult::SyntaxError));
+    }
+
+    // NULL and the empty string are stored apart and print apart in every
+    // mode, and .export, .import and .dump sql keep the difference
+    #[test]
+    fn null_prints_apart_from_empty_text() {
+        let mut db = Database::open(":memory:").unwrap();
+        db.execute("insert 1 NULL x''").unwrap();
+        db.execute("insert 2 x'' null").unwrap();
+        db.execute("insert 3 x'6e756c6c' b@x").unwrap();
+        let rows = select(&mut db, "select");
+        assert!(rows[0].username_is_null() && !rows[0].email_is_null());
+        assert!(!rows[1].username_is_null() && rows[1].email_is_null());
+        assert!(!rows[2].username_is_null());
+        assert_eq!(rows[2].get_username(), "null");
+
+        let modes = [
+            (".mode list", "(1, NULL, )\n(2, , NULL)\n(3, null, b@x)\n"),
+            (
+                ".mode json",
+                "{\"id\":1,\"username\":null,\"email\":\"\"}\n\
+                 {\"id\":2,\"username\":\"\",\"email\":null}\n\
+                 {\"id\":3,\"username\":\"null\",\"email\":\"b@x\"}\n",
+            ),
+            (".mode csv", "1,,\"\"\n2,\"\",\n3,null,b@x\n"),
+            (".mode hex", "(1, NULL, x'')\n(2, x'', NULL)\n(3, x'6e756c6c', x'624078')\n"),
+        ];
+        for (mode, expected) in modes {
+            run_meta(&mut db, mode);
+            let expected = format!("{}Executed successfully.\n", expected);
+            assert_eq!(run_output(&mut db, "select"), expected, "{}", mode);
+        }
+
+        // NULL matches no comparison, and count distinct leaves it out
+        assert_eq!(ids(&select(&mut db, "select where username = ''")), [2]);
+        assert_eq!(ids(&select(&mut db, "select where email = ''")), [1]);
+        assert_eq!(count(&mut db, "select count(distinct username)"), 2);
+
+        let path = env::temp_dir().join(format!("database-test-{}-null.csv", process::id()));
+        let mut file = File::create(&path).unwrap();
+        export_csv(&mut db.table, &mut file).unwrap();
+        let mut imported = Database::open(":memory:").unwrap();
+        let mut progress = ImportProgress::default();
+        let options = ImportOptions::default();
+        import_rows(&mut imported.table, path.to_str().unwrap(), &options, &mut progress).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut dump = Vec::new();
+        dump_sql(&mut db.table, &mut dump).unwrap();
+        let mut reloaded = Database::open(":memory:").unwrap();
+        for line in String::from_utf8(dump).unwrap().lines() {
+            reloaded.execute(line).unwrap();
+        }
+
+        for mut copy in [imported, reloaded] {
+            run_meta(&mut copy, ".mode hex");
+            assert_eq!(run_output(&mut copy, "select"), run_output(&mut db, "select"));
+        }
+    }
+
+    // A format 3 file, whose cells have no flags byte, is rebuilt in the
+    // current format on open with every row kept
+    #[test]
+    fn format_3_file_is_upgraded_on_open() {
+        let path = env::temp_dir().join(format!("database-test-{}-format3.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut db = Database::open(filename).unwrap();
+        for key in 1..=40 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key, key)).unwrap();
+        }
+        db.close().unwrap();
+
+        // Write the file back as format 3 would have: each leaf's cells
+        // packed FORMAT_3_CELL_SIZE apart, without their flags byte
+        let mut bytes = std::fs::read(&path).unwrap();
+        set_u32_at(&mut bytes, FILE_HEADER_VERSION_OFFSET, 3);
+        for page in bytes.chunks_mut(DEFAULT_PAGE_SIZE).skip(1) {
+            if page[NODE_TYPE_OFFSET] != NodeType::Leaf as u8 {
+                continue;
+            }
+            for cell_num in 0..leaf_node_num_cells(page) as usize {
+                let from = leaf_node_cell_offset(page, cell_num);
+                let to = LEAF_NODE_HEADER_SIZE + cell_num * FORMAT_3_CELL_SIZE;
+                page.copy_within(from..from + FORMAT_3_CELL_SIZE, to);
+            }
+        }
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut db = Database::open(filename).unwrap();
+        assert_eq!(file_format_version(&mut db.table.pager).unwrap(), Some(FORMAT_VERSION));
+        let rows = select(&mut db, "select");
+        assert_eq!(ids(&rows), (1..=40).collect::<Vec<_>>());
+        assert!(rows.iter().all(|row| !row.username_is_null() && !row.email_is_null()));
+        assert_eq!(rows[39].get_email(), "e40@x");
+        db.close().unwrap();
+        assert!(!Path::new(&format!("{}-upgrade", filename)).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+
+    // A soft delete only marks the row: every read skips it, but its cell
+    // stays in the leaf until .vacuum rebuilds the table without it
+    #[test]
+    fn soft_deleted_rows_are_hidden_but_kept_until_vacuum() {
+        let mut db = Database::open(":memory:").unwrap();
+        for key in 1..=100 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key % 10, key)).unwrap();
+        }
+        run_meta(&mut db, ".createindex email");
+        let pages = db.table.pager.num_pages;
+        // Cells along the table's leaf chain, tombstones included
+        let cells = |db: &mut Database| {
+            let mut cells = 0;
+            let mut page_num = db.leftmost_leaf().unwrap();
+            while page_num != NO_LEAF as usize {
+                let node = get_page(&mut db.table.pager, page_num).unwrap();
+                cells += leaf_node_num_cells(node) as usize;
+                page_num = get_leaf_node_next_leaf(node) as usize;
+            }
+            cells
+        };
+        let total_cells = cells(&mut db);
+
+        run_meta(&mut db, ".softdelete on");
+        for key in (2..=100).step_by(2) {
+            db.execute(&format!("delete {}", key)).unwrap();
+        }
+        assert!(matches!(db.execute("delete 2"), Err(DbError::KeyNotFound)));
+        assert!(matches!(db.execute("update 2 u e@x"), Err(DbError::KeyNotFound)));
+
+        let odd: Vec<u32> = (1..=99).step_by(2).collect();
+        assert_eq!(ids(&select(&mut db, "select")), odd);
+        assert_eq!(ids(&select(&mut db, "select order by id desc limit 1")), [99]);
+        assert!(select(&mut db, "select where id = 2").is_empty());
+        assert!(select(&mut db, "select where email = 'e2@x'").is_empty());
+        assert_eq!(ids(&select(&mut db, "select where username = 'u2'")), Vec::<u32>::new());
+        assert_eq!(count(&mut db, "select count"), 50);
+        assert_eq!(count(&mut db, "select count where id between 1 and 10"), 5);
+        assert_eq!(count(&mut db, "select count(distinct username)"), 5);
+        assert!(!db.exists(2).unwrap());
+        assert_eq!(db.keys().unwrap().collect::<Result<Vec<_>, _>>().unwrap(), odd);
+        assert_eq!(db.rows().unwrap().count(), 50);
+        let mut dump = Vec::new();
+        dump_sql(&mut db.table, &mut dump).unwrap();
+        assert_eq!(String::from_utf8(dump).unwrap().lines().count(), 50);
+
+        // The deleted rows still take up their cells and pages
+        assert_eq!(db.table.pager.num_pages, pages);
+        assert_eq!(cells(&mut db), total_cells);
+        let info = run_output(&mut db, ".dbinfo");
+        assert!(info.contains("total rows: 50\ndeleted rows: 50\n"), "{}", info);
+
+        // Inserting a deleted id takes its cell back
+        db.execute("insert 2 back b@x").unwrap();
+        assert_eq!(select(&mut db, "select where id = 2")[0].get_username(), "back");
+        assert_eq!(ids(&select(&mut db, "select where email = 'b@x'")), [2]);
+        assert_eq!(cells(&mut db), total_cells);
+
+        run_meta(&mut db, ".vacuum");
+        assert_eq!(cells(&mut db), 51);
+        assert!(db.table.pager.num_pages < pages);
+        assert_eq!(count(&mut db, "select count"), 51);
+        assert_eq!(ids(&select(&mut db, "select where email = 'e3@x'")), [3]);
+    }
+
+
+    // .createindex username answers username lookups from the index: it
+    // yields the ids, then each row is fetched from the table, so a lookup
+    // stays under a max_scan that a full scan would exceed. Inserts,
+    // updates and deletes keep the index in step, and its root survives
+    // a reopen.
+    #[test]
+    fn username_lookups_go_through_the_username_index() {
+        let path = env::temp_dir().join(format!("database-test-{}-userindex.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut db = Database::open(filename).unwrap();
+        for key in 1..=100 {
+            db.execute(&format!("insert {} u{} e{}@x", key, key % 10, key)).unwrap();
+        }
+        assert_eq!(run_output(&mut db, ".createindex username"), "Indexed 100 rows.\n");
+        let output = run_output(&mut db, ".createindex username");
+        assert_eq!(output, "Error: The username index already exists.\n");
+        assert_ne!(index_root(&db.table.pager, Column::Username), 0);
+        assert_eq!(index_root(&db.table.pager, Column::Email), 0);
+
+        let u3: Vec<u32> = (3..=93).step_by(10).collect();
+        let ids_for = |db: &mut Database, name: &str| {
+            index_lookup(&mut db.table, Column::Username, name.as_bytes()).unwrap()
+        };
+        assert_eq!(ids_for(&mut db, "u3"), Some(u3.clone()));
+        assert_eq!(ids_for(&mut db, "nobody"), Some(Vec::new()));
+        db.execute("pragma max_scan = 10").unwrap();
+        assert_eq!(ids(&select(&mut db, "select where username = u3")), u3);
+        assert!(matches!(db.execute("select"), Err(DbError::ScanLimitExceeded)));
+
+        db.execute("insert 101 u3 e101@x").unwrap();
+        db.execute("update 13 renamed e13@x").unwrap();
+        db.execute("delete 23").unwrap();
+        let mut expected: Vec<u32> = u3.iter().copied().filter(|id| ![13, 23].contains(id))
+            .collect();
+        expected.push(101);
+        assert_eq!(ids(&select(&mut db, "select where username = u3")), expected);
+        assert_eq!(ids(&select(&mut db, "select where username = renamed")), vec![13]);
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+        db.close().unwrap();
+
+        let mut db = Database::open(filename).unwrap();
+        assert_ne!(index_root(&db.table.pager, Column::Username), 0);
+        assert_eq!(ids_for(&mut db, "u3"), Some(expected.clone()));
+        assert_eq!(ids(&select(&mut db, "select where username = u3")), expected);
+        db.close().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+
+    // Rows with equal usernames always come out in ascending id order:
+    // from the username index's buckets, and as the tie-break of order by
+    // username in either direction, whatever order they were inserted in
+    #[test]
+    fn equal_usernames_keep_id_order() {
+        let mut db = small_node_db(3, 3);
+        let rows = [(5, "bob"), (7, "amy"), (2, "bob"), (3, "cat"), (9, "bob"), (1, "amy")];
+        for (key, name) in rows {
+            db.execute(&format!("insert {} {} e{}@x", key, name, key)).unwrap();
+        }
+        db.execute("insert 4 null e4@x").unwrap();
+        assert_eq!(ids(&select(&mut db, "select order by username asc")), [4, 1, 7, 2, 5, 9, 3]);
+        assert_eq!(ids(&select(&mut db, "select order by username desc")), [3, 2, 5, 9, 1, 7, 4]);
+        let limited = "select where id between 2 and 9 order by username asc limit 3";
+        assert_eq!(ids(&select(&mut db, limited)), [4, 7, 2]);
+
+        run_meta(&mut db, ".createindex username");
+        let bucket = index_lookup(&mut db.table, Column::Username, b"bob").unwrap();
+        assert_eq!(bucket, Some(vec![2, 5, 9]));
+        db.execute("delete 5").unwrap();
+        db.execute("insert 5 bob e5@x").unwrap();
+        db.execute("insert 8 bob e8@x").unwrap();
+        assert_eq!(ids(&select(&mut db, "select where username = bob")), [2, 5, 8, 9]);
+        let by_name = "select where username = bob order by username desc";
+        assert_eq!(ids(&select(&mut db, by_name)), [2, 5, 8, 9]);
+        let by_id = "select where username = bob order by id desc";
+        assert_eq!(ids(&select(&mut db, by_id)), [9, 8, 5, 2]);
+    }
+
+
+    // create table <name> registers a second tree in the header's
+    // directory. Two tables and the main one are filled and queried
+    // apart, keep their rows through a reopen and a vacuum, and .tables
+    // lists them in creation order.
+    #[test]
+    fn named_tables_are_independent_and_survive_reopen() {
+        let path = env::temp_dir().join(format!("database-test-{}-tables.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut db = Database::open(filename).unwrap();
+        db.execute("create table users").unwrap();
+        db.execute("create table orders").unwrap();
+        assert!(matches!(db.execute("create table users"), Err(DbError::TableExists { .. })));
+        assert!(matches!(db.execute("create table from"), Err(DbError::InvalidStatement(_))));
+        assert!(matches!(db.execute("create table 9lives"), Err(DbError::InvalidStatement(_))));
+        assert_eq!(run_output(&mut db, ".tables"), "users\norders\n");
+
+        db.table.leaf_max_cells = 3;
+        db.table.internal_max_cells = 3;
+        for key in 1..=40 {
+            db.execute(&format!("insert into users {} user{} u{}@x", key, key, key)).unwrap();
+            db.execute(&format!("insert into orders {} order{} o{}@x", key * 2, key, key)).unwrap();
+        }
+        db.execute("insert 7 main m7@x").unwrap();
+        let missing = db.execute("insert into nobody 1 a b");
+        assert!(matches!(missing, Err(DbError::NoSuchTable { .. })));
+        assert!(matches!(db.execute("insert into users 3 a b"), Err(DbError::DuplicateKey)));
+
+        let check = |db: &mut Database| {
+            assert_eq!(ids(&select(db, "select from users")), (1..=40).collect::<Vec<u32>>());
+            let orders: Vec<u32> = (1..=40).map(|key| key * 2).collect();
+            assert_eq!(ids(&select(db, "select from orders")), orders);
+            let rows = select(db, "select from orders where id = 10");
+            assert_eq!(rows[0].get_username(), "order5");
+            let rows = select(db, "select from users where username = user9");
+            assert_eq!(ids(&rows), [9]);
+            assert_eq!(ids(&select(db, "select from users order by id desc limit 2")), [40, 39]);
+            assert_eq!(ids(&select(db, "select")), [7]);
+            assert_eq!(verify_tree(&mut db.table), Ok(()));
+        };
+        check(&mut db);
+        let users_root = db.table.pager.tables[0].root_page_num as usize;
+        let node = get_page(&mut db.table.pager, users_root).unwrap();
+        assert_eq!(node_type_checked(node, users_root).unwrap(), NodeType::Internal);
+        db.close().unwrap();
+
+        let mut db = Database::open(filename).unwrap();
+        db.table.leaf_max_cells = 3;
+        db.table.internal_max_cells = 3;
+        assert_eq!(run_output(&mut db, ".tables"), "users\norders\n");
+        check(&mut db);
+        run_meta(&mut db, ".vacuum");
+        check(&mut db);
+        db.close().unwrap();
+
+        let mut db = Database::open(filename).unwrap();
+        db.table.leaf_max_cells = 3;
+        db.table.internal_max_cells = 3;
+        check(&mut db);
+        db.close().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+
+    #[test]
+    fn heap_tables_keep_small_leaves_and_read_rows_from_the_heap() {
+        let path = env::temp_dir().join(format!("database-test-{}-heap.db", process::id()));
+        let filename = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut db = Database::open(filename).unwrap();
+        db.execute("create table plain").unwrap();
+        db.execute("create table heaped heap").unwrap();
+        assert_eq!(run_output(&mut db, ".tables"), "plain\nheaped (heap)\n");
+        // Out of key order, so heap order and key order differ
+        let keys: Vec<u32> = (0..1000).map(|i| (i * 7919) % 1000 + 1).collect();
+        for key in &keys {
+            for name in ["plain", "heaped"] {
+                let sql = format!("insert into {} {} user{} u{}@x", name, key, key, key);
+                db.execute(&sql).unwrap();
+            }
+        }
+        assert!(matches!(db.execute("insert into heaped 5 a b"), Err(DbError::DuplicateKey)));
+        db.close().unwrap();
+
+        // Pages read from a cold cache by running sql
+        let cold_misses = |sql: &str| {
+            let mut db = Database::open(filename).unwrap();
+            let rows = select(&mut db, sql);
+            (rows, db.table.pager.cache_misses)
+        };
+        let mut db = Database::open(filename).unwrap();
+        let roots: Vec<usize> =
+            db.table.pager.tables.iter().map(|named| named.root_page_num as usize).collect();
+        let mut tree_size = |root| tree_pages(&mut db.table.pager, root).unwrap().len();
+        let (plain_tree, heap_tree) = (tree_size(roots[0]), tree_size(roots[1]));
+        // 1000 rows take 105 leaves under one root, but one root over 4
+        // leaves of locators plus 77 heap pages
+        assert_eq!((plain_tree, heap_tree), (106, 5));
+        assert!(run_output(&mut db, ".dbinfo").contains("heap pages: 77\n"));
+        assert_eq!(verify_tree(&mut db.table), Ok(()));
+        db.close().unwrap();
+
+        let (plain_rows, plain_scan) = cold_misses("select from plain");
+        let (heap_rows, heap_scan) = cold_misses("select from heaped");
+        assert_eq!(ids(&heap_rows), (1..=1000).collect::<Vec<u32>>());
+        let names = |rows: &[Row]| rows.iter().map(|r| r.get_username()).collect::<Vec<_>>();
+        assert_eq!(names(&heap_rows), names(&plain_rows));
+        let (plain_row, plain_lookup) = cold_misses("select from plain where id = 500");
+        let (heap_row, heap_lookup) = cold_misses("select from heaped where id = 500");
+        assert_eq!(names(&heap_row), names(&plain_row));
+        // A scan reads the heap's few leaves and its pages, fewer than the
+        // full-row leaves; a lookup reads one page more, for the row
+        assert_eq!((plain_scan, heap_scan), (106, 82));
+        assert_eq!((plain_lookup, heap_lookup), (2, 3));
+
+        let mut db = Database::open(filename).unwrap();
+        run_meta(&mut db, ".vacuum");
+        assert_eq!(run_output(&mut db, ".tables"), "plain\nheaped (heap)\n");
+        let rows = select(&mut db, "select from heaped where id = 500");
+        assert_eq!(names(&rows), names(&heap_row));
+        assert_eq!(select(&mut db, "select from heaped").len(), 1000);
+        db.close().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+
+    // Deterministic xorshift, so a failing input can be reproduced
+    fn next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    // Random statements built from keywords, numbers at the u32 and i64
+    // limits, quotes, embedded NULs and raw bytes that are not valid UTF-8
+    // all prepare to some PrepareResult, and run without panicking when
+    // they do parse
+    #[test]
+    fn prepare_statement_never_panics() {
+        let pieces: &[&[u8]] = &[
+            b"insert", b"or", b"replace", b"ignore", b"select", b"count", b"distinct",
+            b"exists", b"delete", b"update", b"where", b"id", b"username", b"email", b"=",
+            b"!=", b"<", b">=", b"between", b"and", b"like", b"order by", b"id desc",
+            b"limit", b"?1", b"x'", b"'", b"%", b"_", b"0", b"-1", b"4294967295",
+            b"4294967296", b"9223372036854775808", b"999999999999", b"\0", b"\xff",
+        ];
+        let settings = Settings::default();
+        let mut db = small_node_db(3, 3);
+        let mut state = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..20_000 {
+            let mut bytes = Vec::new();
+            let len = next_random(&mut state) % 300;
+            while (bytes.len() as u64) < len {
+                let n = next_random(&mut state);
+                match n % 4 {
+                    0 => bytes.push((n >> 8) as u8),
+                    1 => bytes.push(0),
+                    _ => bytes.extend_from_slice(pieces[(n >> 8) as usize % pieces.len()]),
+                }
+                if n.is_multiple_of(3) {
+                    bytes.push(b' ');
+                }
+            }
+            let input = String::from_utf8_lossy(&bytes).into_owned();
+
+            let mut input_buffer = InputBuffer::new();
+            input_buffer.set_input(&input);
+            let _ = prepare_statement(&input_buffer, &settings, TextWidths::default());
+            let _ = db.execute(&input);
+        }
+    }
+
+    proptest! {
+        // Any order of unique inserts reads back sorted with the values
+        // it was given, and leaves a tree .verify accepts
+        #[test]
+        fn insert_select_round_trip(
+            inserts in prop::collection::vec((0u32..5000, "[a-z]{1,12}", "[a-z]{1,12}@x"), 0..150),
+        ) {
+            let mut db = small_node_db(3, 3);
+            let mut expected = BTreeMap::new();
+            for (id, username, email) in inserts {
+                if expected.contains_key(&id) {
+                    continue;
+                }
+                db.execute(&format!("insert {} {} {}", id, username, email)).unwrap();
+                expected.insert(id, (username, email));
+            }
+
+            let rows = select(&mut db, "select");
+            prop_assert_eq!(ids(&rows), expected.keys().copied().collect::<Vec<_>>());
+            for (row, (username, email)) in rows.iter().zip(expected.values()) {
+                prop_assert_eq!(&row.get_username(), username);
+                prop_assert_eq!(&row.get_email(), email);
+            }
+            prop_assert_eq!(verify_tree(&mut db.table), Ok(()));
+        }
+    }
+}